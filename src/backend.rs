@@ -0,0 +1,82 @@
+use std::env;
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use futures_util::future::BoxFuture;
+use tokio::sync::mpsc;
+
+use crate::gql::{self, RiverSnapshot};
+use crate::hyprland::HyprlandBackend;
+use crate::niri::NiriBackend;
+use crate::river::{self, RiverBackend};
+use crate::sway::SwayBackend;
+
+/// A change a client asks the compositor to make.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Show `tags` on `output`, focusing it first, or on the focused output.
+    /// `focused` is focused again afterwards, so the seat keeps its focus.
+    SetFocusedTags {
+        tags: u32,
+        output: Option<String>,
+        focused: Option<String>,
+    },
+}
+
+/// A source of compositor status. Every backend reports river's events, so
+/// the server keeps one snapshot and schema whatever it is connected to.
+pub trait StatusBackend: Send + Sync {
+    /// Reported as `serverInfo.backend` and `source`.
+    fn kind(&self) -> gql::Backend;
+
+    /// Connects and starts reporting events. The protocols are those
+    /// `serverInfo` lists; the events stop when the compositor goes away.
+    fn connect(&self)
+    -> BoxFuture<'_, Result<(mpsc::Receiver<river::Received>, river::Protocols)>>;
+
+    /// What the bridge bound from the Wayland registry, for `waylandGlobals`;
+    /// `None` for backends that don't speak Wayland.
+    fn registry(&self) -> Option<river::RegistryHandle> {
+        None
+    }
+
+    /// Folds `event` into `snapshot`.
+    fn reduce(&self, snapshot: &mut RiverSnapshot, event: &river::Event) {
+        snapshot.apply_event(event);
+    }
+
+    /// Runs `command` on the compositor. Backends that can't change anything
+    /// keep the default, which refuses.
+    fn execute(&self, command: &Command) -> BoxFuture<'_, Result<()>> {
+        let kind = self.kind().name();
+        let command = command.clone();
+        Box::pin(async move { bail!("the {kind} backend cannot run {command:?}") })
+    }
+}
+
+/// The compositor of this session, from the variables sway, Hyprland and
+/// niri export to their clients; anything else is taken to be river.
+pub fn detect() -> gql::Backend {
+    if env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        gql::Backend::Hyprland
+    } else if env::var_os("SWAYSOCK").is_some() {
+        gql::Backend::Sway
+    } else if env::var_os("NIRI_SOCKET").is_some() {
+        gql::Backend::Niri
+    } else {
+        gql::Backend::River
+    }
+}
+
+/// The backend for compositor `kind`; river connects with `status`.
+pub fn compositor(
+    kind: gql::Backend,
+    status: river::StatusOptions,
+) -> Result<Arc<dyn StatusBackend>> {
+    Ok(match kind {
+        gql::Backend::Sway => Arc::new(SwayBackend::from_env()?),
+        gql::Backend::Hyprland => Arc::new(HyprlandBackend::from_env()?),
+        gql::Backend::Niri => Arc::new(NiriBackend::from_env()?),
+        gql::Backend::River | gql::Backend::Mock => Arc::new(RiverBackend::new(status)),
+    })
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use anyhow::{Result, bail};
+use serde_json::{Map, Value};
+use tracing::warn;
+
+use crate::gql::TagIndicatorFormat;
+use crate::{EndpointTarget, client};
+
+pub const DEFAULT_FORMAT: &str = "{tags}  {layout}  {title}";
+
+const QUERY: &str = "subscription { events { __typename \
+    ... on OutputFocusedTags { outputId name tags } \
+    ... on OutputViewTags { outputId name tags } \
+    ... on OutputUrgentTags { outputId name tags } \
+    ... on OutputLayoutName { outputId outputName layout } \
+    ... on OutputRemoved { outputId } \
+    ... on OutputNamed { outputId name } \
+    ... on SeatFocusedOutput { outputId name } \
+    ... on SeatFocusedView { title } \
+    ... on SeatMode { name } \
+    ... on SubscriptionLagged { missed } } }";
+
+pub struct BarOptions {
+    pub endpoint: EndpointTarget,
+    /// Line template; see [`Template`] for placeholders.
+    pub format: String,
+    /// Follow this output instead of the seat's focused one.
+    pub output: Option<String>,
+    /// Glyphs and tag count for `{tags}`.
+    pub tags: TagIndicatorFormat,
+}
+
+pub async fn run(options: BarOptions) -> Result<()> {
+    let BarOptions {
+        endpoint,
+        format,
+        output,
+        tags,
+    } = options;
+    if !(1..=32).contains(&tags.tag_count) {
+        bail!("--tag-count must be between 1 and 32");
+    }
+    let template = Template::parse(&format)?;
+    let mut state = BarState::default();
+    let mut last_line = None;
+
+    client::subscribe(endpoint, QUERY, &Map::new(), |payload| {
+        if let Some(event) = payload.pointer("/data/events") {
+            state.apply(event);
+        }
+        let line = template.render(&state.view(output.as_deref(), &tags));
+        if last_line.as_ref() != Some(&line) {
+            let mut stdout = io::stdout().lock();
+            let _ = writeln!(stdout, "{line}");
+            let _ = stdout.flush();
+            last_line = Some(line);
+        }
+    })
+    .await
+}
+
+#[derive(Default)]
+struct OutputInfo {
+    name: Option<String>,
+    focused: u32,
+    occupied: u32,
+    urgent: u32,
+    layout: String,
+}
+
+/// Client-side mirror of the server snapshot, rebuilt from the event stream.
+#[derive(Default)]
+struct BarState {
+    outputs: HashMap<String, OutputInfo>,
+    focused_output: Option<String>,
+    title: String,
+    mode: String,
+}
+
+impl BarState {
+    fn apply(&mut self, event: &Value) {
+        let str_field = |field: &str| event[field].as_str().unwrap_or_default().to_string();
+        let mask = |value: &Value| value.as_u64().map_or(0, |v| v as u32);
+        let id = str_field("outputId");
+        let typename = event["__typename"].as_str().unwrap_or_default();
+
+        match typename {
+            "OutputRemoved" => {
+                self.outputs.remove(&id);
+                if self.focused_output.as_ref() == Some(&id) {
+                    self.focused_output = None;
+                }
+                return;
+            }
+            "SeatFocusedView" => {
+                self.title = str_field("title");
+                return;
+            }
+            "SeatMode" => {
+                self.mode = str_field("name");
+                return;
+            }
+            "SubscriptionLagged" => {
+                warn!(
+                    missed = event["missed"].as_i64().unwrap_or_default(),
+                    "bar fell behind the event stream; state may be stale until the next change"
+                );
+                return;
+            }
+            _ => {}
+        }
+
+        let output = self.outputs.entry(id.clone()).or_default();
+        if let Some(name) = event["name"].as_str().or(event["outputName"].as_str()) {
+            output.name = Some(name.to_string());
+        }
+        match typename {
+            "OutputFocusedTags" => output.focused = mask(&event["tags"]),
+            "OutputUrgentTags" => output.urgent = mask(&event["tags"]),
+            "OutputViewTags" => {
+                output.occupied = event["tags"]
+                    .as_array()
+                    .map(|tags| tags.iter().fold(0, |acc, tags| acc | mask(tags)))
+                    .unwrap_or_default();
+            }
+            "OutputLayoutName" => output.layout = str_field("layout"),
+            "SeatFocusedOutput" => self.focused_output = Some(id),
+            _ => {}
+        }
+    }
+
+    fn view(&self, pinned: Option<&str>, format: &TagIndicatorFormat) -> BarView {
+        let output = match pinned {
+            Some(name) => self
+                .outputs
+                .values()
+                .find(|o| o.name.as_deref() == Some(name)),
+            None => self
+                .focused_output
+                .as_ref()
+                .and_then(|id| self.outputs.get(id)),
+        };
+        let tags = output
+            .map(|o| format.render(o.focused, o.occupied, o.urgent))
+            .unwrap_or_default();
+        BarView {
+            output: output.and_then(|o| o.name.clone()).unwrap_or_default(),
+            tags,
+            layout: output.map(|o| o.layout.clone()).unwrap_or_default(),
+            title: self.title.clone(),
+            mode: self.mode.clone(),
+        }
+    }
+}
+
+struct BarView {
+    output: String,
+    tags: String,
+    layout: String,
+    title: String,
+    mode: String,
+}
+
+enum Segment {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    Output,
+    Tags,
+    Layout,
+    Title,
+    Mode,
+}
+
+/// Parsed `--format` string. Placeholders are `{output}`, `{tags}`,
+/// `{layout}`, `{title}` and `{mode}`; `{{` and `}}` produce literal braces.
+struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    fn parse(format: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        bail!("unterminated placeholder {{{name} in --format");
+                    }
+                    let field = match name.as_str() {
+                        "output" => Field::Output,
+                        "tags" => Field::Tags,
+                        "layout" => Field::Layout,
+                        "title" => Field::Title,
+                        "mode" => Field::Mode,
+                        other => bail!(
+                            "unknown placeholder {{{other}}} in --format; expected output, tags, layout, title or mode"
+                        ),
+                    };
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::Field(field));
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Self { segments })
+    }
+
+    fn render(&self, view: &BarView) -> String {
+        let mut line = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => line.push_str(text),
+                Segment::Field(field) => line.push_str(match field {
+                    Field::Output => &view.output,
+                    Field::Tags => &view.tags,
+                    Field::Layout => &view.layout,
+                    Field::Title => &view.title,
+                    Field::Mode => &view.mode,
+                }),
+            }
+        }
+        // A newline in a window title would split the status line.
+        line.replace('\n', " ")
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_graphql::futures_util::stream::BoxStream;
+use async_graphql::parser::parse_query;
+use async_graphql::parser::types::OperationType;
+use async_graphql::{Data, Executor, Request, Response, Value};
+
+use crate::gql::{AppSchema, RiverSnapshot, RiverStateHandle};
+use crate::live;
+
+/// (document, operation name, serialized variables)
+type CacheKey = (String, Option<String>, String);
+
+struct Entry {
+    stored_at: Instant,
+    /// Snapshots the response was resolved against, one per display; any
+    /// river event swaps in a new one, which invalidates the entry.
+    snapshots: Vec<Arc<RiverSnapshot>>,
+    data: Value,
+}
+
+/// Executor that answers identical queries from memory for a short TTL, so a
+/// burst of polling widgets doesn't re-resolve the same document. A zero TTL
+/// passes every request straight to the schema.
+#[derive(Clone)]
+pub struct CachingExecutor {
+    schema: AppSchema,
+    states: Vec<RiverStateHandle>,
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<CacheKey, Entry>>>,
+}
+
+impl CachingExecutor {
+    pub fn new(schema: AppSchema, states: Vec<RiverStateHandle>, ttl: Duration) -> Self {
+        Self {
+            schema,
+            states,
+            ttl,
+            entries: Arc::default(),
+        }
+    }
+
+    fn key(request: &Request) -> Option<CacheKey> {
+        // Uploads aren't part of the document text, so they can't be keyed.
+        if !request.uploads.is_empty() {
+            return None;
+        }
+        // Mutations change the compositor, so they always run.
+        let doc = parse_query(&request.query).ok()?;
+        if doc
+            .operations
+            .iter()
+            .any(|(_, operation)| operation.node.ty != OperationType::Query)
+        {
+            return None;
+        }
+        let variables = serde_json::to_string(&request.variables).ok()?;
+        Some((
+            request.query.clone(),
+            request.operation_name.clone(),
+            variables,
+        ))
+    }
+
+    fn lookup(&self, key: &CacheKey, snapshots: &[Arc<RiverSnapshot>]) -> Option<Value> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        let fresh =
+            entry.stored_at.elapsed() < self.ttl && same_snapshots(&entry.snapshots, snapshots);
+        fresh.then(|| entry.data.clone())
+    }
+
+    fn store(&self, key: CacheKey, snapshots: Vec<Arc<RiverSnapshot>>, data: Value) {
+        let mut entries = self.entries.lock().unwrap();
+        let ttl = self.ttl;
+        entries.retain(|_, entry| {
+            entry.stored_at.elapsed() < ttl && same_snapshots(&entry.snapshots, &snapshots)
+        });
+        entries.insert(
+            key,
+            Entry {
+                stored_at: Instant::now(),
+                snapshots,
+                data,
+            },
+        );
+    }
+}
+
+fn same_snapshots(a: &[Arc<RiverSnapshot>], b: &[Arc<RiverSnapshot>]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| Arc::ptr_eq(a, b))
+}
+
+impl Executor for CachingExecutor {
+    async fn execute(&self, mut request: Request) -> Response {
+        // Over HTTP a live query is answered once, like a plain query.
+        if let Err(err) = live::take_live_directive(&mut request) {
+            return Response::from_errors(vec![err]);
+        }
+        if self.ttl.is_zero() {
+            return self.schema.execute(request).await;
+        }
+        let Some(key) = Self::key(&request) else {
+            return self.schema.execute(request).await;
+        };
+        let snapshots: Vec<_> = self.states.iter().map(|state| state.load_full()).collect();
+        if let Some(data) = self.lookup(&key, &snapshots) {
+            return Response::new(data);
+        }
+        let response = self.schema.execute(request).await;
+        // Only plain successful results are replayable; anything carrying
+        // errors, extensions or headers is resolved again next time.
+        if response.is_ok() && response.extensions.is_empty() && response.http_headers.is_empty() {
+            self.store(key, snapshots, response.data.clone());
+        }
+        response
+    }
+
+    fn execute_stream(
+        &self,
+        request: Request,
+        session_data: Option<Arc<Data>>,
+    ) -> BoxStream<'static, Response> {
+        Executor::execute_stream(&self.schema, request, session_data)
+    }
+}
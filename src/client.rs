@@ -1,27 +1,186 @@
-use crate::EndpointTarget;
-use anyhow::{Result, bail};
+use crate::format::OutputFormat;
+use crate::script::Script;
+use crate::sink::Pipe;
+use crate::{EndpointTarget, library};
+use anyhow::{Result, anyhow, bail};
+use async_graphql::parser::parse_query;
 use axum::http::{HeaderValue, header};
 use futures_util::{SinkExt, StreamExt};
 use serde::Deserialize;
-use serde_json::{Value, json};
+use serde_json::{Map, Value, json};
+use std::cell::Cell;
+use std::fmt;
 use std::fs;
 use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::Notify;
 use tokio_tungstenite::{
-    WebSocketStream, client_async, connect_async,
-    tungstenite::{client::IntoClientRequest, protocol::Message},
+    WebSocketStream, client_async,
+    tungstenite::{
+        client::IntoClientRequest,
+        protocol::{CloseFrame, Message},
+    },
 };
 use tracing::{error, warn};
 
 #[derive(Deserialize, Debug)]
-struct ServerMsg {
+pub struct ServerMsg {
     #[serde(rename = "type")]
-    typ: String,
+    pub typ: String,
     #[serde(default)]
-    payload: Option<Value>,
+    pub id: Option<String>,
+    #[serde(default)]
+    pub payload: Option<Value>,
+}
+
+/// Server closed the connection with a graphql-transport-ws close code.
+#[derive(Debug)]
+pub struct CloseError {
+    pub code: u16,
+    pub reason: String,
 }
 
-pub async fn run(endpoint: EndpointTarget, query_arg: Option<String>) -> Result<()> {
+impl CloseError {
+    pub fn from_frame(frame: Option<CloseFrame<'_>>) -> Option<Self> {
+        let frame = frame?;
+        let code = u16::from(frame.code);
+        (4000..5000).contains(&code).then(|| CloseError {
+            code,
+            reason: frame.reason.into_owned(),
+        })
+    }
+
+    fn description(&self) -> &'static str {
+        match self.code {
+            4400 => "bad request: the server rejected a protocol message (check the query payload)",
+            4401 => "unauthorized: the server requires a successful connection_init",
+            4403 => "forbidden: the server refused the connection",
+            4408 => "connection initialisation timeout: no connection_ack in time",
+            4409 => "subscriber already exists: an operation with this id is already running",
+            4429 => "too many initialisation requests",
+            4500 => "internal server error",
+            _ => "connection closed by server",
+        }
+    }
+
+    /// Process exit status for this close code, so scripts can tell failures apart.
+    pub fn exit_code(&self) -> i32 {
+        match self.code {
+            4400 => 10,
+            4401 => 11,
+            4403 => 12,
+            4408 => 13,
+            4409 => 14,
+            4429 => 15,
+            4500 => 16,
+            _ => 19,
+        }
+    }
+}
+
+impl fmt::Display for CloseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (close code {})", self.description(), self.code)?;
+        if !self.reason.is_empty() {
+            write!(f, ": {}", self.reason)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CloseError {}
+
+/// First delay before reconnecting; doubles per failed attempt.
+const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(250);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// How a subscription ended when no error occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ended {
+    /// The server sent `complete` for the operation.
+    Completed,
+    /// The connection went away while the operation was still running.
+    Disconnected,
+}
+
+/// Client-mode behavior shared by inline and named queries.
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    /// Retry with backoff instead of exiting when the connection fails.
+    pub reconnect: bool,
+    pub format: OutputFormat,
+    /// Print only the value of the root field rather than the whole payload.
+    pub raw: bool,
+    /// Exit after printing this many results.
+    pub count: Option<u64>,
+    /// Print an exit summary to stderr; see [`Summary`].
+    pub summary: bool,
+    /// Rhai script deciding what to print instead of `format`; see [`Script`].
+    pub script: Option<PathBuf>,
+    /// Shell commands fed the printed lines instead of stdout.
+    pub pipes: Vec<String>,
+}
+
+/// Why the client stopped, as reported in the exit summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitReason {
+    Completed,
+    Disconnected,
+    Count,
+    Error,
+    Signal(i32),
+}
+
+impl ExitReason {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Completed => "complete",
+            Self::Disconnected => "disconnected",
+            Self::Count => "count",
+            Self::Error => "error",
+            Self::Signal(_) => "signal",
+        }
+    }
+}
+
+/// Final line on stderr with `--summary`, for wrapper scripts that log why a
+/// subscriber stopped.
+struct Summary {
+    reason: ExitReason,
+    events: u64,
+    duration: Duration,
+    reconnects: u32,
+    error: Option<String>,
+}
+
+impl Summary {
+    fn to_json(&self) -> Value {
+        let mut summary = json!({
+            "status": "exited",
+            "reason": self.reason.name(),
+            "events": self.events,
+            "durationMs": self.duration.as_millis() as u64,
+            "reconnects": self.reconnects,
+        });
+        if let ExitReason::Signal(signal) = self.reason {
+            summary["signal"] = json!(signal);
+        }
+        if let Some(error) = &self.error {
+            summary["error"] = json!(error);
+        }
+        summary
+    }
+}
+
+pub async fn run(
+    endpoint: EndpointTarget,
+    query_arg: Option<String>,
+    options: ClientOptions,
+) -> Result<()> {
     let query = match query_arg {
         Some(q) if q.starts_with('@') => fs::read_to_string(&q[1..])?,
         Some(q) => q,
@@ -36,6 +195,212 @@ pub async fn run(endpoint: EndpointTarget, query_arg: Option<String>) -> Result<
         }
     };
 
+    print_results(endpoint, &query, &Map::new(), options).await
+}
+
+/// Runs the library operation `name`, with `overrides` (`name=value`) applied
+/// on top of its default variables.
+pub async fn run_named(
+    endpoint: EndpointTarget,
+    name: &str,
+    overrides: &[String],
+    options: ClientOptions,
+) -> Result<()> {
+    let library::NamedQuery {
+        query,
+        mut variables,
+    } = library::load(name)?;
+    for arg in overrides {
+        let (name, value) = library::parse_variable(arg)?;
+        variables.insert(name, value);
+    }
+    print_results(endpoint, &query, &variables, options).await
+}
+
+async fn print_results(
+    endpoint: EndpointTarget,
+    query: &str,
+    variables: &Map<String, Value>,
+    options: ClientOptions,
+) -> Result<()> {
+    validate_document(query)?;
+
+    let mut script = options.script.as_deref().map(Script::load).transpose()?;
+    let pipes: Vec<Pipe> = options.pipes.iter().cloned().map(Pipe::spawn).collect();
+    let started = Instant::now();
+    let format = options.format;
+    let events = Cell::new(0u64);
+    let reconnects = Cell::new(0u32);
+    let count_reached = Notify::new();
+    let print = |payload: Value| {
+        // More results may already be buffered when the count is reached.
+        if options.count.is_some_and(|count| events.get() >= count) {
+            return;
+        }
+        let line = match script.as_mut() {
+            None if options.raw => {
+                // The envelope is gone, so errors go to stderr instead.
+                if let Some(message) = payload["errors"][0]["message"].as_str() {
+                    warn!("{message}");
+                }
+                crate::format::unwrapped_data(&payload).to_string()
+            }
+            None => format.render(&payload),
+            Some(script) => match script.render(&payload) {
+                Ok(Some(line)) => line,
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("{e:#}");
+                    return;
+                }
+            },
+        };
+        if pipes.is_empty() {
+            println!("{line}");
+        }
+        for pipe in &pipes {
+            pipe.send(line.clone());
+        }
+        events.set(events.get() + 1);
+        if options.count.is_some_and(|count| events.get() >= count) {
+            count_reached.notify_one();
+        }
+    };
+    let (reason, result) = tokio::select! {
+        result = stream_results(&endpoint, query, variables, options.reconnect, &reconnects, print) => {
+            match result {
+                Ok(Ended::Completed) => (ExitReason::Completed, Ok(())),
+                Ok(Ended::Disconnected) => (ExitReason::Disconnected, Ok(())),
+                Err(e) => (ExitReason::Error, Err(e)),
+            }
+        }
+        _ = count_reached.notified() => (ExitReason::Count, Ok(())),
+        signal = exit_signal(), if options.summary => (ExitReason::Signal(signal?), Ok(())),
+    };
+    for pipe in pipes {
+        pipe.close().await;
+    }
+    if options.summary {
+        let summary = Summary {
+            reason,
+            events: events.get(),
+            duration: started.elapsed(),
+            reconnects: reconnects.get(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        report_status(summary.to_json());
+    }
+    if let ExitReason::Signal(signal) = reason {
+        std::process::exit(128 + signal);
+    }
+    result
+}
+
+/// Resolves with the number of the first SIGINT or SIGTERM.
+async fn exit_signal() -> io::Result<i32> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(tokio::select! {
+        _ = interrupt.recv() => libc::SIGINT,
+        _ = terminate.recv() => libc::SIGTERM,
+    })
+}
+
+/// Runs the subscription once, or with `reconnect` until the server completes
+/// it or refuses it, counting reconnections in `reconnects`.
+async fn stream_results(
+    endpoint: &EndpointTarget,
+    query: &str,
+    variables: &Map<String, Value>,
+    reconnect: bool,
+    reconnects: &Cell<u32>,
+    mut print: impl FnMut(Value),
+) -> Result<Ended> {
+    if !reconnect {
+        return subscribe_once(endpoint, query, variables, &mut || {}, &mut print).await;
+    }
+
+    // Consecutive failed attempts; a successful connection starts over.
+    let mut attempt = 0u32;
+    loop {
+        attempt += 1;
+        report_status(json!({ "status": "connecting", "attempt": attempt }));
+        let mut connected = false;
+        let result = subscribe_once(
+            endpoint,
+            query,
+            variables,
+            &mut || {
+                connected = true;
+                report_status(json!({ "status": "connected", "attempt": attempt }));
+            },
+            &mut print,
+        )
+        .await;
+        let reason = match result {
+            Ok(Ended::Completed) => return Ok(Ended::Completed),
+            // The server refused the operation; retrying won't change that.
+            Err(e) if e.is::<CloseError>() => return Err(e),
+            Ok(Ended::Disconnected) => "connection closed".to_string(),
+            Err(e) => e.to_string(),
+        };
+        if connected {
+            attempt = 1;
+        }
+        let delay = RECONNECT_BASE_DELAY
+            .saturating_mul(1 << (attempt - 1).min(6))
+            .min(RECONNECT_MAX_DELAY);
+        report_status(json!({
+            "status": "retrying",
+            "attempt": attempt + 1,
+            "delayMs": delay.as_millis() as u64,
+            "error": reason,
+        }));
+        tokio::time::sleep(delay).await;
+        reconnects.set(reconnects.get() + 1);
+    }
+}
+
+/// Connection status for supervisors, one JSON object per stderr line so it
+/// stays apart from the data on stdout.
+fn report_status(status: Value) {
+    eprintln!("{status}");
+}
+
+/// Runs `query` against `endpoint`, handing every `next` payload to `on_next`
+/// until the server completes the operation or closes the connection.
+pub async fn subscribe(
+    endpoint: EndpointTarget,
+    query: &str,
+    variables: &Map<String, Value>,
+    mut on_next: impl FnMut(Value),
+) -> Result<()> {
+    subscribe_once(&endpoint, query, variables, &mut || {}, &mut on_next).await?;
+    Ok(())
+}
+
+/// Like [`subscribe`], but calls `on_connected` once the server acknowledges
+/// the connection and reports how the operation ended.
+pub async fn subscribe_once(
+    endpoint: &EndpointTarget,
+    query: &str,
+    variables: &Map<String, Value>,
+    on_connected: &mut impl FnMut(),
+    on_next: &mut impl FnMut(Value),
+) -> Result<Ended> {
+    let mut ws = connect(endpoint).await?;
+    drive_subscription(&mut ws, query, variables, on_connected, on_next).await
+}
+
+/// The byte stream under a websocket, for either kind of endpoint.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+pub type Socket = WebSocketStream<Box<dyn Transport>>;
+
+/// Opens a graphql-transport-ws websocket to `endpoint`.
+pub async fn connect(endpoint: &EndpointTarget) -> Result<Socket> {
     match endpoint {
         EndpointTarget::Tcp(url) => {
             let mut req = url.clone().into_client_request()?;
@@ -43,25 +408,35 @@ pub async fn run(endpoint: EndpointTarget, query_arg: Option<String>) -> Result<
                 header::SEC_WEBSOCKET_PROTOCOL,
                 HeaderValue::from_static("graphql-transport-ws"),
             );
+            let handshake_failed = || {
+                anyhow!(
+                    "websocket handshake failed; ensure server is at {url} and supports graphql-transport-ws"
+                )
+            };
 
-            let (mut ws, _resp) = match connect_async(req).await {
-                Ok(v) => v,
+            let host = url.host_str().unwrap_or_default();
+            let port = url.port_or_known_default().unwrap_or(80);
+            let stream: Box<dyn Transport> = match TcpStream::connect((host, port)).await {
+                Ok(s) => Box::new(s),
                 Err(e) => {
                     error!("connect error: {}", e);
-                    bail!(
-                        "websocket handshake failed; ensure server is at {url} and supports graphql-transport-ws"
-                    );
+                    return Err(handshake_failed());
                 }
             };
-
-            drive_subscription(&mut ws, &query).await?
+            match client_async(req, stream).await {
+                Ok((ws, _resp)) => Ok(ws),
+                Err(e) => {
+                    error!("connect error: {}", e);
+                    Err(handshake_failed())
+                }
+            }
         }
         #[cfg(unix)]
         EndpointTarget::Unix { socket, path } => {
             use tokio::net::UnixStream;
 
-            let stream = match UnixStream::connect(&socket).await {
-                Ok(s) => s,
+            let stream: Box<dyn Transport> = match UnixStream::connect(socket).await {
+                Ok(s) => Box::new(s),
                 Err(e) => {
                     error!("unix connect error: {}", e);
                     return Err(e.into());
@@ -74,8 +449,8 @@ pub async fn run(endpoint: EndpointTarget, query_arg: Option<String>) -> Result<
                 HeaderValue::from_static("graphql-transport-ws"),
             );
 
-            let (mut ws, _resp) = match client_async(req, stream).await {
-                Ok(v) => v,
+            match client_async(req, stream).await {
+                Ok((ws, _resp)) => Ok(ws),
                 Err(e) => {
                     error!("connect error: {}", e);
                     bail!(
@@ -83,48 +458,55 @@ pub async fn run(endpoint: EndpointTarget, query_arg: Option<String>) -> Result<
                         socket.display()
                     );
                 }
-            };
-
-            drive_subscription(&mut ws, &query).await?
+            }
         }
     }
+}
 
-    Ok(())
+/// Parses the document locally so typos are reported with their position
+/// instead of surfacing as a server `error` frame after the handshake.
+pub fn validate_document(query: &str) -> Result<()> {
+    let doc = match parse_query(query) {
+        Ok(doc) => doc,
+        Err(e) => {
+            let at = e
+                .positions()
+                .next()
+                .map(|pos| format!(" at line {}, column {}", pos.line, pos.column))
+                .unwrap_or_default();
+            bail!("invalid GraphQL document{at}:\n{e}");
+        }
+    };
+    match doc.operations.iter().count() {
+        0 => bail!("GraphQL document contains no query or subscription operation"),
+        1 => Ok(()),
+        n => bail!("GraphQL document contains {n} operations; supply exactly one"),
+    }
 }
 
-async fn drive_subscription<S>(ws: &mut WebSocketStream<S>, query: &str) -> Result<()>
+async fn drive_subscription<S>(
+    ws: &mut WebSocketStream<S>,
+    query: &str,
+    variables: &Map<String, Value>,
+    on_connected: &mut impl FnMut(),
+    on_next: &mut impl FnMut(Value),
+) -> Result<Ended>
 where
     S: AsyncRead + AsyncWrite + Unpin,
 {
-    ws.send(Message::Text(
-        json!({
-            "type": "connection_init",
-            "payload": {}
-        })
-        .to_string(),
-    ))
-    .await?;
-
-    loop {
-        let Some(msg) = ws.next().await else {
-            bail!("connection closed before ack");
-        };
-        let msg = msg?;
-        if let Message::Text(txt) = msg {
-            if let Ok(parsed) = serde_json::from_str::<ServerMsg>(&txt) {
-                if parsed.typ == "connection_ack" {
-                    break;
-                }
-            }
-        }
-    }
+    init_connection(ws).await?;
+    on_connected();
 
     let sub_id = "1";
+    let mut payload = json!({ "query": query });
+    if !variables.is_empty() {
+        payload["variables"] = Value::Object(variables.clone());
+    }
     ws.send(Message::Text(
         json!({
             "id": sub_id,
             "type": "subscribe",
-            "payload": { "query": query }
+            "payload": payload
         })
         .to_string(),
     ))
@@ -138,7 +520,7 @@ where
                     match parsed.typ.as_str() {
                         "next" => {
                             if let Some(payload) = parsed.payload {
-                                println!("{}", payload);
+                                on_next(payload);
                             }
                         }
                         "error" => {
@@ -147,17 +529,61 @@ where
                                 parsed.payload.unwrap_or(serde_json::Value::Null)
                             );
                         }
-                        "complete" => break,
+                        "complete" => return Ok(Ended::Completed),
                         _ => {}
                     }
                 }
             }
-            Message::Close(_) => break,
+            Message::Close(frame) => {
+                if let Some(err) = CloseError::from_frame(frame) {
+                    return Err(err.into());
+                }
+                break;
+            }
             _ => {
                 warn!("unexpected websocket message: {:?}", m);
             }
         }
     }
 
+    Ok(Ended::Disconnected)
+}
+
+/// Sends `connection_init` and waits for the server's `connection_ack`.
+pub async fn init_connection<S>(ws: &mut WebSocketStream<S>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    ws.send(Message::Text(
+        json!({
+            "type": "connection_init",
+            "payload": {}
+        })
+        .to_string(),
+    ))
+    .await?;
+
+    loop {
+        let Some(msg) = ws.next().await else {
+            bail!("connection closed before ack");
+        };
+        match msg? {
+            Message::Text(txt) => {
+                if let Ok(parsed) = serde_json::from_str::<ServerMsg>(&txt) {
+                    if parsed.typ == "connection_ack" {
+                        break;
+                    }
+                }
+            }
+            Message::Close(frame) => {
+                if let Some(err) = CloseError::from_frame(frame) {
+                    return Err(err.into());
+                }
+                bail!("connection closed before ack");
+            }
+            _ => {}
+        }
+    }
+
     Ok(())
 }
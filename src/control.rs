@@ -0,0 +1,179 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::unix::pipe;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tracing::warn;
+
+use crate::EndpointTarget;
+use crate::client::{self, ClientOptions, CloseError, ServerMsg, Socket};
+
+/// Operation id for the query given on the command line, if any.
+const INITIAL_ID: &str = "main";
+
+/// One line read from the control FIFO.
+#[derive(Debug, PartialEq, Eq)]
+enum ControlCommand {
+    /// `subscribe ID QUERY`; QUERY runs to the end of the line, or is
+    /// `@file`.
+    Subscribe { id: String, query: String },
+    /// `unsubscribe ID`
+    Unsubscribe { id: String },
+}
+
+impl ControlCommand {
+    fn parse(line: &str) -> Result<Self> {
+        let line = line.trim();
+        let (verb, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim_start();
+        match verb {
+            "subscribe" => {
+                let Some((id, query)) = rest.split_once(char::is_whitespace) else {
+                    bail!("expected `subscribe ID QUERY`");
+                };
+                let query = match query.trim().strip_prefix('@') {
+                    Some(path) => fs::read_to_string(path)
+                        .with_context(|| format!("reading query file {path}"))?,
+                    None => query.trim().to_string(),
+                };
+                Ok(Self::Subscribe {
+                    id: id.to_string(),
+                    query,
+                })
+            }
+            "unsubscribe" if !rest.is_empty() && !rest.contains(char::is_whitespace) => {
+                Ok(Self::Unsubscribe {
+                    id: rest.to_string(),
+                })
+            }
+            "unsubscribe" => bail!("expected `unsubscribe ID`"),
+            other => bail!("unknown control command {other:?}; expected subscribe or unsubscribe"),
+        }
+    }
+}
+
+/// Keeps one connection open and runs the operations named on the FIFO at
+/// `control`, printing each result with its operation's `id`. `query`, when
+/// given, starts as operation `main`. Runs until the server closes the
+/// connection.
+pub async fn run(
+    endpoint: EndpointTarget,
+    query: Option<String>,
+    control: &Path,
+    options: ClientOptions,
+) -> Result<()> {
+    // Opened read-write so the FIFO never reports end of file when the last
+    // writer goes away.
+    let fifo = pipe::OpenOptions::new()
+        .read_write(true)
+        .open_receiver(control)
+        .with_context(|| format!("opening control FIFO {}", control.display()))?;
+    let mut commands = BufReader::new(fifo).lines();
+
+    let mut ws = client::connect(&endpoint).await?;
+    client::init_connection(&mut ws).await?;
+    let mut active = HashSet::new();
+    if let Some(query) = query {
+        subscribe(&mut ws, &mut active, INITIAL_ID, &query).await?;
+    }
+
+    loop {
+        tokio::select! {
+            line = commands.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match ControlCommand::parse(&line) {
+                    Ok(ControlCommand::Subscribe { id, query }) => {
+                        if let Err(e) = subscribe(&mut ws, &mut active, &id, &query).await {
+                            warn!(id = %id, "control subscribe rejected: {e:#}");
+                        }
+                    }
+                    Ok(ControlCommand::Unsubscribe { id }) => {
+                        if active.remove(&id) {
+                            ws.send(Message::Text(json!({ "id": id, "type": "complete" }).to_string()))
+                                .await?;
+                        } else {
+                            warn!(id = %id, "control unsubscribe for an unknown operation");
+                        }
+                    }
+                    Err(e) => warn!(line = %line, "invalid control command: {e:#}"),
+                }
+            }
+            msg = ws.next() => {
+                let Some(msg) = msg else {
+                    return Ok(());
+                };
+                match msg? {
+                    Message::Text(txt) => {
+                        let Ok(parsed) = serde_json::from_str::<ServerMsg>(&txt) else {
+                            continue;
+                        };
+                        let id = parsed.id.unwrap_or_default();
+                        match parsed.typ.as_str() {
+                            "next" => {
+                                if let Some(Value::Object(mut payload)) = parsed.payload {
+                                    payload.insert("id".into(), Value::String(id));
+                                    println!("{}", options.format.render(&Value::Object(payload)));
+                                }
+                            }
+                            "complete" => {
+                                active.remove(&id);
+                            }
+                            // The server ends a rejected operation without a
+                            // `complete`, so its id is free again.
+                            "error" => {
+                                active.remove(&id);
+                                warn!(
+                                    id = %id,
+                                    "operation failed: {}",
+                                    parsed.payload.unwrap_or(serde_json::Value::Null)
+                                );
+                            }
+                            _ => {}
+                        }
+                    }
+                    Message::Close(frame) => {
+                        if let Some(err) = CloseError::from_frame(frame) {
+                            return Err(err.into());
+                        }
+                        return Ok(());
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Starts `query` as operation `id` unless that id is already running.
+async fn subscribe(
+    ws: &mut Socket,
+    active: &mut HashSet<String>,
+    id: &str,
+    query: &str,
+) -> Result<()> {
+    if active.contains(id) {
+        bail!("operation {id:?} is already running");
+    }
+    client::validate_document(query)?;
+    ws.send(Message::Text(
+        json!({
+            "id": id,
+            "type": "subscribe",
+            "payload": { "query": query }
+        })
+        .to_string(),
+    ))
+    .await?;
+    active.insert(id.to_string());
+    Ok(())
+}
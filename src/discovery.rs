@@ -0,0 +1,140 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::ListenTarget;
+
+/// What a running server tells clients about itself, kept as
+/// `$XDG_RUNTIME_DIR/riverql.json` while it runs.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Discovery {
+    /// Unix socket the server listens on, if it listens on one.
+    pub socket: Option<PathBuf>,
+    /// TCP address the server listens on, if it listens on one.
+    pub tcp: Option<SocketAddr>,
+    /// FNV-1a hash of the schema's SDL, as 16 hex digits.
+    pub schema_hash: String,
+    pub pid: u32,
+}
+
+impl Discovery {
+    pub fn new(listen: &ListenTarget, sdl: &str) -> Self {
+        let (socket, tcp) = match listen {
+            ListenTarget::Tcp(addr) => (None, Some(*addr)),
+            #[cfg(unix)]
+            ListenTarget::Unix(path) => (Some(path.clone()), None),
+        };
+        Self {
+            socket,
+            tcp,
+            schema_hash: schema_hash(sdl),
+            pid: std::process::id(),
+        }
+    }
+
+    /// The file left by a server that is still running, if any. A file
+    /// whose server died without removing it is ignored.
+    pub fn read() -> Option<Self> {
+        let path = path()?;
+        let text = fs::read_to_string(&path).ok()?;
+        let discovery: Self = match serde_json::from_str(&text) {
+            Ok(discovery) => discovery,
+            Err(e) => {
+                warn!(file = %path.display(), "ignoring unreadable discovery file: {e}");
+                return None;
+            }
+        };
+        if !process_alive(discovery.pid) {
+            debug!(file = %path.display(), pid = discovery.pid, "ignoring stale discovery file");
+            return None;
+        }
+        Some(discovery)
+    }
+
+    /// The websocket endpoint to reach this server, preferring its socket.
+    pub fn endpoint(&self) -> Option<String> {
+        match (&self.socket, self.tcp) {
+            (Some(socket), _) => Some(format!("unix://{}#/graphql", socket.display())),
+            (None, Some(addr)) => Some(format!("ws://{addr}/graphql")),
+            (None, None) => None,
+        }
+    }
+
+    /// Writes the file, which is removed when the returned guard drops.
+    /// Without `XDG_RUNTIME_DIR` nothing is written.
+    pub fn publish(&self) -> Result<Option<Published>> {
+        let Some(path) = path() else {
+            debug!("XDG_RUNTIME_DIR is not set; not writing a discovery file");
+            return Ok(None);
+        };
+        // Written aside and renamed, so clients never read half a file.
+        let partial = path.with_extension(format!("json.{}", self.pid));
+        fs::write(&partial, serde_json::to_vec(self)?)
+            .with_context(|| format!("writing {}", partial.display()))?;
+        fs::rename(&partial, &path).with_context(|| format!("writing {}", path.display()))?;
+        Ok(Some(Published {
+            path,
+            pid: self.pid,
+        }))
+    }
+}
+
+/// Removes the discovery file on drop, unless another server has replaced
+/// it since.
+pub struct Published {
+    path: PathBuf,
+    pid: u32,
+}
+
+impl Drop for Published {
+    fn drop(&mut self) {
+        let ours = fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|text| serde_json::from_str::<Discovery>(&text).ok())
+            .is_some_and(|discovery| discovery.pid == self.pid);
+        if !ours {
+            return;
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!(file = %self.path.display(), "failed to remove discovery file: {e}");
+            }
+        }
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join("riverql.json"))
+}
+
+/// Stable across builds and platforms, unlike `DefaultHasher`.
+fn schema_hash(sdl: &str) -> String {
+    let hash = sdl.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks; EPERM still means the process exists.
+    let alive = unsafe { libc::kill(pid, 0) == 0 };
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
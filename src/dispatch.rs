@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+use crate::gql;
+use crate::river;
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Fans a display's events out to broadcast channels, so a subscription
+/// only wakes for events it could pass on. Every event goes to the main
+/// channel. An event about a named output also goes to that output's
+/// channel. Seat-wide events and heartbeats go to the seat channel and to
+/// every output's channel, which keeps each output's stream in order
+/// without merging channels.
+#[derive(Clone)]
+pub struct Dispatcher {
+    all: Sender<river::Event>,
+    seat: Sender<river::Event>,
+    /// Keyed by output name. Only outputs the compositor named get a
+    /// channel, so clients can't add channels by asking for made-up names.
+    outputs: Arc<Mutex<HashMap<String, OutputChannel>>>,
+}
+
+struct OutputChannel {
+    sender: Sender<river::Event>,
+    /// Cleared when the output goes away. The channel stays while it has
+    /// subscribers, so they carry on when the output comes back.
+    present: bool,
+}
+
+impl Default for Dispatcher {
+    fn default() -> Self {
+        Self {
+            all: broadcast::channel(CHANNEL_CAPACITY).0,
+            seat: broadcast::channel(CHANNEL_CAPACITY).0,
+            outputs: Arc::default(),
+        }
+    }
+}
+
+impl Dispatcher {
+    /// Sends `event` to each channel that carries it; returns how many
+    /// subscribers of the main channel got it.
+    pub fn send(
+        &self,
+        event: river::Event,
+    ) -> Result<usize, broadcast::error::SendError<river::Event>> {
+        // Nobody listening on a narrower channel is fine.
+        if event.output_id().is_none() {
+            let _ = self.seat.send(event.clone());
+            for output in self.outputs.lock().unwrap().values() {
+                let _ = output.sender.send(event.clone());
+            }
+        } else if let Some(name) = gql::event_output_name(&event) {
+            let mut outputs = self.outputs.lock().unwrap();
+            if let river::Event::OutputNamed { .. } = event {
+                outputs
+                    .entry(name.to_string())
+                    .or_insert_with(|| OutputChannel {
+                        sender: broadcast::channel(CHANNEL_CAPACITY).0,
+                        present: true,
+                    })
+                    .present = true;
+            }
+            if let Some(output) = outputs.get_mut(name) {
+                let _ = output.sender.send(event.clone());
+                if let river::Event::OutputRemoved { .. } = event {
+                    output.present = false;
+                    outputs
+                        .retain(|_, output| output.present || output.sender.receiver_count() > 0);
+                }
+            }
+        }
+        self.all.send(event)
+    }
+
+    /// Every event.
+    pub fn subscribe(&self) -> Receiver<river::Event> {
+        self.all.subscribe()
+    }
+
+    /// Events about no particular output: seat focus, mode and heartbeats.
+    pub fn subscribe_seat(&self) -> Receiver<river::Event> {
+        self.seat.subscribe()
+    }
+
+    /// Events about the output called `name`, plus seat-wide ones. For a
+    /// name the compositor hasn't given any output this is the main
+    /// channel, and the subscriber filters it.
+    pub fn subscribe_output(&self, name: &str) -> Receiver<river::Event> {
+        match self.outputs.lock().unwrap().get(name) {
+            Some(output) => output.sender.subscribe(),
+            None => self.all.subscribe(),
+        }
+    }
+
+    /// The main channel, for consumers that take a plain sender.
+    pub fn sender(&self) -> &Sender<river::Event> {
+        &self.all
+    }
+}
@@ -0,0 +1,225 @@
+use std::str::FromStr;
+
+use anyhow::{Error, bail};
+use serde_json::Value;
+
+/// How client mode prints each result.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The `next` payload as one line of JSON.
+    #[default]
+    Json,
+    /// `RIVERQL_*='value'` assignments, safe to `eval` in a POSIX shell.
+    Shell,
+    /// Aligned columns, one row per list item, for reading in a terminal.
+    Table,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "json" => Ok(Self::Json),
+            "shell" => Ok(Self::Shell),
+            "table" => Ok(Self::Table),
+            other => bail!("unknown format {other:?}; expected json, shell or table"),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn render(self, payload: &Value) -> String {
+        match self {
+            Self::Json => payload.to_string(),
+            Self::Shell => shell_line(payload),
+            Self::Table => table(payload),
+        }
+    }
+}
+
+/// Lays `payload.data` out as columns. A lone root field is unwrapped as in
+/// [`shell_line`]; with several, each gets its own table under its name. A
+/// list becomes one row per item and an object a single row, with columns in
+/// the order the query selects them. Nested objects
+/// add dotted columns (`position.x`), lists of scalars are joined with
+/// spaces, and the first error follows the table.
+fn table(payload: &Value) -> String {
+    let mut sections = match &payload["data"] {
+        Value::Null => Vec::new(),
+        Value::Object(root) if root.len() > 1 => root
+            .iter()
+            .map(|(name, value)| format!("{name}\n{}", columns(value)))
+            .collect(),
+        _ => vec![columns(unwrapped_data(payload))],
+    };
+    if let Some(message) = payload["errors"][0]["message"].as_str() {
+        sections.push(format!("error: {message}"));
+    }
+    sections.join("\n\n")
+}
+
+fn columns(value: &Value) -> String {
+    let rows: Vec<Vec<(String, String)>> = match value {
+        Value::Array(items) => items.iter().map(row).collect(),
+        Value::Object(_) => vec![row(value)],
+        other => return scalar(other),
+    };
+    if rows.is_empty() {
+        return "(none)".to_string();
+    }
+    let mut header: Vec<String> = Vec::new();
+    for (column, _) in rows.iter().flatten() {
+        if !header.contains(column) {
+            header.push(column.clone());
+        }
+    }
+    let mut lines = vec![header.clone()];
+    lines.extend(rows.iter().map(|row| {
+        header
+            .iter()
+            .map(|column| {
+                row.iter()
+                    .find(|(name, _)| name == column)
+                    .map(|(_, cell)| cell.clone())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }));
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| {
+            lines
+                .iter()
+                .map(|line| line[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    lines
+        .iter()
+        .map(|line| {
+            let padded: Vec<String> = line
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:<width$}"))
+                .collect();
+            padded.join("  ").trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The cells of one table row, keyed by column.
+fn row(value: &Value) -> Vec<(String, String)> {
+    let mut cells = Vec::new();
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                cell(key, value, &mut cells);
+            }
+        }
+        value => cell("value", value, &mut cells),
+    }
+    cells
+}
+
+fn cell(name: &str, value: &Value, cells: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                cell(&format!("{name}.{key}"), value, cells);
+            }
+        }
+        Value::Array(items) if items.iter().any(|item| item.is_object() || item.is_array()) => {
+            cells.push((name.to_string(), value.to_string()));
+        }
+        Value::Array(items) => {
+            let words = items.iter().map(scalar).collect::<Vec<_>>();
+            cells.push((name.to_string(), words.join(" ")));
+        }
+        value => cells.push((name.to_string(), scalar(value))),
+    }
+}
+
+const SHELL_PREFIX: &str = "RIVERQL";
+
+/// Flattens `payload.data` into assignments. A lone root field is unwrapped,
+/// so `events { name tags }` yields `RIVERQL_NAME` and `RIVERQL_TAGS`; nested
+/// objects and arrays of objects extend the name with their key or index,
+/// and scalar lists become space-separated words. The first error, if any,
+/// is exposed as `RIVERQL_ERROR`, and the operation `id` added by
+/// `--control` as `RIVERQL_ID`.
+fn shell_line(payload: &Value) -> String {
+    let mut vars = Vec::new();
+    if let Some(id) = payload["id"].as_str() {
+        vars.push((format!("{SHELL_PREFIX}_ID"), id.to_string()));
+    }
+    flatten(SHELL_PREFIX, unwrapped_data(payload), &mut vars);
+    if let Some(message) = payload["errors"][0]["message"].as_str() {
+        vars.push((format!("{SHELL_PREFIX}_ERROR"), message.to_string()));
+    }
+    vars.iter()
+        .map(|(name, value)| format!("{name}={}", shell_quote(value)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `payload.data`, or the value of its root field when there is only one.
+pub fn unwrapped_data(payload: &Value) -> &Value {
+    match &payload["data"] {
+        Value::Object(root) if root.len() == 1 => root.values().next().unwrap_or(&Value::Null),
+        data => data,
+    }
+}
+
+fn flatten(name: &str, value: &Value, vars: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                flatten(&format!("{name}_{}", shell_name(key)), value, vars);
+            }
+        }
+        Value::Array(items) if items.iter().any(|item| item.is_object() || item.is_array()) => {
+            for (index, item) in items.iter().enumerate() {
+                flatten(&format!("{name}_{index}"), item, vars);
+            }
+        }
+        Value::Array(items) => {
+            let words = items.iter().map(scalar).collect::<Vec<_>>();
+            vars.push((name.to_string(), words.join(" ")));
+        }
+        value => vars.push((name.to_string(), scalar(value))),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// `focusedTagsList` -> `FOCUSED_TAGS_LIST`; `__typename` -> `TYPENAME`.
+pub fn shell_name(key: &str) -> String {
+    let mut name = String::new();
+    let mut prev_lower = false;
+    for c in key.trim_start_matches('_').chars() {
+        if c.is_ascii_uppercase() && prev_lower {
+            name.push('_');
+        }
+        prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        name.push(if c.is_ascii_alphanumeric() {
+            c.to_ascii_uppercase()
+        } else {
+            '_'
+        });
+    }
+    name
+}
+
+/// Single-quotes `value`; embedded quotes become `'\''`. Newlines turn into
+/// spaces so every result stays on one line for `while read`.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''").replace('\n', " "))
+}
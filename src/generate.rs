@@ -0,0 +1,147 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Result, bail};
+use async_graphql::{EmptyMutation, Schema};
+use serde_json::Value;
+
+use crate::gql::{QueryRoot, SubscriptionRoot};
+
+const EVENT_UNION: &str = "RiverEvent";
+
+/// Builds an `events` subscription selecting `fields` on each of `types`.
+/// Both are comma-separated; fields may be snake_case (`output_id`). Every
+/// event type is used when `types` is empty and every scalar field when
+/// `fields` is empty. Names are checked against the schema built into this
+/// binary, so mistakes are reported before connecting.
+pub async fn subscription(types: Option<&str>, fields: Option<&str>) -> Result<String> {
+    let schema = Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot).finish();
+    let response = schema
+        .execute(format!(
+            "{{ __type(name: \"{EVENT_UNION}\") {{ possibleTypes {{ name \
+             fields {{ name type {{ kind ofType {{ kind ofType {{ kind ofType {{ kind }} }} }} }} }} }} }} }}"
+        ))
+        .await;
+    let introspection = response.data.into_json()?;
+    let known: Vec<EventType> = introspection["__type"]["possibleTypes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(EventType::from_introspection)
+        .collect();
+
+    let selected: Vec<&EventType> = match types {
+        None => known.iter().collect(),
+        Some(types) => split_list(types)
+            .map(|name| match known.iter().find(|ty| ty.name == name) {
+                Some(ty) => Ok(ty),
+                None => bail!(
+                    "unknown event type {name:?}; expected one of {}",
+                    known
+                        .iter()
+                        .map(|ty| ty.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            })
+            .collect::<Result<_>>()?,
+    };
+    if selected.is_empty() {
+        bail!("--types needs at least one event type");
+    }
+    // `__typename` is always selected.
+    let wanted: Option<Vec<String>> = fields.map(|fields| {
+        split_list(fields)
+            .map(camel_case)
+            .filter(|field| field != "__typename")
+            .collect()
+    });
+    if let Some(wanted) = &wanted {
+        let available: BTreeSet<&str> = selected
+            .iter()
+            .flat_map(|ty| ty.fields.iter().map(String::as_str))
+            .collect();
+        for field in wanted {
+            if !available.contains(field.as_str()) {
+                bail!(
+                    "no selected event type has a field {field:?}; available: {}",
+                    available.into_iter().collect::<Vec<_>>().join(", ")
+                );
+            }
+        }
+    }
+
+    let mut fragments = String::new();
+    let mut tag_lists = false;
+    for ty in selected {
+        let picked: Vec<&str> = ty
+            .fields
+            .iter()
+            .filter(|field| wanted.as_ref().is_none_or(|wanted| wanted.contains(field)))
+            .map(String::as_str)
+            .collect();
+        tag_lists |= picked.contains(&"tagsList");
+        // An empty fragment still limits the subscription to this type.
+        let selection = if picked.is_empty() {
+            "__typename".to_string()
+        } else {
+            picked.join(" ")
+        };
+        fragments.push_str(&format!(" ... on {} {{ {selection} }}", ty.name));
+    }
+    let args = if tag_lists { "(tagList: true)" } else { "" };
+    Ok(format!(
+        "subscription {{ events{args} {{ __typename{fragments} }} }}"
+    ))
+}
+
+struct EventType {
+    name: String,
+    /// Fields whose value is a scalar or enum, or a list of them.
+    fields: Vec<String>,
+}
+
+impl EventType {
+    fn from_introspection(ty: &Value) -> Self {
+        let fields = ty["fields"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|field| {
+                let mut ty = &field["type"];
+                while matches!(ty["kind"].as_str(), Some("NON_NULL" | "LIST")) {
+                    ty = &ty["ofType"];
+                }
+                matches!(ty["kind"].as_str(), Some("SCALAR" | "ENUM"))
+            })
+            .filter_map(|field| field["name"].as_str().map(str::to_string))
+            .collect();
+        Self {
+            name: ty["name"].as_str().unwrap_or_default().to_string(),
+            fields,
+        }
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// `output_id` -> `outputId`; names already in camelCase are unchanged.
+fn camel_case(name: &str) -> String {
+    let rest = name.trim_start_matches('_');
+    let mut out = name[..name.len() - rest.len()].to_string();
+    let mut upper = false;
+    for c in rest.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
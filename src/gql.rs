@@ -1,14 +1,22 @@
+use arc_swap::ArcSwap;
 use async_graphql::futures_util::future::ready;
 use async_graphql::futures_util::{Stream, StreamExt, stream};
 use async_graphql::parser::types::{FragmentDefinition, Selection, SelectionSet};
 use async_graphql::{
-    Context, EmptyMutation, Enum, ID, Name, Object, Positioned, Schema, Subscription, Union,
+    Context, Enum, ID, InputObject, InputValueError, InputValueResult, Name, Object, Positioned,
+    Scalar, ScalarType, Schema, Subscription, Union, Value,
 };
-use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, RwLock};
-use tokio::sync::broadcast::Sender;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
 
+use crate::backend::{Command, StatusBackend};
+use crate::dispatch::Dispatcher;
+use crate::metrics::{Metrics, MetricsHandle};
 use crate::river;
 
 #[derive(Enum, Copy, Clone, Eq, PartialEq, Hash)]
@@ -19,10 +27,14 @@ pub enum RiverEventType {
     OutputLayoutName,
     OutputLayoutNameClear,
     OutputRemoved,
+    OutputNamed,
+    OutputLayoutDemand,
+    OutputPosition,
     SeatFocusedOutput,
     SeatUnfocusedOutput,
     SeatFocusedView,
     SeatMode,
+    Heartbeat,
 }
 
 impl From<&river::Event> for RiverEventType {
@@ -35,21 +47,121 @@ impl From<&river::Event> for RiverEventType {
             OutputLayoutName { .. } => RiverEventType::OutputLayoutName,
             OutputLayoutNameClear { .. } => RiverEventType::OutputLayoutNameClear,
             OutputRemoved { .. } => RiverEventType::OutputRemoved,
+            OutputNamed { .. } => RiverEventType::OutputNamed,
+            OutputLayoutDemand { .. } => RiverEventType::OutputLayoutDemand,
+            OutputPosition { .. } => RiverEventType::OutputPosition,
             SeatFocusedOutput { .. } => RiverEventType::SeatFocusedOutput,
             SeatUnfocusedOutput { .. } => RiverEventType::SeatUnfocusedOutput,
             SeatFocusedView { .. } => RiverEventType::SeatFocusedView,
             SeatMode { .. } => RiverEventType::SeatMode,
+            Heartbeat { .. } => RiverEventType::Heartbeat,
         }
     }
 }
 
+/// Accepts the event's type name (`SeatUnfocusedOutput`) as well as the enum
+/// value (`SEAT_UNFOCUSED_OUTPUT`).
+impl FromStr for RiverEventType {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let items = <Self as async_graphql::resolver_utils::EnumType>::items();
+        items
+            .iter()
+            .find(|item| item.name.replace('_', "").eq_ignore_ascii_case(value))
+            .map(|item| item.value)
+            .ok_or_else(|| anyhow::anyhow!("unknown event type {value:?}"))
+    }
+}
+
+/// River tag bitmask, kept unsigned so tag 32 doesn't turn negative.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct TagMask(pub u32);
+
+/// Unsigned 32-bit river tag bitmask (bit N set means tag N+1). Replaces the
+/// former `Int` masks, which were negative whenever tag 32 was set; all other
+/// values serialize identically.
+#[Scalar(name = "TagMask")]
+impl ScalarType for TagMask {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        if let Value::Number(n) = &value {
+            if let Some(mask) = n.as_u64().and_then(|v| u32::try_from(v).ok()) {
+                return Ok(TagMask(mask));
+            }
+        }
+        Err(InputValueError::expected_type(value))
+    }
+
+    fn to_value(&self) -> Value {
+        Value::Number(self.0.into())
+    }
+}
+
+/// Glyphs for `OutputState.tagIndicator`; `{}` in a glyph is replaced with the
+/// 1-based tag number. Urgent wins over focused, focused over occupied, and
+/// tags whose glyph renders empty are left out.
+#[derive(InputObject, Clone, Debug)]
+pub struct TagIndicatorFormat {
+    #[graphql(default = "[{}]")]
+    pub focused: String,
+    #[graphql(default = "{}")]
+    pub occupied: String,
+    #[graphql(default = "*{}")]
+    pub urgent: String,
+    #[graphql(default = "")]
+    pub empty: String,
+    #[graphql(default = " ")]
+    pub separator: String,
+    #[graphql(default = 9, validator(minimum = 1, maximum = 32))]
+    pub tag_count: u32,
+}
+
+impl Default for TagIndicatorFormat {
+    fn default() -> Self {
+        Self {
+            focused: "[{}]".to_string(),
+            occupied: "{}".to_string(),
+            urgent: "*{}".to_string(),
+            empty: String::new(),
+            separator: " ".to_string(),
+            tag_count: 9,
+        }
+    }
+}
+
+impl TagIndicatorFormat {
+    pub fn render(&self, focused: u32, occupied: u32, urgent: u32) -> String {
+        (0..self.tag_count.min(32))
+            .filter_map(|bit| {
+                let mask = 1u32 << bit;
+                let glyph = if urgent & mask != 0 {
+                    &self.urgent
+                } else if focused & mask != 0 {
+                    &self.focused
+                } else if occupied & mask != 0 {
+                    &self.occupied
+                } else {
+                    &self.empty
+                };
+                let glyph = glyph.replace("{}", &(bit + 1).to_string());
+                (!glyph.is_empty()).then_some(glyph)
+            })
+            .collect::<Vec<_>>()
+            .join(&self.separator)
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct RiverSnapshot {
     pub outputs: HashMap<String, OutputState>,
     output_names: HashMap<String, String>,
     pub seat_focused_output: Option<NamedOutputId>,
-    pub seat_focused_view: Option<String>,
+    pub seat_focused_view: Option<GSeatFocusedView>,
     pub seat_mode: Option<String>,
+    /// Seat that sent the last seat event.
+    pub seat: Option<String>,
+    /// Sequence number of the last change applied; see `ChangeLog`.
+    pub seq: u64,
 }
 
 #[derive(Clone)]
@@ -62,26 +174,116 @@ pub struct NamedOutputId {
 pub struct OutputState {
     pub output_id: ID,
     pub name: Option<String>,
-    pub focused_tags: Option<i32>,
+    pub focused_tags: Option<TagMask>,
     pub focused_tags_list: Option<Vec<i32>>,
-    pub view_tags: Option<Vec<i32>>,
+    /// Focused tags before the last change to them.
+    pub previous_focused_tags: Option<TagMask>,
+    pub previous_focused_tags_list: Option<Vec<i32>>,
+    pub view_tags: Option<Vec<TagMask>>,
     pub view_tags_list: Option<Vec<i32>>,
-    pub urgent_tags: Option<i32>,
+    pub urgent_tags: Option<TagMask>,
     pub urgent_tags_list: Option<Vec<i32>>,
     pub layout_name: Option<String>,
+    pub layout_demand: Option<LayoutDemand>,
+    pub position: Option<Position>,
 }
 
 #[derive(Clone)]
 pub struct GOutputState {
     pub output_id: ID,
     pub name: Option<String>,
-    pub focused_tags: Option<i32>,
+    pub focused_tags: Option<TagMask>,
     pub focused_tags_list: Option<Vec<i32>>,
-    pub view_tags: Option<Vec<i32>>,
+    /// Focused tags before the last change to them.
+    pub previous_focused_tags: Option<TagMask>,
+    pub previous_focused_tags_list: Option<Vec<i32>>,
+    pub view_tags: Option<Vec<TagMask>>,
     pub view_tags_list: Option<Vec<i32>>,
-    pub urgent_tags: Option<i32>,
+    pub urgent_tags: Option<TagMask>,
     pub urgent_tags_list: Option<Vec<i32>>,
     pub layout_name: Option<String>,
+    pub layout_demand: Option<LayoutDemand>,
+    pub position: Option<Position>,
+    /// Rank in `POSITION` order among the current outputs.
+    pub index: i32,
+}
+
+/// Sort order for `outputs`.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum OutputOrder {
+    /// Left to right, then top to bottom; outputs without a known position
+    /// come last, by name.
+    Position,
+    Name,
+}
+
+/// Placement of an output in the global compositor space, from xdg-output,
+/// with the scale from wl_output. With `--event-geometry`, output-scoped
+/// events carry it as `geometry`, as it stood when the event was sent, so a
+/// bar can place its per-output widgets without querying `outputs` first.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub scale: Option<i32>,
+}
+
+#[Object(name = "Position")]
+impl Position {
+    /// Left edge, in logical pixels.
+    async fn x(&self) -> i32 {
+        self.x
+    }
+
+    /// Top edge, in logical pixels.
+    async fn y(&self) -> i32 {
+        self.y
+    }
+
+    /// Logical width, i.e. after scaling; null until reported.
+    async fn width(&self) -> Option<i32> {
+        self.width
+    }
+
+    async fn height(&self) -> Option<i32> {
+        self.height
+    }
+
+    /// Integer buffer scale; null until reported.
+    async fn scale(&self) -> Option<i32> {
+        self.scale
+    }
+}
+
+/// Most recent layout demand river sent for an output.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LayoutDemand {
+    pub view_count: u32,
+    pub usable_width: u32,
+    pub usable_height: u32,
+    pub tags: TagMask,
+}
+
+#[Object(name = "LayoutDemand")]
+impl LayoutDemand {
+    /// Number of views river is laying out.
+    async fn view_count(&self) -> i32 {
+        self.view_count as i32
+    }
+
+    async fn usable_width(&self) -> i32 {
+        self.usable_width as i32
+    }
+
+    async fn usable_height(&self) -> i32 {
+        self.usable_height as i32
+    }
+
+    async fn tags(&self) -> TagMask {
+        self.tags
+    }
 }
 
 impl From<OutputState> for GOutputState {
@@ -97,11 +299,16 @@ impl From<&OutputState> for GOutputState {
             name: state.name.clone(),
             focused_tags: state.focused_tags,
             focused_tags_list: state.focused_tags_list.clone(),
+            previous_focused_tags: state.previous_focused_tags,
+            previous_focused_tags_list: state.previous_focused_tags_list.clone(),
             view_tags: state.view_tags.clone(),
             view_tags_list: state.view_tags_list.clone(),
             urgent_tags: state.urgent_tags,
             urgent_tags_list: state.urgent_tags_list.clone(),
             layout_name: state.layout_name.clone(),
+            layout_demand: state.layout_demand,
+            position: state.position,
+            index: 0,
         }
     }
 }
@@ -112,11 +319,17 @@ impl GOutputState {
         &self.output_id
     }
 
+    /// Derived from the output's name, so it survives compositor and server
+    /// restarts, unlike `outputId`. Null while the output is unnamed.
+    async fn stable_id(&self) -> Option<ID> {
+        self.name.as_deref().map(stable_output_id)
+    }
+
     async fn name(&self) -> Option<&str> {
         self.name.as_deref()
     }
 
-    async fn focused_tags(&self) -> Option<i32> {
+    async fn focused_tags(&self) -> Option<TagMask> {
         self.focused_tags
     }
 
@@ -124,7 +337,18 @@ impl GOutputState {
         self.focused_tags_list.as_ref()
     }
 
-    async fn view_tags(&self) -> Option<&Vec<i32>> {
+    /// The focused tags before they last changed, for "back to the previous
+    /// tags" bindings. Null until the output's focused tags have changed
+    /// while the server was running.
+    async fn previous_focused_tags(&self) -> Option<TagMask> {
+        self.previous_focused_tags
+    }
+
+    async fn previous_focused_tags_list(&self) -> Option<&Vec<i32>> {
+        self.previous_focused_tags_list.as_ref()
+    }
+
+    async fn view_tags(&self) -> Option<&Vec<TagMask>> {
         self.view_tags.as_ref()
     }
 
@@ -132,7 +356,7 @@ impl GOutputState {
         self.view_tags_list.as_ref()
     }
 
-    async fn urgent_tags(&self) -> Option<i32> {
+    async fn urgent_tags(&self) -> Option<TagMask> {
         self.urgent_tags
     }
 
@@ -143,15 +367,152 @@ impl GOutputState {
     async fn layout_name(&self) -> Option<&str> {
         self.layout_name.as_deref()
     }
+
+    /// Null unless the server runs with `--layout-namespace` and the output
+    /// uses that layout.
+    async fn layout_demand(&self) -> Option<LayoutDemand> {
+        self.layout_demand
+    }
+
+    /// Null until the compositor reports it over xdg-output.
+    async fn position(&self) -> Option<Position> {
+        self.position
+    }
+
+    /// Zero-based rank of this output in `POSITION` order, for mapping
+    /// "monitor 0/1/2" in bar configs. Changes only when outputs are added,
+    /// removed or rearranged.
+    async fn index(&self) -> i32 {
+        self.index
+    }
+
+    /// Compact dwm-style tag string such as `[1] 2 *3 4`, for bars that
+    /// render tags from a single field.
+    async fn tag_indicator(&self, format: Option<TagIndicatorFormat>) -> String {
+        format.unwrap_or_default().render(
+            self.focused_tags.unwrap_or_default().0,
+            self.occupied_tags(),
+            self.urgent_tags.unwrap_or_default().0,
+        )
+    }
+
+    /// The first `tagCount` tags with their state, ready for a bar to render.
+    /// `names[i]` labels tag `i + 1`. Null until river has reported any tags.
+    async fn tags_summary(
+        &self,
+        #[graphql(default = 9, validator(minimum = 1, maximum = 32))] tag_count: u32,
+        names: Option<Vec<String>>,
+    ) -> Option<Vec<TagInfo>> {
+        if self.focused_tags.is_none() && self.view_tags.is_none() && self.urgent_tags.is_none() {
+            return None;
+        }
+        let focused = self.focused_tags.unwrap_or_default().0;
+        let occupied = self.occupied_tags();
+        let urgent = self.urgent_tags.unwrap_or_default().0;
+        let names = names.unwrap_or_default();
+        let tags = (0..tag_count.min(32))
+            .map(|bit| {
+                let mask = 1u32 << bit;
+                TagInfo {
+                    number: bit + 1,
+                    mask: TagMask(mask),
+                    focused: focused & mask != 0,
+                    occupied: occupied & mask != 0,
+                    urgent: urgent & mask != 0,
+                    name: names.get(bit as usize).cloned(),
+                }
+            })
+            .collect();
+        Some(tags)
+    }
+}
+
+impl GOutputState {
+    /// Tags holding at least one view.
+    fn occupied_tags(&self) -> u32 {
+        self.view_tags
+            .iter()
+            .flatten()
+            .fold(0, |acc, tags| acc | tags.0)
+    }
+}
+
+/// Where one tag is in use, as returned by `outputsByTag`. Each list is in
+/// `POSITION` order.
+pub struct TagOutputs {
+    tag: u32,
+    focused: Vec<GOutputState>,
+    occupied: Vec<GOutputState>,
+    urgent: Vec<GOutputState>,
+}
+
+#[Object(name = "TagOutputs")]
+impl TagOutputs {
+    /// 1-based tag number, as passed to `outputsByTag`.
+    async fn tag(&self) -> i32 {
+        self.tag as i32
+    }
+
+    /// Outputs showing the tag.
+    async fn focused(&self) -> &Vec<GOutputState> {
+        &self.focused
+    }
+
+    /// Outputs with at least one view on the tag.
+    async fn occupied(&self) -> &Vec<GOutputState> {
+        &self.occupied
+    }
+
+    async fn urgent(&self) -> &Vec<GOutputState> {
+        &self.urgent
+    }
+}
+
+/// One tag of an output, as listed by `tagsSummary`.
+#[derive(Clone)]
+pub struct TagInfo {
+    number: u32,
+    mask: TagMask,
+    focused: bool,
+    occupied: bool,
+    urgent: bool,
+    name: Option<String>,
+}
+
+#[Object(name = "TagInfo")]
+impl TagInfo {
+    /// 1-based tag number, as shown by `tagIndicator`.
+    async fn number(&self) -> i32 {
+        self.number as i32
+    }
+
+    /// Bitmask selecting just this tag, e.g. for `riverctl set-focused-tags`.
+    async fn mask(&self) -> TagMask {
+        self.mask
+    }
+
+    async fn focused(&self) -> bool {
+        self.focused
+    }
+
+    /// At least one view has this tag.
+    async fn occupied(&self) -> bool {
+        self.occupied
+    }
+
+    async fn urgent(&self) -> bool {
+        self.urgent
+    }
+
+    /// Label passed in `tagsSummary(names:)`, if any.
+    async fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
 }
 
 impl RiverSnapshot {
-    fn update_output_state<F>(
-        &mut self,
-        object_id: &wayland_backend::client::ObjectId,
-        name: &Option<String>,
-        f: F,
-    ) where
+    fn update_output_state<F>(&mut self, object_id: &river::OutputId, name: &Option<String>, f: F)
+    where
         F: FnOnce(&mut OutputState),
     {
         let output_id = id_to_graphql(object_id);
@@ -165,11 +526,15 @@ impl RiverSnapshot {
                 name: name_clone.clone(),
                 focused_tags: None,
                 focused_tags_list: None,
+                previous_focused_tags: None,
+                previous_focused_tags_list: None,
                 view_tags: None,
                 view_tags_list: None,
                 urgent_tags: None,
                 urgent_tags_list: None,
                 layout_name: None,
+                layout_demand: None,
+                position: None,
             });
         entry.output_id = output_id;
         if let Some(name_value) = name_clone.take() {
@@ -184,19 +549,49 @@ impl RiverSnapshot {
         f(entry);
     }
 
+    /// Outputs left to right, then top to bottom. Outputs without a position
+    /// follow, by name, so the order is deterministic either way.
+    fn outputs_by_position(&self) -> Vec<&OutputState> {
+        let mut outputs = self.outputs.values().collect::<Vec<_>>();
+        outputs.sort_by(|a, b| {
+            let key = |o: &OutputState| match o.position {
+                Some(p) => (false, p.x, p.y),
+                None => (true, 0, 0),
+            };
+            key(a)
+                .cmp(&key(b))
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.output_id.cmp(&b.output_id))
+        });
+        outputs
+    }
+
+    fn output_index(&self, output_id: &ID) -> i32 {
+        self.outputs_by_position()
+            .iter()
+            .position(|o| &o.output_id == output_id)
+            .map_or(0, |index| index as i32)
+    }
+
     pub fn apply_event(&mut self, event: &river::Event) {
         use river::Event::*;
         match event {
             OutputFocusedTags { id, name, tags } => {
                 let list = bitmask_to_tags(*tags);
                 self.update_output_state(id, name, move |state| {
-                    state.focused_tags = Some(*tags as i32);
+                    let tags = Some(TagMask(*tags));
+                    // River repeats unchanged tags on every focus change.
+                    if state.focused_tags.is_some() && state.focused_tags != tags {
+                        state.previous_focused_tags = state.focused_tags;
+                        state.previous_focused_tags_list = state.focused_tags_list.take();
+                    }
+                    state.focused_tags = tags;
                     state.focused_tags_list = Some(list);
                 });
             }
             OutputViewTags { id, name, tags } => {
-                let converted = tags.iter().map(|v| *v as i32).collect::<Vec<i32>>();
-                let list = bit_values_to_tags(&converted);
+                let converted = tags.iter().copied().map(TagMask).collect::<Vec<_>>();
+                let list = bit_values_to_tags(tags);
                 self.update_output_state(id, name, move |state| {
                     state.view_tags = Some(converted.clone());
                     state.view_tags_list = Some(list.clone());
@@ -205,7 +600,7 @@ impl RiverSnapshot {
             OutputUrgentTags { id, name, tags } => {
                 let list = bitmask_to_tags(*tags);
                 self.update_output_state(id, name, move |state| {
-                    state.urgent_tags = Some(*tags as i32);
+                    state.urgent_tags = Some(TagMask(*tags));
                     state.urgent_tags_list = Some(list);
                 });
             }
@@ -224,6 +619,44 @@ impl RiverSnapshot {
                     state.layout_name = None;
                 });
             }
+            OutputLayoutDemand {
+                id,
+                name,
+                view_count,
+                usable_width,
+                usable_height,
+                tags,
+            } => {
+                let demand = LayoutDemand {
+                    view_count: *view_count,
+                    usable_width: *usable_width,
+                    usable_height: *usable_height,
+                    tags: TagMask(*tags),
+                };
+                self.update_output_state(id, name, move |state| {
+                    state.layout_demand = Some(demand);
+                });
+            }
+            OutputPosition {
+                id,
+                name,
+                x,
+                y,
+                width,
+                height,
+                scale,
+            } => {
+                let position = Position {
+                    x: *x,
+                    y: *y,
+                    width: *width,
+                    height: *height,
+                    scale: *scale,
+                };
+                self.update_output_state(id, name, move |state| {
+                    state.position = Some(position);
+                });
+            }
             OutputRemoved { id, name } => {
                 let gql_id = id_to_graphql(id);
                 let key = gql_id.to_string();
@@ -243,21 +676,44 @@ impl RiverSnapshot {
                     self.seat_focused_output = None;
                 }
             }
-            SeatFocusedOutput { id, name } => {
+            OutputNamed { id, name } => {
+                self.update_output_state(id, &Some(name.clone()), |_| {});
+                let gql_id = id_to_graphql(id);
+                if let Some(focused) = self.seat_focused_output.as_mut() {
+                    if focused.output_id == gql_id {
+                        focused.name = Some(name.clone());
+                    }
+                }
+            }
+            SeatFocusedOutput { id, name, seat } => {
                 self.seat_focused_output = Some(NamedOutputId {
                     output_id: id_to_graphql(id),
                     name: name.clone(),
                 });
+                self.seat = seat.clone();
             }
             SeatUnfocusedOutput { .. } => {
                 // ignore this. only store focused output in the snapshot
             }
-            SeatFocusedView { title } => {
-                self.seat_focused_view = Some(title.clone());
+            SeatFocusedView {
+                title,
+                app_id,
+                view_id,
+                seat,
+            } => {
+                self.seat_focused_view = Some(GSeatFocusedView {
+                    title: title.clone(),
+                    app_id: app_id.clone(),
+                    view_id: view_id.clone(),
+                    seat: seat.clone(),
+                });
+                self.seat = seat.clone();
             }
-            SeatMode { name } => {
+            SeatMode { name, seat } => {
                 self.seat_mode = Some(name.clone());
+                self.seat = seat.clone();
             }
+            Heartbeat { .. } => {}
         }
     }
 
@@ -278,11 +734,11 @@ impl RiverSnapshot {
         output_filter: Option<&str>,
     ) -> Vec<RiverEvent> {
         let mut events = Vec::new();
-        let type_allowed = |ty: RiverEventType| types.map_or(true, |set| set.contains(&ty));
+        let type_allowed = |ty: RiverEventType| types.is_none_or(|set| set.contains(&ty));
 
         for state in self.outputs.values() {
             let matches_output =
-                output_filter.map_or(true, |target| state.name.as_deref() == Some(target));
+                output_filter.is_none_or(|target| state.name.as_deref() == Some(target));
             if !matches_output {
                 continue;
             }
@@ -295,6 +751,7 @@ impl RiverSnapshot {
                         None
                     };
                     events.push(RiverEvent::OutputFocusedTags(GOutputFocusedTags {
+                        geometry: None,
                         output_id: state.output_id.clone(),
                         name: state.name.clone(),
                         tags,
@@ -311,6 +768,7 @@ impl RiverSnapshot {
                         None
                     };
                     events.push(RiverEvent::OutputViewTags(GOutputViewTags {
+                        geometry: None,
                         output_id: state.output_id.clone(),
                         name: state.name.clone(),
                         tags: tags.clone(),
@@ -327,6 +785,7 @@ impl RiverSnapshot {
                         None
                     };
                     events.push(RiverEvent::OutputUrgentTags(GOutputUrgentTags {
+                        geometry: None,
                         output_id: state.output_id.clone(),
                         name: state.name.clone(),
                         tags,
@@ -339,6 +798,7 @@ impl RiverSnapshot {
                 Some(layout) => {
                     if type_allowed(RiverEventType::OutputLayoutName) {
                         events.push(RiverEvent::OutputLayoutName(GOutputLayoutName {
+                            geometry: None,
                             output_id: state.output_id.clone(),
                             output_name: state.name.clone(),
                             layout: layout.clone(),
@@ -348,6 +808,7 @@ impl RiverSnapshot {
                 None => {
                     if type_allowed(RiverEventType::OutputLayoutNameClear) {
                         events.push(RiverEvent::OutputLayoutName(GOutputLayoutName {
+                            geometry: None,
                             output_id: state.output_id.clone(),
                             output_name: state.name.clone(),
                             layout: String::new(),
@@ -355,32 +816,56 @@ impl RiverSnapshot {
                     }
                 }
             }
+
+            if type_allowed(RiverEventType::OutputLayoutDemand) {
+                if let Some(demand) = state.layout_demand {
+                    events.push(RiverEvent::OutputLayoutDemand(GOutputLayoutDemand {
+                        geometry: None,
+                        output_id: state.output_id.clone(),
+                        name: state.name.clone(),
+                        demand,
+                    }));
+                }
+            }
+
+            if type_allowed(RiverEventType::OutputPosition) {
+                if let Some(position) = state.position {
+                    events.push(RiverEvent::OutputPosition(GOutputPosition {
+                        output_id: state.output_id.clone(),
+                        name: state.name.clone(),
+                        position,
+                    }));
+                }
+            }
         }
 
         if type_allowed(RiverEventType::SeatFocusedOutput) {
             if let Some(named) = &self.seat_focused_output {
                 let matches_output =
-                    output_filter.map_or(true, |target| named.name.as_deref() == Some(target));
+                    output_filter.is_none_or(|target| named.name.as_deref() == Some(target));
                 if matches_output {
                     events.push(RiverEvent::SeatFocusedOutput(GSeatFocusedOutput {
+                        geometry: None,
                         output_id: named.output_id.clone(),
                         name: named.name.clone(),
+                        seat: self.seat.clone(),
                     }));
                 }
             }
         }
 
         if type_allowed(RiverEventType::SeatFocusedView) {
-            if let Some(title) = &self.seat_focused_view {
-                events.push(RiverEvent::SeatFocusedView(GSeatFocusedView {
-                    title: title.clone(),
-                }));
+            if let Some(view) = &self.seat_focused_view {
+                events.push(RiverEvent::SeatFocusedView(view.clone()));
             }
         }
 
         if type_allowed(RiverEventType::SeatMode) {
             if let Some(name) = &self.seat_mode {
-                events.push(RiverEvent::SeatMode(GSeatMode { name: name.clone() }));
+                events.push(RiverEvent::SeatMode(GSeatMode {
+                    name: name.clone(),
+                    seat: self.seat.clone(),
+                }));
             }
         }
 
@@ -466,27 +951,165 @@ fn event_types_for_name(name: &str) -> Vec<RiverEventType> {
             RiverEventType::OutputLayoutNameClear,
         ],
         "OutputRemoved" => vec![RiverEventType::OutputRemoved],
+        "OutputNamed" => vec![RiverEventType::OutputNamed],
+        "OutputLayoutDemand" => vec![RiverEventType::OutputLayoutDemand],
+        "OutputPosition" => vec![RiverEventType::OutputPosition],
         "SeatFocusedOutput" => vec![RiverEventType::SeatFocusedOutput],
         "SeatUnfocusedOutput" => vec![RiverEventType::SeatUnfocusedOutput],
         "SeatFocusedView" => vec![RiverEventType::SeatFocusedView],
         "SeatMode" => vec![RiverEventType::SeatMode],
+        "Heartbeat" => vec![RiverEventType::Heartbeat],
         _ => Vec::new(),
     }
 }
 
-pub type RiverStateHandle = Arc<RwLock<RiverSnapshot>>;
+/// Shared snapshot. Resolvers load it lock-free; the single event task
+/// replaces it copy-on-write via `update_river_state`.
+pub type RiverStateHandle = Arc<ArcSwap<RiverSnapshot>>;
 
 pub fn new_river_state() -> RiverStateHandle {
-    Arc::new(RwLock::new(RiverSnapshot::default()))
+    Arc::new(ArcSwap::from_pointee(RiverSnapshot::default()))
+}
+
+/// Applies a change the change log recorded as `seq`; `apply` is the
+/// backend's reducer.
+pub fn update_river_state(
+    handle: &RiverStateHandle,
+    seq: u64,
+    apply: impl FnOnce(&mut RiverSnapshot),
+) {
+    let mut next = RiverSnapshot::clone(&handle.load());
+    apply(&mut next);
+    next.seq = seq;
+    handle.store(Arc::new(next));
+}
+
+/// How many applied events `changesSince` can replay.
+const CHANGE_LOG_CAPACITY: usize = 4096;
+
+/// The most recent events applied to a snapshot, numbered from 1. Events
+/// are recorded before the snapshot that contains them is stored, so a
+/// snapshot's `seq` is always covered by the log. Changes that aren't kept,
+/// such as suppressed events, hold their number without an event.
+#[derive(Default)]
+pub struct ChangeLog {
+    entries: Mutex<VecDeque<(u64, Option<river::Event>)>>,
+    next_seq: AtomicU64,
+}
+
+pub type ChangeLogHandle = Arc<ChangeLog>;
+
+impl ChangeLog {
+    /// Records `event` and returns its sequence number.
+    pub fn record(&self, event: &river::Event) -> u64 {
+        self.push(Some(event.clone()))
+    }
+
+    /// Numbers a change without keeping it; `changesSince` across it reports
+    /// incomplete.
+    pub fn record_unkept(&self) -> u64 {
+        self.push(None)
+    }
+
+    fn push(&self, event: Option<river::Event>) -> u64 {
+        let seq = self.next_seq.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == CHANGE_LOG_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back((seq, event));
+        seq
+    }
+
+    /// Changes after `since` up to and including `until`, or `None` when
+    /// some of them are no longer retained or were never kept.
+    fn between(&self, since: u64, until: u64) -> Option<Vec<(u64, river::Event)>> {
+        let entries = self.entries.lock().unwrap();
+        let oldest = entries.front().map_or(until + 1, |(seq, _)| *seq);
+        if since + 1 < oldest && since < until {
+            return None;
+        }
+        entries
+            .iter()
+            .filter(|(seq, _)| *seq > since && *seq <= until)
+            .map(|(seq, event)| Some((*seq, event.clone()?)))
+            .collect()
+    }
+}
+
+fn resolve_changes(
+    snapshot: &RiverSnapshot,
+    log: &ChangeLog,
+    since: i64,
+    tag_list: Option<bool>,
+) -> GChanges {
+    let include_lists = tag_list.unwrap_or(false);
+    let current = snapshot.seq;
+    // A negative or future `since` comes from another server run.
+    let patches = u64::try_from(since)
+        .ok()
+        .filter(|since| *since <= current)
+        .and_then(|since| log.between(since, current));
+    GChanges {
+        seq: current as i64,
+        complete: patches.is_some(),
+        patches: patches
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(seq, event)| GPatch {
+                seq: seq as i64,
+                event: make_river_event(event, include_lists),
+            })
+            .collect(),
+    }
+}
+
+/// Result of `changesSince`.
+#[derive(Clone)]
+pub struct GChanges {
+    seq: i64,
+    complete: bool,
+    patches: Vec<GPatch>,
+}
+
+#[Object(name = "Changes")]
+impl GChanges {
+    /// Sequence number of the snapshot; pass it to the next `changesSince`.
+    async fn seq(&self) -> i64 {
+        self.seq
+    }
+
+    /// False when `patches` can't bring the caller up to date, because the
+    /// changes were evicted, include a suppressed event, or `seq` came from
+    /// another server run; re-query the snapshot instead.
+    async fn complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Events applied since the requested `seq`, oldest first.
+    async fn patches(&self) -> &[GPatch] {
+        &self.patches
+    }
+}
+
+#[derive(Clone)]
+pub struct GPatch {
+    seq: i64,
+    event: RiverEvent,
 }
 
-pub fn update_river_state(handle: &RiverStateHandle, event: &river::Event) {
-    if let Ok(mut state) = handle.write() {
-        state.apply_event(event);
+#[Object(name = "Patch")]
+impl GPatch {
+    async fn seq(&self) -> i64 {
+        self.seq
+    }
+
+    async fn event(&self) -> &RiverEvent {
+        &self.event
     }
 }
 
-fn event_output_name<'a>(event: &'a river::Event) -> Option<&'a str> {
+pub fn event_output_name(event: &river::Event) -> Option<&str> {
     use river::Event::*;
 
     match event {
@@ -496,10 +1119,13 @@ fn event_output_name<'a>(event: &'a river::Event) -> Option<&'a str> {
         | OutputLayoutName { name, .. }
         | OutputLayoutNameClear { name, .. }
         | OutputRemoved { name, .. }
+        | OutputLayoutDemand { name, .. }
+        | OutputPosition { name, .. }
         | SeatFocusedOutput { name, .. }
         | SeatUnfocusedOutput { name, .. } => name.as_deref(),
+        OutputNamed { name, .. } => Some(name),
 
-        SeatFocusedView { .. } | SeatMode { .. } => unreachable!(),
+        SeatFocusedView { .. } | SeatMode { .. } | Heartbeat { .. } => unreachable!(),
     }
 }
 
@@ -507,8 +1133,8 @@ fn event_matches_output_name(event: &river::Event, target: &str) -> bool {
     use river::Event::*;
 
     match event {
-        // Seat events are always matched
-        SeatFocusedView { .. } | SeatMode { .. } => true,
+        // Seat events and heartbeats are always matched
+        SeatFocusedView { .. } | SeatMode { .. } | Heartbeat { .. } => true,
         _ => {
             if let Some(name) = event_output_name(event) {
                 name == target
@@ -520,27 +1146,14 @@ fn event_matches_output_name(event: &river::Event, target: &str) -> bool {
 }
 
 fn bitmask_to_tags(mask: u32) -> Vec<i32> {
-    (0..32)
-        .filter(|bit| (mask & (1 << bit)) != 0)
-        .map(|bit| bit as i32)
-        .collect()
+    (0..32).filter(|bit| (mask & (1 << bit)) != 0).collect()
 }
 
-fn bit_values_to_tags(values: &[i32]) -> Vec<i32> {
+fn bit_values_to_tags(values: &[u32]) -> Vec<i32> {
     values
         .iter()
-        .filter_map(|value| {
-            if *value <= 0 {
-                None
-            } else {
-                let v = *value as u32;
-                if v.is_power_of_two() {
-                    Some(v.trailing_zeros() as i32)
-                } else {
-                    None
-                }
-            }
-        })
+        .filter(|v| v.is_power_of_two())
+        .map(|v| v.trailing_zeros() as i32)
         .collect()
 }
 
@@ -551,22 +1164,88 @@ pub enum RiverEvent {
     OutputUrgentTags(GOutputUrgentTags),
     OutputLayoutName(GOutputLayoutName),
     OutputRemoved(GOutputRemoved),
+    OutputNamed(GOutputNamed),
+    OutputLayoutDemand(GOutputLayoutDemand),
+    OutputPosition(GOutputPosition),
     SeatFocusedOutput(GSeatFocusedOutput),
     SeatUnfocusedOutput(GSeatUnfocusedOutput),
     SeatFocusedView(GSeatFocusedView),
     SeatMode(GSeatMode),
+    SubscriptionLagged(GSubscriptionLagged),
+    Heartbeat(GHeartbeat),
+}
+
+impl RiverEvent {
+    fn output_name(&self) -> Option<&str> {
+        match self {
+            RiverEvent::OutputFocusedTags(e) => e.name.as_deref(),
+            RiverEvent::OutputViewTags(e) => e.name.as_deref(),
+            RiverEvent::OutputUrgentTags(e) => e.name.as_deref(),
+            RiverEvent::OutputLayoutName(e) => e.output_name.as_deref(),
+            RiverEvent::OutputRemoved(e) => e.name.as_deref(),
+            RiverEvent::OutputNamed(e) => Some(&e.name),
+            RiverEvent::OutputLayoutDemand(e) => e.name.as_deref(),
+            RiverEvent::OutputPosition(e) => e.name.as_deref(),
+            RiverEvent::SeatFocusedOutput(e) => e.name.as_deref(),
+            RiverEvent::SeatUnfocusedOutput(e) => e.name.as_deref(),
+            _ => None,
+        }
+    }
+
+    fn seat(&self) -> Option<&str> {
+        match self {
+            RiverEvent::SeatFocusedOutput(e) => e.seat.as_deref(),
+            RiverEvent::SeatUnfocusedOutput(e) => e.seat.as_deref(),
+            RiverEvent::SeatFocusedView(e) => e.seat.as_deref(),
+            RiverEvent::SeatMode(e) => e.seat.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Every tag the event mentions, or `None` if it carries no tags.
+    fn tags(&self) -> Option<u32> {
+        match self {
+            RiverEvent::OutputFocusedTags(e) => Some(e.tags.0),
+            RiverEvent::OutputViewTags(e) => Some(e.tags.iter().fold(0, |all, tags| all | tags.0)),
+            RiverEvent::OutputUrgentTags(e) => Some(e.tags.0),
+            RiverEvent::OutputLayoutDemand(e) => Some(e.demand.tags.0),
+            _ => None,
+        }
+    }
+
+    /// Fills `geometry` on output-scoped events from `snapshot`.
+    fn with_geometry(mut self, snapshot: &RiverSnapshot) -> Self {
+        let (output_id, geometry) = match &mut self {
+            RiverEvent::OutputFocusedTags(e) => (&e.output_id, &mut e.geometry),
+            RiverEvent::OutputViewTags(e) => (&e.output_id, &mut e.geometry),
+            RiverEvent::OutputUrgentTags(e) => (&e.output_id, &mut e.geometry),
+            RiverEvent::OutputLayoutName(e) => (&e.output_id, &mut e.geometry),
+            RiverEvent::OutputNamed(e) => (&e.output_id, &mut e.geometry),
+            RiverEvent::OutputLayoutDemand(e) => (&e.output_id, &mut e.geometry),
+            RiverEvent::SeatFocusedOutput(e) => (&e.output_id, &mut e.geometry),
+            RiverEvent::SeatUnfocusedOutput(e) => (&e.output_id, &mut e.geometry),
+            _ => return self,
+        };
+        *geometry = snapshot
+            .outputs
+            .get(output_id.as_str())
+            .and_then(|state| state.position);
+        self
+    }
 }
 
 #[derive(Clone)]
 pub struct GOutputFocusedTags {
     pub output_id: ID,
     pub name: Option<String>,
-    pub tags: i32,
+    pub tags: TagMask,
     pub tags_list: Option<Vec<i32>>,
+    /// Output placement, with `--event-geometry`.
+    pub geometry: Option<Position>,
 }
 #[Object(name = "OutputFocusedTags")]
 impl GOutputFocusedTags {
-    async fn tags(&self) -> i32 {
+    async fn tags(&self) -> TagMask {
         self.tags
     }
 
@@ -578,21 +1257,34 @@ impl GOutputFocusedTags {
         &self.output_id
     }
 
+    /// Name-based output id; see `OutputState.stableId`.
+    async fn stable_id(&self) -> Option<ID> {
+        self.name.as_deref().map(stable_output_id)
+    }
+
     async fn name(&self) -> Option<&str> {
         self.name.as_deref()
     }
+
+    /// Null without `--event-geometry`, or until xdg-output has reported the
+    /// output's position.
+    async fn geometry(&self) -> Option<Position> {
+        self.geometry
+    }
 }
 
 #[derive(Clone)]
 pub struct GOutputViewTags {
     pub output_id: ID,
     pub name: Option<String>,
-    pub tags: Vec<i32>,
+    pub tags: Vec<TagMask>,
     pub tags_list: Option<Vec<i32>>,
+    /// Output placement, with `--event-geometry`.
+    pub geometry: Option<Position>,
 }
 #[Object(name = "OutputViewTags")]
 impl GOutputViewTags {
-    async fn tags(&self) -> &Vec<i32> {
+    async fn tags(&self) -> &Vec<TagMask> {
         &self.tags
     }
 
@@ -604,21 +1296,34 @@ impl GOutputViewTags {
         &self.output_id
     }
 
+    /// Name-based output id; see `OutputState.stableId`.
+    async fn stable_id(&self) -> Option<ID> {
+        self.name.as_deref().map(stable_output_id)
+    }
+
     async fn name(&self) -> Option<&str> {
         self.name.as_deref()
     }
+
+    /// Null without `--event-geometry`, or until xdg-output has reported the
+    /// output's position.
+    async fn geometry(&self) -> Option<Position> {
+        self.geometry
+    }
 }
 
 #[derive(Clone)]
 pub struct GOutputUrgentTags {
     pub output_id: ID,
     pub name: Option<String>,
-    pub tags: i32,
+    pub tags: TagMask,
     pub tags_list: Option<Vec<i32>>,
+    /// Output placement, with `--event-geometry`.
+    pub geometry: Option<Position>,
 }
 #[Object(name = "OutputUrgentTags")]
 impl GOutputUrgentTags {
-    async fn tags(&self) -> i32 {
+    async fn tags(&self) -> TagMask {
         self.tags
     }
 
@@ -630,9 +1335,20 @@ impl GOutputUrgentTags {
         &self.output_id
     }
 
+    /// Name-based output id; see `OutputState.stableId`.
+    async fn stable_id(&self) -> Option<ID> {
+        self.name.as_deref().map(stable_output_id)
+    }
+
     async fn name(&self) -> Option<&str> {
         self.name.as_deref()
     }
+
+    /// Null without `--event-geometry`, or until xdg-output has reported the
+    /// output's position.
+    async fn geometry(&self) -> Option<Position> {
+        self.geometry
+    }
 }
 
 #[derive(Clone)]
@@ -640,6 +1356,8 @@ pub struct GOutputLayoutName {
     pub output_id: ID,
     pub output_name: Option<String>,
     pub layout: String,
+    /// Output placement, with `--event-geometry`.
+    pub geometry: Option<Position>,
 }
 #[Object(name = "OutputLayoutName")]
 impl GOutputLayoutName {
@@ -651,9 +1369,20 @@ impl GOutputLayoutName {
         &self.output_id
     }
 
+    /// Name-based output id; see `OutputState.stableId`.
+    async fn stable_id(&self) -> Option<ID> {
+        self.output_name.as_deref().map(stable_output_id)
+    }
+
     async fn output_name(&self) -> Option<&str> {
         self.output_name.as_deref()
     }
+
+    /// Null without `--event-geometry`, or until xdg-output has reported the
+    /// output's position.
+    async fn geometry(&self) -> Option<Position> {
+        self.geometry
+    }
 }
 
 #[derive(Clone)]
@@ -667,71 +1396,303 @@ impl GOutputRemoved {
         &self.output_id
     }
 
+    /// Name-based output id; see `OutputState.stableId`.
+    async fn stable_id(&self) -> Option<ID> {
+        self.name.as_deref().map(stable_output_id)
+    }
+
     async fn name(&self) -> Option<&str> {
         self.name.as_deref()
     }
 }
 
-// no-op clear event omitted in minimal schema
-
+/// Emitted once an output's name becomes known (or changes), so clients can
+/// re-key state that was recorded against its `outputId` with a null name.
 #[derive(Clone)]
-pub struct GSeatFocusedOutput {
+pub struct GOutputNamed {
     pub output_id: ID,
-    pub name: Option<String>,
+    pub name: String,
+    /// Output placement, with `--event-geometry`.
+    pub geometry: Option<Position>,
 }
-#[Object(name = "SeatFocusedOutput")]
-impl GSeatFocusedOutput {
+#[Object(name = "OutputNamed")]
+impl GOutputNamed {
     async fn output_id(&self) -> &ID {
         &self.output_id
     }
 
-    async fn name(&self) -> Option<&str> {
-        self.name.as_deref()
+    /// Name-based output id; see `OutputState.stableId`.
+    async fn stable_id(&self) -> ID {
+        stable_output_id(&self.name)
+    }
+
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Null without `--event-geometry`, or until xdg-output has reported the
+    /// output's position.
+    async fn geometry(&self) -> Option<Position> {
+        self.geometry
     }
 }
 
 #[derive(Clone)]
-pub struct GSeatUnfocusedOutput {
+pub struct GOutputLayoutDemand {
     pub output_id: ID,
     pub name: Option<String>,
+    pub demand: LayoutDemand,
+    /// Output placement, with `--event-geometry`.
+    pub geometry: Option<Position>,
 }
-#[Object(name = "SeatUnfocusedOutput")]
-impl GSeatUnfocusedOutput {
+#[Object(name = "OutputLayoutDemand")]
+impl GOutputLayoutDemand {
     async fn output_id(&self) -> &ID {
         &self.output_id
     }
 
+    /// Name-based output id; see `OutputState.stableId`.
+    async fn stable_id(&self) -> Option<ID> {
+        self.name.as_deref().map(stable_output_id)
+    }
+
     async fn name(&self) -> Option<&str> {
         self.name.as_deref()
     }
+
+    async fn view_count(&self) -> i32 {
+        self.demand.view_count as i32
+    }
+
+    async fn usable_width(&self) -> i32 {
+        self.demand.usable_width as i32
+    }
+
+    async fn usable_height(&self) -> i32 {
+        self.demand.usable_height as i32
+    }
+
+    async fn tags(&self) -> TagMask {
+        self.demand.tags
+    }
+
+    /// Null without `--event-geometry`, or until xdg-output has reported the
+    /// output's position.
+    async fn geometry(&self) -> Option<Position> {
+        self.geometry
+    }
 }
 
 #[derive(Clone)]
-pub struct GSeatFocusedView {
-    pub title: String,
+pub struct GOutputPosition {
+    pub output_id: ID,
+    pub name: Option<String>,
+    pub position: Position,
 }
-#[Object(name = "SeatFocusedView")]
+#[Object(name = "OutputPosition")]
+impl GOutputPosition {
+    async fn output_id(&self) -> &ID {
+        &self.output_id
+    }
+
+    /// Name-based output id; see `OutputState.stableId`.
+    async fn stable_id(&self) -> Option<ID> {
+        self.name.as_deref().map(stable_output_id)
+    }
+
+    async fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    async fn x(&self) -> i32 {
+        self.position.x
+    }
+
+    async fn y(&self) -> i32 {
+        self.position.y
+    }
+
+    async fn width(&self) -> Option<i32> {
+        self.position.width
+    }
+
+    async fn height(&self) -> Option<i32> {
+        self.position.height
+    }
+
+    async fn scale(&self) -> Option<i32> {
+        self.position.scale
+    }
+}
+
+// no-op clear event omitted in minimal schema
+
+#[derive(Clone)]
+pub struct GSeatFocusedOutput {
+    pub output_id: ID,
+    pub name: Option<String>,
+    pub seat: Option<String>,
+    /// Output placement, with `--event-geometry`.
+    pub geometry: Option<Position>,
+}
+#[Object(name = "SeatFocusedOutput")]
+impl GSeatFocusedOutput {
+    async fn output_id(&self) -> &ID {
+        &self.output_id
+    }
+
+    /// Name-based output id; see `OutputState.stableId`.
+    async fn stable_id(&self) -> Option<ID> {
+        self.name.as_deref().map(stable_output_id)
+    }
+
+    async fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// wl_seat name of the seat; null before wl_seat version 2.
+    async fn seat(&self) -> Option<&str> {
+        self.seat.as_deref()
+    }
+
+    /// Null without `--event-geometry`, or until xdg-output has reported the
+    /// output's position.
+    async fn geometry(&self) -> Option<Position> {
+        self.geometry
+    }
+}
+
+#[derive(Clone)]
+pub struct GSeatUnfocusedOutput {
+    pub output_id: ID,
+    pub name: Option<String>,
+    pub seat: Option<String>,
+    /// Output placement, with `--event-geometry`.
+    pub geometry: Option<Position>,
+}
+#[Object(name = "SeatUnfocusedOutput")]
+impl GSeatUnfocusedOutput {
+    async fn output_id(&self) -> &ID {
+        &self.output_id
+    }
+
+    /// Name-based output id; see `OutputState.stableId`.
+    async fn stable_id(&self) -> Option<ID> {
+        self.name.as_deref().map(stable_output_id)
+    }
+
+    async fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    async fn seat(&self) -> Option<&str> {
+        self.seat.as_deref()
+    }
+
+    /// Null without `--event-geometry`, or until xdg-output has reported the
+    /// output's position.
+    async fn geometry(&self) -> Option<Position> {
+        self.geometry
+    }
+}
+
+#[derive(Clone)]
+pub struct GSeatFocusedView {
+    pub title: String,
+    pub app_id: Option<String>,
+    pub view_id: Option<String>,
+    pub seat: Option<String>,
+}
+#[Object(name = "SeatFocusedView")]
 impl GSeatFocusedView {
     async fn title(&self) -> &str {
         &self.title
     }
+
+    /// App id of the matching foreign toplevel, when the compositor offers
+    /// `zwlr_foreign_toplevel_manager_v1`.
+    async fn app_id(&self) -> Option<&str> {
+        self.app_id.as_deref()
+    }
+
+    /// Identifies the focused view for as long as it stays open; null without
+    /// foreign-toplevel data.
+    async fn view_id(&self) -> Option<ID> {
+        self.view_id.clone().map(ID)
+    }
+
+    async fn seat(&self) -> Option<&str> {
+        self.seat.as_deref()
+    }
 }
 
 #[derive(Clone)]
 pub struct GSeatMode {
     pub name: String,
+    pub seat: Option<String>,
 }
 #[Object(name = "SeatMode")]
 impl GSeatMode {
     async fn name(&self) -> &str {
         &self.name
     }
+
+    async fn seat(&self) -> Option<&str> {
+        self.seat.as_deref()
+    }
+}
+
+/// Sent when a subscriber fell behind the broadcast buffer and `missed` events
+/// were dropped. Clients should re-query the snapshot to resynchronize.
+#[derive(Clone)]
+pub struct GSubscriptionLagged {
+    pub missed: i32,
 }
+#[Object(name = "SubscriptionLagged")]
+impl GSubscriptionLagged {
+    async fn missed(&self) -> i32 {
+        self.missed
+    }
+}
+
+/// Sent every `--heartbeat` seconds, so clients can tell a quiet compositor
+/// from a dead stream.
+#[derive(Clone)]
+pub struct GHeartbeat {
+    pub seq: u64,
+    pub at: u64,
+}
+#[Object(name = "Heartbeat")]
+impl GHeartbeat {
+    /// Counts up from 1 for each heartbeat since the server started.
+    async fn seq(&self) -> i64 {
+        self.seq as i64
+    }
 
-fn id_to_graphql(id: &wayland_backend::client::ObjectId) -> ID {
+    /// When the heartbeat was sent, in milliseconds since the Unix epoch.
+    async fn at(&self) -> i64 {
+        self.at as i64
+    }
+}
+
+fn lagged_event(err: BroadcastStreamRecvError, metrics: &Metrics) -> RiverEvent {
+    let BroadcastStreamRecvError::Lagged(missed) = err;
+    metrics.record_lagged(missed);
+    RiverEvent::SubscriptionLagged(GSubscriptionLagged {
+        missed: i32::try_from(missed).unwrap_or(i32::MAX),
+    })
+}
+
+fn id_to_graphql(id: &river::OutputId) -> ID {
     ID(id.to_string())
 }
 
+/// `stableId` for an output called `name`: its connector name, or its
+/// description or make and model when the compositor gives no name.
+fn stable_output_id(name: &str) -> ID {
+    ID(format!("output:{name}"))
+}
+
 fn make_river_event(value: river::Event, include_lists: bool) -> RiverEvent {
     use river::Event::*;
     match value {
@@ -740,9 +1701,10 @@ fn make_river_event(value: river::Event, include_lists: bool) -> RiverEvent {
             name,
             tags,
         } => RiverEvent::OutputFocusedTags(GOutputFocusedTags {
+            geometry: None,
             output_id: id_to_graphql(&output_id),
             name,
-            tags: tags as i32,
+            tags: TagMask(tags),
             tags_list: include_lists.then(|| bitmask_to_tags(tags)),
         }),
         OutputViewTags {
@@ -750,9 +1712,10 @@ fn make_river_event(value: river::Event, include_lists: bool) -> RiverEvent {
             name,
             tags,
         } => {
-            let tag_values = tags.into_iter().map(|v| v as i32).collect::<Vec<i32>>();
-            let tags_list = include_lists.then(|| bit_values_to_tags(&tag_values));
+            let tags_list = include_lists.then(|| bit_values_to_tags(&tags));
+            let tag_values = tags.into_iter().map(TagMask).collect::<Vec<_>>();
             RiverEvent::OutputViewTags(GOutputViewTags {
+                geometry: None,
                 output_id: id_to_graphql(&output_id),
                 name,
                 tags: tag_values,
@@ -764,9 +1727,10 @@ fn make_river_event(value: river::Event, include_lists: bool) -> RiverEvent {
             name,
             tags,
         } => RiverEvent::OutputUrgentTags(GOutputUrgentTags {
+            geometry: None,
             output_id: id_to_graphql(&output_id),
             name,
-            tags: tags as i32,
+            tags: TagMask(tags),
             tags_list: include_lists.then(|| bitmask_to_tags(tags)),
         }),
         OutputLayoutName {
@@ -774,6 +1738,7 @@ fn make_river_event(value: river::Event, include_lists: bool) -> RiverEvent {
             name,
             layout,
         } => RiverEvent::OutputLayoutName(GOutputLayoutName {
+            geometry: None,
             output_id: id_to_graphql(&output_id),
             output_name: name,
             layout,
@@ -782,6 +1747,7 @@ fn make_river_event(value: river::Event, include_lists: bool) -> RiverEvent {
             id: output_id,
             name,
         } => RiverEvent::OutputLayoutName(GOutputLayoutName {
+            geometry: None,
             output_id: id_to_graphql(&output_id),
             output_name: name,
             layout: String::new(),
@@ -793,22 +1759,84 @@ fn make_river_event(value: river::Event, include_lists: bool) -> RiverEvent {
             output_id: id_to_graphql(&output_id),
             name,
         }),
+        OutputNamed {
+            id: output_id,
+            name,
+        } => RiverEvent::OutputNamed(GOutputNamed {
+            geometry: None,
+            output_id: id_to_graphql(&output_id),
+            name,
+        }),
+        OutputLayoutDemand {
+            id: output_id,
+            name,
+            view_count,
+            usable_width,
+            usable_height,
+            tags,
+        } => RiverEvent::OutputLayoutDemand(GOutputLayoutDemand {
+            geometry: None,
+            output_id: id_to_graphql(&output_id),
+            name,
+            demand: LayoutDemand {
+                view_count,
+                usable_width,
+                usable_height,
+                tags: TagMask(tags),
+            },
+        }),
+        OutputPosition {
+            id: output_id,
+            name,
+            x,
+            y,
+            width,
+            height,
+            scale,
+        } => RiverEvent::OutputPosition(GOutputPosition {
+            output_id: id_to_graphql(&output_id),
+            name,
+            position: Position {
+                x,
+                y,
+                width,
+                height,
+                scale,
+            },
+        }),
         SeatFocusedOutput {
             id: output_id,
             name,
+            seat,
         } => RiverEvent::SeatFocusedOutput(GSeatFocusedOutput {
+            geometry: None,
             output_id: id_to_graphql(&output_id),
             name,
+            seat,
         }),
         SeatUnfocusedOutput {
             id: output_id,
             name,
+            seat,
         } => RiverEvent::SeatUnfocusedOutput(GSeatUnfocusedOutput {
+            geometry: None,
             output_id: id_to_graphql(&output_id),
             name,
+            seat,
+        }),
+        SeatFocusedView {
+            title,
+            app_id,
+            view_id,
+            seat,
+        } => RiverEvent::SeatFocusedView(GSeatFocusedView {
+            title,
+            app_id,
+            view_id,
+            seat,
         }),
-        SeatFocusedView { title } => RiverEvent::SeatFocusedView(GSeatFocusedView { title }),
-        SeatMode { name } => RiverEvent::SeatMode(GSeatMode { name }),
+        SeatMode { name, seat } => RiverEvent::SeatMode(GSeatMode { name, seat }),
+        Heartbeat { seq, at } => RiverEvent::Heartbeat(GHeartbeat { seq, at }),
     }
 }
 
@@ -818,6 +1846,236 @@ impl From<river::Event> for RiverEvent {
     }
 }
 
+/// How subscription events are built; set from the command line.
+#[derive(Clone, Default)]
+pub struct EventOptions {
+    /// Join the output's `Position` into output-scoped events.
+    pub geometry: bool,
+    /// Types never sent, not even in the initial snapshot events.
+    pub suppress: HashSet<RiverEventType>,
+}
+
+/// Where the bridge's events come from.
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Backend {
+    River,
+    Mock,
+    /// sway or i3, over its IPC socket.
+    Sway,
+    Hyprland,
+    Niri,
+}
+
+impl Backend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::River => "river",
+            Self::Mock => "mock",
+            Self::Sway => "sway",
+            Self::Hyprland => "hyprland",
+            Self::Niri => "niri",
+        }
+    }
+}
+
+/// Compositors `--backend` can connect to.
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "river" => Ok(Self::River),
+            "sway" => Ok(Self::Sway),
+            "hyprland" => Ok(Self::Hyprland),
+            "niri" => Ok(Self::Niri),
+            other => {
+                anyhow::bail!("unknown backend {other:?}; expected river, sway, hyprland or niri")
+            }
+        }
+    }
+}
+
+/// Facts about the running bridge, fixed once the event source is connected.
+#[derive(Clone)]
+pub struct ServerInfo {
+    pub backend: Backend,
+    pub protocols: river::Protocols,
+}
+
+#[Object(name = "ServerInfo")]
+impl ServerInfo {
+    async fn version(&self) -> &str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    /// `river`, `mock`, `sway`, `hyprland` or `niri`; see `source` for the
+    /// same as an enum.
+    async fn backend(&self) -> &str {
+        self.backend.name()
+    }
+
+    async fn source(&self) -> Backend {
+        self.backend
+    }
+
+    /// Globals the bridge bound, at the negotiated version.
+    async fn protocols(&self) -> Vec<GProtocolVersion> {
+        self.protocols
+            .globals
+            .iter()
+            .map(|global| GProtocolVersion {
+                interface: global.interface.clone(),
+                version: global.version as i32,
+            })
+            .collect()
+    }
+
+    /// Which river-status events the compositor can send; fields whose event
+    /// is unavailable stay null.
+    async fn capabilities(&self) -> GCapabilities {
+        // The IPC backends report app ids but lay out windows themselves.
+        // niri has no layout names or modes, which river-status v2 lacks too.
+        match self.backend {
+            Backend::Sway | Backend::Hyprland => {
+                return GCapabilities {
+                    status_version: 4,
+                    layout_version: None,
+                    toplevel_version: Some(1),
+                };
+            }
+            Backend::Niri => {
+                return GCapabilities {
+                    status_version: 2,
+                    layout_version: None,
+                    toplevel_version: Some(1),
+                };
+            }
+            Backend::River | Backend::Mock => {}
+        }
+        GCapabilities {
+            status_version: self.protocols.status_version(),
+            layout_version: self.protocols.version(river::Protocols::LAYOUT_MANAGER),
+            toplevel_version: self.protocols.version(river::Protocols::TOPLEVEL_MANAGER),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GProtocolVersion {
+    pub interface: String,
+    pub version: i32,
+}
+#[Object(name = "ProtocolVersion")]
+impl GProtocolVersion {
+    async fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    async fn version(&self) -> i32 {
+        self.version
+    }
+}
+
+/// The main display's Wayland registry, for `waylandGlobals`. Only in the
+/// schema when the server runs with `--debug`; `None` for backends that
+/// don't speak Wayland.
+#[derive(Clone)]
+pub struct DebugRegistry(pub Option<river::RegistryHandle>);
+
+pub struct GWaylandGlobals(Arc<river::RegistryView>);
+
+#[Object(name = "WaylandGlobals")]
+impl GWaylandGlobals {
+    /// Every global bound on the current connection, in bind order,
+    /// including each output and seat.
+    async fn globals(&self) -> Vec<GWaylandGlobal> {
+        self.0.globals.iter().cloned().map(GWaylandGlobal).collect()
+    }
+
+    /// Live river output status objects; river sends output events only
+    /// through these.
+    async fn output_statuses(&self) -> i32 {
+        self.0.output_statuses as i32
+    }
+
+    /// Live river seat status objects.
+    async fn seat_statuses(&self) -> i32 {
+        self.0.seat_statuses as i32
+    }
+}
+
+pub struct GWaylandGlobal(river::RegistryGlobal);
+
+#[Object(name = "WaylandGlobal")]
+impl GWaylandGlobal {
+    async fn interface(&self) -> &str {
+        &self.0.interface
+    }
+
+    /// The compositor's registry name for the global.
+    async fn name(&self) -> i64 {
+        self.0.name.into()
+    }
+
+    /// Negotiated version.
+    async fn version(&self) -> i32 {
+        self.0.version as i32
+    }
+
+    /// The bridge's object for it, e.g. `wl_output@12`.
+    async fn object_id(&self) -> &str {
+        &self.0.object_id
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct GCapabilities {
+    status_version: u32,
+    layout_version: Option<u32>,
+    toplevel_version: Option<u32>,
+}
+
+#[Object(name = "Capabilities")]
+impl GCapabilities {
+    async fn focused_tags(&self) -> bool {
+        self.status_version >= 1
+    }
+
+    async fn view_tags(&self) -> bool {
+        self.status_version >= 1
+    }
+
+    async fn urgent_tags(&self) -> bool {
+        self.status_version >= 2
+    }
+
+    async fn layout_name(&self) -> bool {
+        self.status_version >= 4
+    }
+
+    async fn seat_focused_output(&self) -> bool {
+        self.status_version >= 1
+    }
+
+    async fn seat_focused_view(&self) -> bool {
+        self.status_version >= 1
+    }
+
+    async fn seat_mode(&self) -> bool {
+        self.status_version >= 3
+    }
+
+    /// The bridge is registered as a layout generator (`--layout-namespace`).
+    async fn layout_demand(&self) -> bool {
+        self.layout_version.is_some()
+    }
+
+    /// `SeatFocusedView.appId` and `viewId` can be filled in.
+    async fn app_id(&self) -> bool {
+        self.toplevel_version.is_some()
+    }
+}
+
 pub struct QueryRoot;
 #[Object]
 impl QueryRoot {
@@ -825,26 +2083,32 @@ impl QueryRoot {
         "ok"
     }
 
-    async fn outputs(&self, ctx: &Context<'_>, tag_list: Option<bool>) -> Vec<GOutputState> {
-        let include_lists = tag_list.unwrap_or(false);
-        let handle = ctx.data_unchecked::<RiverStateHandle>();
-        let Ok(snapshot) = handle.read() else {
-            return Vec::new();
+    async fn server_info(&self, ctx: &Context<'_>) -> ServerInfo {
+        ctx.data_unchecked::<ServerInfo>().clone()
+    }
+
+    /// What the bridge bound from the Wayland registry, for finding out why
+    /// a compositor yields no events. Needs the server to run with `--debug`.
+    async fn wayland_globals(&self, ctx: &Context<'_>) -> async_graphql::Result<GWaylandGlobals> {
+        let Some(DebugRegistry(registry)) = ctx.data_opt::<DebugRegistry>() else {
+            return Err("waylandGlobals needs the server to run with --debug".into());
         };
-        snapshot
-            .outputs
-            .values()
-            .cloned()
-            .map(|state| {
-                let mut gql = GOutputState::from(state);
-                if !include_lists {
-                    gql.focused_tags_list = None;
-                    gql.view_tags_list = None;
-                    gql.urgent_tags_list = None;
-                }
-                gql
-            })
-            .collect::<Vec<_>>()
+        let Some(registry) = registry else {
+            let backend = ctx.data_unchecked::<ServerInfo>().backend;
+            return Err(format!("the {} backend has no Wayland registry", backend.name()).into());
+        };
+        Ok(GWaylandGlobals(registry.load_full()))
+    }
+
+    /// Order is unspecified unless `orderBy` is given.
+    async fn outputs(
+        &self,
+        ctx: &Context<'_>,
+        tag_list: Option<bool>,
+        order_by: Option<OutputOrder>,
+    ) -> Vec<GOutputState> {
+        let handle = ctx.data_unchecked::<RiverStateHandle>();
+        resolve_outputs(&handle.load(), tag_list, order_by)
     }
 
     async fn output(
@@ -853,94 +2117,379 @@ impl QueryRoot {
         name: String,
         tag_list: Option<bool>,
     ) -> Option<GOutputState> {
-        let include_lists = tag_list.unwrap_or(false);
         let handle = ctx.data_unchecked::<RiverStateHandle>();
-        let Ok(snapshot) = handle.read() else {
-            return None;
-        };
-        snapshot.output_by_name(&name).map(|state| {
+        resolve_output(&handle.load(), &name, tag_list)
+    }
+
+    /// Outputs that have 1-based `tag` focused, occupied or urgent.
+    async fn outputs_by_tag(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(validator(minimum = 1, maximum = 32))] tag: u32,
+        tag_list: Option<bool>,
+    ) -> TagOutputs {
+        let handle = ctx.data_unchecked::<RiverStateHandle>();
+        resolve_outputs_by_tag(&handle.load(), tag, tag_list)
+    }
+
+    async fn seat_focused_output(&self, ctx: &Context<'_>) -> Option<GSeatFocusedOutput> {
+        let handle = ctx.data_unchecked::<RiverStateHandle>();
+        resolve_seat_focused_output(&handle.load())
+    }
+
+    async fn seat_focused_view(&self, ctx: &Context<'_>) -> Option<GSeatFocusedView> {
+        let handle = ctx.data_unchecked::<RiverStateHandle>();
+        let snapshot = handle.load();
+        snapshot.seat_focused_view.clone()
+    }
+
+    async fn seat_mode(&self, ctx: &Context<'_>) -> Option<GSeatMode> {
+        let handle = ctx.data_unchecked::<RiverStateHandle>();
+        let snapshot = handle.load();
+        snapshot.seat_mode.clone().map(|name| GSeatMode {
+            name,
+            seat: snapshot.seat.clone(),
+        })
+    }
+
+    /// Events applied after `seq`, for catching up without re-reading the
+    /// whole snapshot. Start from the `seq` of any earlier result.
+    async fn changes_since(&self, ctx: &Context<'_>, seq: i64, tag_list: Option<bool>) -> GChanges {
+        let handle = ctx.data_unchecked::<RiverStateHandle>();
+        let log = ctx.data_unchecked::<ChangeLogHandle>();
+        resolve_changes(&handle.load(), log, seq, tag_list)
+    }
+
+    /// A compositor added with `--display`, by name.
+    async fn display(&self, ctx: &Context<'_>, name: String) -> Option<GDisplay> {
+        let displays = ctx.data_unchecked::<Displays>();
+        displays.get(&name).map(|source| GDisplay {
+            name,
+            source: source.clone(),
+        })
+    }
+
+    /// Compositors added with `--display`, sorted by name.
+    async fn displays(&self, ctx: &Context<'_>) -> Vec<GDisplay> {
+        let displays = ctx.data_unchecked::<Displays>();
+        displays
+            .iter()
+            .map(|(name, source)| GDisplay {
+                name: name.clone(),
+                source: source.clone(),
+            })
+            .collect()
+    }
+}
+
+fn resolve_outputs(
+    snapshot: &RiverSnapshot,
+    tag_list: Option<bool>,
+    order_by: Option<OutputOrder>,
+) -> Vec<GOutputState> {
+    let include_lists = tag_list.unwrap_or(false);
+    let mut outputs = snapshot
+        .outputs_by_position()
+        .into_iter()
+        .enumerate()
+        .map(|(index, state)| {
             let mut gql = GOutputState::from(state);
+            gql.index = index as i32;
             if !include_lists {
                 gql.focused_tags_list = None;
+                gql.previous_focused_tags_list = None;
                 gql.view_tags_list = None;
                 gql.urgent_tags_list = None;
             }
             gql
         })
+        .collect::<Vec<_>>();
+    match order_by {
+        Some(OutputOrder::Name) => outputs.sort_by(|a, b| a.name.cmp(&b.name)),
+        Some(OutputOrder::Position) | None => {}
     }
+    outputs
+}
 
-    async fn seat_focused_output(&self, ctx: &Context<'_>) -> Option<GSeatFocusedOutput> {
-        let handle = ctx.data_unchecked::<RiverStateHandle>();
-        let Ok(snapshot) = handle.read() else {
-            return None;
-        };
-        snapshot
-            .seat_focused_output
-            .clone()
-            .map(|named| GSeatFocusedOutput {
-                output_id: named.output_id,
-                name: named.name,
-            })
+fn resolve_output(
+    snapshot: &RiverSnapshot,
+    name: &str,
+    tag_list: Option<bool>,
+) -> Option<GOutputState> {
+    let include_lists = tag_list.unwrap_or(false);
+    snapshot.output_by_name(name).map(|state| {
+        let index = snapshot.output_index(&state.output_id);
+        let mut gql = GOutputState::from(state);
+        gql.index = index;
+        if !include_lists {
+            gql.focused_tags_list = None;
+            gql.previous_focused_tags_list = None;
+            gql.view_tags_list = None;
+            gql.urgent_tags_list = None;
+        }
+        gql
+    })
+}
+
+fn resolve_outputs_by_tag(
+    snapshot: &RiverSnapshot,
+    tag: u32,
+    tag_list: Option<bool>,
+) -> TagOutputs {
+    let mask = 1u32 << (tag - 1);
+    let outputs = resolve_outputs(snapshot, tag_list, None);
+    let with = |tags: fn(&GOutputState) -> u32| {
+        outputs
+            .iter()
+            .filter(|output| tags(output) & mask != 0)
+            .cloned()
+            .collect()
+    };
+    TagOutputs {
+        tag,
+        focused: with(|output| output.focused_tags.unwrap_or_default().0),
+        occupied: with(GOutputState::occupied_tags),
+        urgent: with(|output| output.urgent_tags.unwrap_or_default().0),
     }
+}
 
-    async fn seat_focused_view(&self, ctx: &Context<'_>) -> Option<GSeatFocusedView> {
-        let handle = ctx.data_unchecked::<RiverStateHandle>();
-        let Ok(snapshot) = handle.read() else {
-            return None;
-        };
-        snapshot
-            .seat_focused_view
-            .clone()
-            .map(|title| GSeatFocusedView { title })
+fn resolve_seat_focused_output(snapshot: &RiverSnapshot) -> Option<GSeatFocusedOutput> {
+    snapshot
+        .seat_focused_output
+        .clone()
+        .map(|named| GSeatFocusedOutput {
+            geometry: None,
+            output_id: named.output_id,
+            name: named.name,
+            seat: snapshot.seat.clone(),
+        })
+}
+
+/// State and events of one extra compositor connection.
+#[derive(Clone)]
+pub struct DisplaySource {
+    pub state: RiverStateHandle,
+    pub changes: ChangeLogHandle,
+    pub events: Dispatcher,
+    pub info: ServerInfo,
+    pub backend: Arc<dyn StatusBackend>,
+}
+
+/// Compositors added with `--display`, keyed by the name they're served under.
+pub type Displays = BTreeMap<String, DisplaySource>;
+
+/// The state of a compositor added with `--display`; the root fields describe
+/// the default one.
+#[derive(Clone)]
+pub struct GDisplay {
+    name: String,
+    source: DisplaySource,
+}
+
+#[Object(name = "Display")]
+impl GDisplay {
+    async fn name(&self) -> &str {
+        &self.name
     }
 
-    async fn seat_mode(&self, ctx: &Context<'_>) -> Option<GSeatMode> {
-        let handle = ctx.data_unchecked::<RiverStateHandle>();
-        let Ok(snapshot) = handle.read() else {
-            return None;
+    async fn server_info(&self) -> ServerInfo {
+        self.source.info.clone()
+    }
+
+    /// Order is unspecified unless `orderBy` is given.
+    async fn outputs(
+        &self,
+        tag_list: Option<bool>,
+        order_by: Option<OutputOrder>,
+    ) -> Vec<GOutputState> {
+        resolve_outputs(&self.source.state.load(), tag_list, order_by)
+    }
+
+    async fn output(&self, name: String, tag_list: Option<bool>) -> Option<GOutputState> {
+        resolve_output(&self.source.state.load(), &name, tag_list)
+    }
+
+    async fn outputs_by_tag(
+        &self,
+        #[graphql(validator(minimum = 1, maximum = 32))] tag: u32,
+        tag_list: Option<bool>,
+    ) -> TagOutputs {
+        resolve_outputs_by_tag(&self.source.state.load(), tag, tag_list)
+    }
+
+    async fn seat_focused_output(&self) -> Option<GSeatFocusedOutput> {
+        resolve_seat_focused_output(&self.source.state.load())
+    }
+
+    async fn seat_focused_view(&self) -> Option<GSeatFocusedView> {
+        self.source.state.load().seat_focused_view.clone()
+    }
+
+    async fn seat_mode(&self) -> Option<GSeatMode> {
+        let snapshot = self.source.state.load();
+        snapshot.seat_mode.clone().map(|name| GSeatMode {
+            name,
+            seat: snapshot.seat.clone(),
+        })
+    }
+
+    async fn changes_since(&self, seq: i64, tag_list: Option<bool>) -> GChanges {
+        resolve_changes(
+            &self.source.state.load(),
+            &self.source.changes,
+            seq,
+            tag_list,
+        )
+    }
+}
+
+/// The event channels and state for `display`, or the default compositor's.
+fn event_source(
+    ctx: &Context<'_>,
+    display: Option<&str>,
+) -> async_graphql::Result<(Dispatcher, RiverStateHandle)> {
+    let Some(name) = display else {
+        return Ok((
+            ctx.data_unchecked::<Dispatcher>().clone(),
+            ctx.data_unchecked::<RiverStateHandle>().clone(),
+        ));
+    };
+    let displays = ctx.data_unchecked::<Displays>();
+    let source = displays
+        .get(name)
+        .ok_or_else(|| format!("unknown display {name:?}"))?;
+    Ok((source.events.clone(), source.state.clone()))
+}
+
+fn event_geometry(ctx: &Context<'_>) -> bool {
+    ctx.data_opt::<EventOptions>()
+        .is_some_and(|options| options.geometry)
+}
+
+fn enrich(event: RiverEvent, snapshot: Option<&RiverSnapshot>) -> RiverEvent {
+    match snapshot {
+        Some(snapshot) => event.with_geometry(snapshot),
+        None => event,
+    }
+}
+
+/// Narrows `events` and `eventsForOutput` on the server. Set fields must all
+/// match, and an event without the property a field tests (a `SeatMode` under
+/// `outputNames`, an `OutputNamed` under `tagMask`) is dropped. `Heartbeat`
+/// and `SubscriptionLagged` are only subject to `types`.
+#[derive(InputObject, Clone, Default)]
+pub struct EventFilter {
+    /// Like the `types` argument; when both are given an event must be in
+    /// both.
+    pub types: Option<Vec<RiverEventType>>,
+    pub output_names: Option<Vec<String>>,
+    /// wl_seat names, e.g. `seat0`.
+    pub seat_names: Option<Vec<String>>,
+    /// Keep events whose tags intersect this mask. `OutputViewTags` matches
+    /// when any view does.
+    pub tag_mask: Option<TagMask>,
+}
+
+impl EventFilter {
+    fn matches(&self, event: &RiverEvent) -> bool {
+        if matches!(
+            event,
+            RiverEvent::Heartbeat(_) | RiverEvent::SubscriptionLagged(_)
+        ) {
+            return true;
+        }
+        let listed = |names: &Option<Vec<String>>, value: Option<&str>| {
+            names
+                .as_ref()
+                .is_none_or(|names| value.is_some_and(|value| names.iter().any(|n| n == value)))
         };
-        snapshot.seat_mode.clone().map(|name| GSeatMode { name })
+        listed(&self.output_names, event.output_name())
+            && listed(&self.seat_names, event.seat())
+            && self
+                .tag_mask
+                .is_none_or(|mask| event.tags().is_some_and(|tags| tags & mask.0 != 0))
+    }
+}
+
+/// The effective event type filter: `types` and `filter.types` intersected,
+/// falling back to the types the selection asks for with `... on`.
+fn subscribed_event_types(
+    ctx: &Context<'_>,
+    types: Option<Vec<RiverEventType>>,
+    filter: &EventFilter,
+) -> Option<HashSet<RiverEventType>> {
+    let types = types.map(|v| v.into_iter().collect::<HashSet<_>>());
+    let filtered = filter
+        .types
+        .clone()
+        .map(|v| v.into_iter().collect::<HashSet<_>>());
+    let wanted = match (types, filtered) {
+        (Some(types), Some(filtered)) => Some(&types & &filtered),
+        (types, filtered) => types.or(filtered).or_else(|| requested_event_types(ctx)),
+    };
+    match ctx.data_opt::<EventOptions>() {
+        Some(options) if !options.suppress.is_empty() => {
+            let all = <RiverEventType as async_graphql::resolver_utils::EnumType>::items()
+                .iter()
+                .map(|item| item.value)
+                .collect();
+            Some(&wanted.unwrap_or(all) - &options.suppress)
+        }
+        _ => wanted,
     }
 }
 
 pub struct SubscriptionRoot;
 #[Subscription]
 impl SubscriptionRoot {
+    /// `display` selects a compositor added with `--display`.
     async fn events(
         &self,
         ctx: &Context<'_>,
         types: Option<Vec<RiverEventType>>,
         tag_list: Option<bool>,
-    ) -> impl Stream<Item = RiverEvent> {
-        let sender = ctx.data_unchecked::<Sender<river::Event>>().clone();
-        let rx = sender.subscribe();
-        let include_lists = tag_list.unwrap_or(false);
-        let tset = types
-            .map(|v| v.into_iter().collect::<HashSet<_>>())
-            .or_else(|| requested_event_types(ctx));
-        let initial_events = {
-            let handle = ctx.data_unchecked::<RiverStateHandle>();
-            match handle.read() {
-                Ok(snapshot) => snapshot.snapshot_events(include_lists, tset.as_ref(), None),
-                Err(_) => Vec::new(),
-            }
+        display: Option<String>,
+        filter: Option<EventFilter>,
+    ) -> async_graphql::Result<impl Stream<Item = RiverEvent>> {
+        let (dispatcher, handle) = event_source(ctx, display.as_deref())?;
+        let filter = filter.unwrap_or_default();
+        // A single output name only needs that output's channel.
+        let rx = match filter.output_names.as_deref() {
+            Some([name]) => dispatcher.subscribe_output(name),
+            _ => dispatcher.subscribe(),
         };
+        let include_lists = tag_list.unwrap_or(false);
+        let tset = subscribed_event_types(ctx, types, &filter);
+        let geometry = event_geometry(ctx);
+        let snapshot = handle.load();
+        let initial_events = snapshot
+            .snapshot_events(include_lists, tset.as_ref(), None)
+            .into_iter()
+            .filter(|event| filter.matches(event))
+            .map(|event| enrich(event, geometry.then_some(&**snapshot)))
+            .collect::<Vec<_>>();
+        let metrics = ctx.data_unchecked::<MetricsHandle>().clone();
+        let active = metrics.track_subscription("events", tset.as_ref());
         let tset_for_updates = tset.clone();
         let updates = BroadcastStream::new(rx).filter_map(move |item| {
+            let _ = &active;
             let e = match item {
                 Ok(ev) => ev,
-                Err(_) => return ready(None),
+                Err(err) => return ready(Some(lagged_event(err, &metrics))),
             };
             let pass = tset_for_updates
                 .as_ref()
-                .map_or(true, |ts| ts.contains(&RiverEventType::from(&e)));
+                .is_none_or(|ts| ts.contains(&RiverEventType::from(&e)));
             if pass {
-                ready(Some(make_river_event(e, include_lists)))
-            } else {
-                ready(None)
+                let event = make_river_event(e, include_lists);
+                if filter.matches(&event) {
+                    let snapshot = geometry.then(|| handle.load_full());
+                    return ready(Some(enrich(event, snapshot.as_deref())));
+                }
             }
+            ready(None)
         });
-        stream::iter(initial_events.into_iter()).chain(updates)
+        Ok(stream::iter(initial_events).chain(updates))
     }
 
     async fn events_for_output(
@@ -949,43 +2498,168 @@ impl SubscriptionRoot {
         output_name: String,
         types: Option<Vec<RiverEventType>>,
         tag_list: Option<bool>,
-    ) -> impl Stream<Item = RiverEvent> {
-        let sender = ctx.data_unchecked::<Sender<river::Event>>().clone();
-        let rx = sender.subscribe();
+        display: Option<String>,
+        filter: Option<EventFilter>,
+    ) -> async_graphql::Result<impl Stream<Item = RiverEvent>> {
+        let (dispatcher, handle) = event_source(ctx, display.as_deref())?;
+        let rx = dispatcher.subscribe_output(&output_name);
         let include_lists = tag_list.unwrap_or(false);
-        let tset = types
-            .map(|v| v.into_iter().collect::<HashSet<_>>())
-            .or_else(|| requested_event_types(ctx));
+        let filter = filter.unwrap_or_default();
+        let tset = subscribed_event_types(ctx, types, &filter);
         let target_output = output_name;
-        let initial_events = {
-            let handle = ctx.data_unchecked::<RiverStateHandle>();
-            match handle.read() {
-                Ok(snapshot) => snapshot.snapshot_events(
-                    include_lists,
-                    tset.as_ref(),
-                    Some(target_output.as_str()),
-                ),
-                Err(_) => Vec::new(),
-            }
-        };
+        let geometry = event_geometry(ctx);
+        let snapshot = handle.load();
+        let initial_events = snapshot
+            .snapshot_events(include_lists, tset.as_ref(), Some(target_output.as_str()))
+            .into_iter()
+            .filter(|event| filter.matches(event))
+            .map(|event| enrich(event, geometry.then_some(&**snapshot)))
+            .collect::<Vec<_>>();
+        let metrics = ctx.data_unchecked::<MetricsHandle>().clone();
+        let active = metrics.track_subscription("eventsForOutput", tset.as_ref());
         let tset_for_updates = tset.clone();
         let updates = BroadcastStream::new(rx).filter_map(move |item| {
+            let _ = &active;
             let e = match item {
                 Ok(ev) => ev,
-                Err(_) => return ready(None),
+                Err(err) => return ready(Some(lagged_event(err, &metrics))),
             };
             let type_pass = tset_for_updates
                 .as_ref()
-                .map_or(true, |ts| ts.contains(&RiverEventType::from(&e)));
+                .is_none_or(|ts| ts.contains(&RiverEventType::from(&e)));
             let output_pass = event_matches_output_name(&e, &target_output);
             if type_pass && output_pass {
-                ready(Some(make_river_event(e, include_lists)))
-            } else {
-                ready(None)
+                let event = make_river_event(e, include_lists);
+                if filter.matches(&event) {
+                    let snapshot = geometry.then(|| handle.load_full());
+                    return ready(Some(enrich(event, snapshot.as_deref())));
+                }
             }
+            ready(None)
+        });
+        Ok(stream::iter(initial_events).chain(updates))
+    }
+
+    /// The focused view each time it changes, starting with the current one.
+    /// With `matching`, only views whose title matches that regex are sent.
+    async fn focused_view_changed(
+        &self,
+        ctx: &Context<'_>,
+        matching: Option<String>,
+        display: Option<String>,
+    ) -> async_graphql::Result<impl Stream<Item = GSeatFocusedView>> {
+        let pattern = matching
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| format!("invalid matching pattern: {e}"))?;
+        let (dispatcher, handle) = event_source(ctx, display.as_deref())?;
+        let rx = dispatcher.subscribe_seat();
+        let title_matches = move |view: &GSeatFocusedView| {
+            pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(&view.title))
+        };
+        let initial = handle
+            .load()
+            .seat_focused_view
+            .clone()
+            .filter(&title_matches);
+        let metrics = ctx.data_unchecked::<MetricsHandle>().clone();
+        let types = HashSet::from([RiverEventType::SeatFocusedView]);
+        let active = metrics.track_subscription("focusedViewChanged", Some(&types));
+        let updates = BroadcastStream::new(rx).filter_map(move |item| {
+            let _ = &active;
+            let view = match item {
+                Ok(river::Event::SeatFocusedView {
+                    title,
+                    app_id,
+                    view_id,
+                    seat,
+                }) => GSeatFocusedView {
+                    title,
+                    app_id,
+                    view_id,
+                    seat,
+                },
+                Ok(_) => return ready(None),
+                Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                    metrics.record_lagged(missed);
+                    return ready(None);
+                }
+            };
+            ready(title_matches(&view).then_some(view))
         });
-        stream::iter(initial_events.into_iter()).chain(updates)
+        Ok(stream::iter(initial).chain(updates))
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Shows the previous focused tags again on `output`, or the focused
+    /// output, and returns them. The server reads the tags from its snapshot
+    /// and sends the switch as one batch that leaves the seat's focus where
+    /// it was, so a binding needs no state of its own. Fails while the output
+    /// has no previous tags.
+    async fn focus_previous_tags(
+        &self,
+        ctx: &Context<'_>,
+        output: Option<String>,
+        display: Option<String>,
+    ) -> async_graphql::Result<TagMask> {
+        let (backend, state) = command_target(ctx, display.as_deref())?;
+        let snapshot = state.load();
+        let focused = snapshot
+            .seat_focused_output
+            .as_ref()
+            .and_then(|focused| focused.name.clone());
+        let name = match output {
+            Some(name) => name,
+            None => focused.clone().ok_or("no output is focused")?,
+        };
+        let tags = snapshot
+            .output_by_name(&name)
+            .ok_or_else(|| format!("unknown output {name:?}"))?
+            .previous_focused_tags
+            .ok_or_else(|| format!("output {name:?} has no previous focused tags yet"))?;
+        backend
+            .execute(&if focused.as_deref() == Some(name.as_str()) {
+                Command::SetFocusedTags {
+                    tags: tags.0,
+                    output: None,
+                    focused: None,
+                }
+            } else {
+                Command::SetFocusedTags {
+                    tags: tags.0,
+                    output: Some(name),
+                    focused,
+                }
+            })
+            .await
+            .map_err(|e| format!("{e:#}"))?;
+        Ok(tags)
     }
 }
 
-pub type AppSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+/// The backend and state of `display`, or of the default compositor.
+fn command_target(
+    ctx: &Context<'_>,
+    display: Option<&str>,
+) -> async_graphql::Result<(Arc<dyn StatusBackend>, RiverStateHandle)> {
+    let Some(name) = display else {
+        return Ok((
+            ctx.data_unchecked::<Arc<dyn StatusBackend>>().clone(),
+            ctx.data_unchecked::<RiverStateHandle>().clone(),
+        ));
+    };
+    let source = ctx
+        .data_unchecked::<Displays>()
+        .get(name)
+        .ok_or_else(|| format!("unknown display {name:?}"))?;
+    Ok((source.backend.clone(), source.state.clone()))
+}
+
+pub type AppSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
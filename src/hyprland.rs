@@ -0,0 +1,314 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Context, Result, bail};
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tracing::{debug, info, warn};
+
+use crate::backend::{Command, StatusBackend};
+use crate::gql;
+use crate::river::{BoundGlobal, EVENT_QUEUE_CAPACITY, Event, Protocols, Received};
+use crate::snapshot::{FocusedView, OutputStatus, Rect, Reported, Snapshot, workspace_tag};
+
+const REQUEST_SOCKET: &str = ".socket.sock";
+const EVENT_SOCKET: &str = ".socket2.sock";
+/// Name reported as the seat mode outside of any submap.
+const DEFAULT_SUBMAP: &str = "default";
+
+/// Hyprland over its IPC sockets.
+pub struct HyprlandBackend {
+    /// Directory holding the instance's sockets.
+    dir: PathBuf,
+}
+
+impl HyprlandBackend {
+    /// Uses the sockets of the Hyprland instance in the environment.
+    pub fn from_env() -> Result<Self> {
+        let signature = env::var_os("HYPRLAND_INSTANCE_SIGNATURE")
+            .context("HYPRLAND_INSTANCE_SIGNATURE is not set; is Hyprland running?")?;
+        // Hyprland 0.40 moved its sockets from /tmp to the runtime directory.
+        let runtime = env::var_os("XDG_RUNTIME_DIR")
+            .map(|dir| PathBuf::from(dir).join("hypr").join(&signature))
+            .filter(|dir| dir.exists());
+        let dir = runtime.unwrap_or_else(|| Path::new("/tmp/hypr").join(&signature));
+        Ok(Self { dir })
+    }
+}
+
+impl StatusBackend for HyprlandBackend {
+    fn kind(&self) -> gql::Backend {
+        gql::Backend::Hyprland
+    }
+
+    fn connect(&self) -> BoxFuture<'_, Result<(Receiver<Received>, Protocols)>> {
+        Box::pin(async move {
+            info!(sockets = %self.dir.display(), "connecting to Hyprland IPC");
+            Ok((subscribe(&self.dir).await?, protocols()))
+        })
+    }
+
+    /// Switches to the workspace of a single tag, focusing its monitor first
+    /// in the same batch.
+    fn execute(&self, command: &Command) -> BoxFuture<'_, Result<()>> {
+        let command = command.clone();
+        Box::pin(async move {
+            let Command::SetFocusedTags {
+                tags,
+                output,
+                focused,
+            } = command;
+            if tags.count_ones() != 1 {
+                bail!("Hyprland shows one workspace per monitor; cannot focus tags {tags:#b}");
+            }
+            let workspace = tags.trailing_zeros() + 1;
+            let mut dispatches = Vec::new();
+            if let Some(monitor) = output {
+                dispatches.push(format!("dispatch focusmonitor {monitor}"));
+            }
+            dispatches.push(format!("dispatch workspace {workspace}"));
+            if let Some(monitor) = focused {
+                dispatches.push(format!("dispatch focusmonitor {monitor}"));
+            }
+            let request = match dispatches.as_slice() {
+                [single] => single.clone(),
+                _ => format!("[[BATCH]]{}", dispatches.join(";")),
+            };
+            let reply = send(&self.dir, &request).await?;
+            let reply = String::from_utf8_lossy(&reply);
+            // A batch answers each dispatch on its own line.
+            if reply.split_whitespace().any(|line| line != "ok") {
+                bail!("Hyprland refused the dispatch: {reply}");
+            }
+            Ok(())
+        })
+    }
+}
+
+/// What `serverInfo.protocols` lists for this backend.
+fn protocols() -> Protocols {
+    Protocols {
+        globals: vec![BoundGlobal {
+            interface: "hyprland-ipc".to_string(),
+            version: 1,
+        }],
+    }
+}
+
+/// Listens on the event socket in `dir` and reports Hyprland's state as
+/// river events: workspace N is tag N (special and named workspaces have no
+/// tag), each mapped window is a view on its monitor, the active workspace is
+/// the monitor's focused tag and the submap is the seat mode. The layout name
+/// is `general:layout`, the same on every monitor.
+async fn subscribe(dir: &Path) -> Result<Receiver<Received>> {
+    let socket = dir.join(EVENT_SOCKET);
+    let stream = UnixStream::connect(&socket)
+        .await
+        .with_context(|| format!("connecting to Hyprland events at {}", socket.display()))?;
+    let lines = BufReader::new(stream).lines();
+    let urgent = HashSet::new();
+    let mut state = Reported::new("hyprland_output");
+    // There is no query for the current submap; a new connection assumes the
+    // default one.
+    let mut initial = state.set_mode(DEFAULT_SUBMAP.to_string());
+    initial.extend(state.update(fetch(dir, &urgent).await?));
+
+    let (tx, rx) = mpsc::channel(EVENT_QUEUE_CAPACITY);
+    let dir = dir.to_path_buf();
+    tokio::spawn(async move {
+        if let Err(e) = forward(&dir, lines, state, urgent, initial, tx).await {
+            warn!("Hyprland IPC connection ended: {e:#}");
+        }
+    });
+    Ok(rx)
+}
+
+/// Re-reads Hyprland's state after every event line and forwards what
+/// changed, until either side goes away.
+async fn forward(
+    dir: &Path,
+    mut lines: Lines<BufReader<UnixStream>>,
+    mut state: Reported,
+    mut urgent: HashSet<String>,
+    mut pending: Vec<Event>,
+    tx: Sender<Received>,
+) -> Result<()> {
+    // Changes are timed from the event that revealed them.
+    let mut received = Instant::now();
+    loop {
+        for event in pending.drain(..) {
+            if tx
+                .send(Received {
+                    event,
+                    at: received,
+                    dropped: 0,
+                })
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+        let Some(line) = lines.next_line().await? else {
+            bail!("Hyprland closed the event socket");
+        };
+        received = Instant::now();
+        let (name, data) = line.split_once(">>").unwrap_or((&line, ""));
+        debug!(event = name, "Hyprland event");
+        pending = match name {
+            "submap" if data.is_empty() => state.set_mode(DEFAULT_SUBMAP.to_string()),
+            "submap" => state.set_mode(data.to_string()),
+            _ => {
+                // Urgency is only announced as an event; it lasts until the
+                // window is focused or closed.
+                match name {
+                    "urgent" => {
+                        urgent.insert(data.to_string());
+                    }
+                    "activewindowv2" | "closewindow" => {
+                        urgent.remove(data);
+                    }
+                    _ => {}
+                }
+                state.update(fetch(dir, &urgent).await?)
+            }
+        };
+    }
+}
+
+/// Sends `message` on the request socket and returns the reply.
+async fn send(dir: &Path, message: &str) -> Result<Vec<u8>> {
+    let socket = dir.join(REQUEST_SOCKET);
+    let mut stream = UnixStream::connect(&socket)
+        .await
+        .with_context(|| format!("connecting to Hyprland at {}", socket.display()))?;
+    stream.write_all(message.as_bytes()).await?;
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+    Ok(reply)
+}
+
+/// Runs `command` on the request socket, asking for JSON.
+async fn request<T: DeserializeOwned>(dir: &Path, command: &str) -> Result<T> {
+    let reply = send(dir, &format!("j/{command}")).await?;
+    serde_json::from_slice(&reply).with_context(|| format!("parsing Hyprland's reply to {command}"))
+}
+
+/// Reads the monitors, windows and layout; windows whose address is in
+/// `urgent` mark their workspace's tag urgent.
+async fn fetch(dir: &Path, urgent: &HashSet<String>) -> Result<Snapshot> {
+    let monitors: Vec<Monitor> = request(dir, "monitors").await?;
+    let clients: Vec<Client> = request(dir, "clients").await?;
+    let active: ActiveWindow = request(dir, "activewindow").await?;
+    let layout: LayoutOption = request(dir, "getoption general:layout").await?;
+    Ok(snapshot(monitors, &clients, active, layout.value, urgent))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Monitor {
+    id: i64,
+    name: String,
+    x: i32,
+    y: i32,
+    /// In pixels, before scaling.
+    width: i32,
+    height: i32,
+    scale: f64,
+    active_workspace: WorkspaceRef,
+    focused: bool,
+    #[serde(default)]
+    disabled: bool,
+}
+
+#[derive(Deserialize)]
+struct WorkspaceRef {
+    id: i32,
+}
+
+#[derive(Deserialize)]
+struct Client {
+    address: String,
+    mapped: bool,
+    workspace: WorkspaceRef,
+    monitor: i64,
+}
+
+/// `activewindow` answers `{}` when nothing is focused.
+#[derive(Deserialize)]
+struct ActiveWindow {
+    address: Option<String>,
+    class: Option<String>,
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LayoutOption {
+    #[serde(rename = "str")]
+    value: Option<String>,
+}
+
+fn snapshot(
+    monitors: Vec<Monitor>,
+    clients: &[Client],
+    active: ActiveWindow,
+    layout: Option<String>,
+    urgent: &HashSet<String>,
+) -> Snapshot {
+    let names: HashMap<i64, String> = monitors
+        .iter()
+        .map(|monitor| (monitor.id, monitor.name.clone()))
+        .collect();
+    let mut outputs = BTreeMap::new();
+    let mut focused_output = None;
+    for monitor in monitors.into_iter().filter(|monitor| !monitor.disabled) {
+        if monitor.focused {
+            focused_output = Some(monitor.name.clone());
+        }
+        let logical = |pixels: i32| (f64::from(pixels) / monitor.scale).round() as i32;
+        let status = OutputStatus {
+            rect: Rect {
+                x: monitor.x,
+                y: monitor.y,
+                width: logical(monitor.width),
+                height: logical(monitor.height),
+            },
+            // Like wl_output, round fractional scales up.
+            scale: Some(monitor.scale.ceil() as i32),
+            focused_tags: workspace_tag(monitor.active_workspace.id),
+            layout: layout.clone(),
+            ..OutputStatus::default()
+        };
+        outputs.insert(monitor.name, status);
+    }
+    for client in clients.iter().filter(|client| client.mapped) {
+        let Some(status) = names
+            .get(&client.monitor)
+            .and_then(|name| outputs.get_mut(name))
+        else {
+            continue;
+        };
+        let tag = workspace_tag(client.workspace.id);
+        status.view_tags.push(tag);
+        // Events name windows without the `0x` that queries put in front.
+        if urgent.contains(client.address.trim_start_matches("0x")) {
+            status.urgent_tags |= tag;
+        }
+    }
+    let focused_view = active.address.map(|address| FocusedView {
+        title: active.title.unwrap_or_default(),
+        app_id: active.class,
+        view_id: address,
+    });
+    Snapshot {
+        outputs,
+        focused_output,
+        focused_view,
+    }
+}
@@ -0,0 +1,75 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use serde_json::{Map, Value};
+
+/// An operation loaded from the query library.
+pub struct NamedQuery {
+    pub query: String,
+    pub variables: Map<String, Value>,
+}
+
+/// `$XDG_CONFIG_HOME`, falling back to `~/.config`.
+pub fn config_home() -> Result<PathBuf> {
+    Ok(
+        match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => match env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".config"),
+                None => bail!("neither XDG_CONFIG_HOME nor HOME is set"),
+            },
+        },
+    )
+}
+
+/// `$XDG_CONFIG_HOME/riverql/queries`.
+pub fn queries_dir() -> Result<PathBuf> {
+    Ok(config_home()?.join("riverql").join("queries"))
+}
+
+/// Loads `<name>.graphql` from the library, with default variables from a
+/// sibling `<name>.json` holding an object, if one exists.
+pub fn load(name: &str) -> Result<NamedQuery> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        bail!("invalid query name {name:?}; use the file name without .graphql");
+    }
+    let dir = queries_dir()?;
+    let path = dir.join(format!("{name}.graphql"));
+    let query = match fs::read_to_string(&path) {
+        Ok(query) => query,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            bail!("no query named {name:?} in {}", dir.display())
+        }
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+
+    let vars_path = dir.join(format!("{name}.json"));
+    let variables = match fs::read_to_string(&vars_path) {
+        Ok(text) => match serde_json::from_str(&text)
+            .with_context(|| format!("parsing {}", vars_path.display()))?
+        {
+            Value::Object(map) => map,
+            _ => bail!("{} must contain a JSON object", vars_path.display()),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Map::new(),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", vars_path.display())),
+    };
+
+    Ok(NamedQuery { query, variables })
+}
+
+/// Parses a `name=value` override. The value is read as JSON when it parses,
+/// so `tagList=true` is a boolean, and as a plain string otherwise.
+pub fn parse_variable(arg: &str) -> Result<(String, Value)> {
+    let Some((name, value)) = arg.split_once('=') else {
+        bail!("--var expects name=value, got {arg:?}");
+    };
+    if name.is_empty() {
+        bail!("--var name cannot be empty in {arg:?}");
+    }
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+    Ok((name.to_string(), value))
+}
@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use async_graphql::futures_util::stream::{self, BoxStream};
+use async_graphql::futures_util::{StreamExt, future::ready};
+use async_graphql::parser::parse_query;
+use async_graphql::parser::types::{DocumentOperations, ExecutableDocument, OperationType};
+use async_graphql::{Data, Executor, Request, Response, ServerError, Value, Variables};
+use tokio::sync::broadcast::{self, Receiver, Sender, error::TryRecvError};
+
+use crate::gql::AppSchema;
+use crate::river;
+
+const LIVE_DIRECTIVE: &str = "live";
+
+/// Removes `@live` from the operation `request` will run, returning whether it
+/// was present. The directive isn't part of the schema, so the stripped
+/// document is attached to the request for execution.
+pub fn take_live_directive(request: &mut Request) -> Result<bool, ServerError> {
+    // Let the schema report syntax errors the usual way.
+    let Ok(mut doc) = parse_query(&request.query) else {
+        return Ok(false);
+    };
+    let operation = match &mut doc.operations {
+        DocumentOperations::Single(op) => Some(op),
+        DocumentOperations::Multiple(ops) => match &request.operation_name {
+            Some(name) => ops.get_mut(name.as_str()),
+            None if ops.len() == 1 => ops.values_mut().next(),
+            None => None,
+        },
+    };
+    let Some(operation) = operation else {
+        return Ok(false);
+    };
+    let Some(index) = operation
+        .node
+        .directives
+        .iter()
+        .position(|d| d.node.name.node == LIVE_DIRECTIVE)
+    else {
+        return Ok(false);
+    };
+    if operation.node.ty != OperationType::Query {
+        return Err(ServerError::new(
+            "@live is only supported on query operations",
+            Some(operation.node.directives[index].pos),
+        ));
+    }
+    operation.node.directives.remove(index);
+    request.set_parsed_query(doc);
+    Ok(true)
+}
+
+/// Websocket executor that keeps `query @live` operations open, re-running
+/// them after every river event and pushing the result whenever it changes.
+#[derive(Clone)]
+pub struct LiveExecutor {
+    schema: AppSchema,
+    events: Sender<river::Event>,
+}
+
+impl LiveExecutor {
+    pub fn new(schema: AppSchema, events: Sender<river::Event>) -> Self {
+        Self { schema, events }
+    }
+}
+
+impl Executor for LiveExecutor {
+    async fn execute(&self, mut request: Request) -> Response {
+        if let Err(err) = take_live_directive(&mut request) {
+            return Response::from_errors(vec![err]);
+        }
+        self.schema.execute(request).await
+    }
+
+    fn execute_stream(
+        &self,
+        mut request: Request,
+        session_data: Option<Arc<Data>>,
+    ) -> BoxStream<'static, Response> {
+        match take_live_directive(&mut request) {
+            Err(err) => stream::once(ready(Response::from_errors(vec![err]))).boxed(),
+            Ok(false) => Executor::execute_stream(&self.schema, request, session_data),
+            Ok(true) => {
+                let live = LiveQuery {
+                    schema: self.schema.clone(),
+                    // Subscribe before the first run so no change slips in between.
+                    events: self.events.subscribe(),
+                    query: request.query.clone(),
+                    operation_name: request.operation_name.clone(),
+                    variables: request.variables.clone(),
+                    document: request.parsed_query().ok().cloned(),
+                    session_data,
+                    last: None,
+                    started: false,
+                };
+                stream::unfold(live, |mut live| async move {
+                    let response = live.next_change().await?;
+                    Some((response, live))
+                })
+                .boxed()
+            }
+        }
+    }
+}
+
+struct LiveQuery {
+    schema: AppSchema,
+    events: Receiver<river::Event>,
+    query: String,
+    operation_name: Option<String>,
+    variables: Variables,
+    document: Option<ExecutableDocument>,
+    session_data: Option<Arc<Data>>,
+    last: Option<(Value, Vec<ServerError>)>,
+    started: bool,
+}
+
+impl LiveQuery {
+    /// Waits for the snapshot to change in a way that alters this query's
+    /// result; returns `None` once the event source is gone.
+    async fn next_change(&mut self) -> Option<Response> {
+        loop {
+            if self.started {
+                match self.events.recv().await {
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+                // Fold a burst of events into a single re-execution.
+                loop {
+                    match self.events.try_recv() {
+                        Ok(_) | Err(TryRecvError::Lagged(_)) => {}
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Closed) => return None,
+                    }
+                }
+            }
+            self.started = true;
+
+            let response = self.execute().await;
+            let result = (response.data.clone(), response.errors.clone());
+            if self.last.as_ref() != Some(&result) {
+                self.last = Some(result);
+                return Some(response);
+            }
+        }
+    }
+
+    async fn execute(&self) -> Response {
+        let mut request = Request::new(self.query.clone()).variables(self.variables.clone());
+        if let Some(name) = &self.operation_name {
+            request = request.operation_name(name.clone());
+        }
+        if let Some(document) = &self.document {
+            request.set_parsed_query(document.clone());
+        }
+        Executor::execute_stream(&self.schema, request, self.session_data.clone())
+            .next()
+            .await
+            .unwrap_or_default()
+    }
+}
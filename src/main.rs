@@ -1,12 +1,37 @@
+mod backend;
+mod bar;
+mod cache;
 mod client;
+mod control;
+mod discovery;
+mod dispatch;
+mod format;
+mod generate;
 mod gql;
+mod hyprland;
+mod library;
+mod live;
+mod metrics;
+mod mock;
+mod niri;
+mod oneshot;
+mod repl;
 mod river;
+mod script;
 mod server;
+mod service;
+mod sink;
+mod snapshot;
+mod sway;
+mod ws;
 
+use std::collections::HashSet;
 use std::env;
 use std::fmt;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Result, bail};
 use argh::FromArgs;
@@ -16,6 +41,8 @@ use async_graphql::Schema;
 use libc::geteuid;
 use url::Url;
 
+use crate::backend::StatusBackend;
+
 #[derive(Debug, Clone)]
 pub enum ListenTarget {
     Tcp(SocketAddr),
@@ -42,7 +69,7 @@ fn default_listen_addr() -> String {
             return format!("unix://{}", path.display());
         }
         let uid = unsafe { geteuid() };
-        return format!("unix:///run/user/{uid}/riverql.sock");
+        format!("unix:///run/user/{uid}/riverql.sock")
     }
 
     #[cfg(not(unix))]
@@ -51,7 +78,12 @@ fn default_listen_addr() -> String {
     }
 }
 
+/// A running server's discovery file wins; otherwise the default listen
+/// address is assumed.
 fn default_endpoint() -> String {
+    if let Some(endpoint) = discovery::Discovery::read().and_then(|d| d.endpoint()) {
+        return endpoint;
+    }
     match parse_listen_addr(&default_listen_addr()) {
         Ok(ListenTarget::Tcp(addr)) => format!("ws://{addr}/graphql"),
         #[cfg(unix)]
@@ -89,6 +121,36 @@ fn parse_listen_addr(value: &str) -> Result<ListenTarget> {
     bail!("invalid listen address {value:?}");
 }
 
+/// Parses `--suppress-events`; `Heartbeat` is only sent with `--heartbeat`, so it is
+/// turned off there instead.
+fn parse_suppressed(types: Option<&str>) -> Result<HashSet<gql::RiverEventType>> {
+    let mut suppressed = HashSet::new();
+    for name in types.into_iter().flat_map(|types| types.split(',')) {
+        let ty: gql::RiverEventType = name.trim().parse()?;
+        if ty == gql::RiverEventType::Heartbeat {
+            bail!("Heartbeat is not sent unless --heartbeat is given; leave that off instead");
+        }
+        suppressed.insert(ty);
+    }
+    Ok(suppressed)
+}
+
+/// Parses `--display NAME=SOCKET` values; a bare SOCKET is also its name.
+fn parse_displays(values: &[String]) -> Result<Vec<(String, String)>> {
+    let mut displays: Vec<(String, String)> = Vec::new();
+    for value in values {
+        let (name, socket) = value.split_once('=').unwrap_or((value, value));
+        if name.is_empty() || socket.is_empty() {
+            bail!("--display expects NAME=SOCKET, got {value:?}");
+        }
+        if displays.iter().any(|(existing, _)| existing == name) {
+            bail!("--display name {name:?} is used twice");
+        }
+        displays.push((name.to_string(), socket.to_string()));
+    }
+    Ok(displays)
+}
+
 #[derive(Debug, Clone)]
 pub enum EndpointTarget {
     Tcp(Url),
@@ -156,14 +218,122 @@ struct Cli {
     #[argh(option, default = "default_listen_addr()")]
     listen: String,
 
+    /// drop events identical to the previous one for the same output and type (server mode)
+    #[argh(switch)]
+    dedup: bool,
+
+    /// serve generated events instead of connecting to river (server mode)
+    #[argh(switch)]
+    mock: bool,
+
+    /// replay a JSON scenario file instead of connecting to river (server mode)
+    #[argh(option)]
+    mock_scenario: Option<PathBuf>,
+
+    /// compositor to read status from: river, sway (also i3) over the socket in SWAYSOCK, hyprland or niri; detected from the environment by default (server mode and snapshot --standalone)
+    #[argh(option)]
+    backend: Option<gql::Backend>,
+
+    /// register as a river layout generator under this namespace to expose layout demands (server mode)
+    #[argh(option)]
+    layout_namespace: Option<String>,
+
+    /// also serve the compositor at SOCKET (a name in XDG_RUNTIME_DIR or a path) as display(name: NAME); repeatable (server mode)
+    #[argh(option, long = "display")]
+    displays: Vec<String>,
+
+    /// close websocket connections after this many seconds without an open operation or incoming message; 0 disables (server mode)
+    #[argh(option, default = "0")]
+    idle_timeout: u64,
+
+    /// reject subscriptions and live queries beyond this many open at once on one websocket connection; 0 disables (server mode)
+    #[argh(option, default = "0")]
+    max_subscriptions: usize,
+
+    /// compress GraphQL websocket frames with permessage-deflate for clients that offer it (server mode)
+    #[argh(switch)]
+    ws_deflate: bool,
+
+    /// also send each event as a JSON datagram to the unix socket at PATH; repeatable (server mode)
+    #[argh(option, long = "datagram")]
+    datagrams: Vec<PathBuf>,
+
+    /// also write each event to the systemd journal as RIVERQL_* fields (server mode)
+    #[argh(switch)]
+    journal: bool,
+
+    /// run the shell command CMD once, restarting it when it exits, and write each event (server mode) or printed result (client mode) to its stdin instead of stdout; repeatable
+    #[argh(option, long = "pipe-to")]
+    pipes: Vec<String>,
+
+    /// broadcast a Heartbeat event every this many seconds so clients can detect a dead stream; 0 disables (server mode)
+    #[argh(option, default = "0")]
+    heartbeat: u64,
+
+    /// never broadcast or record these comma-separated event types, e.g. OutputLayoutDemand,SeatUnfocusedOutput; queries still reflect them (server mode)
+    #[argh(option)]
+    suppress_events: Option<String>,
+
+    /// add the output's position, size and scale as geometry to output-scoped subscription events (server mode)
+    #[argh(switch)]
+    event_geometry: bool,
+
+    /// serve the waylandGlobals query, listing what the bridge bound from the Wayland registry (server mode)
+    #[argh(switch)]
+    debug: bool,
+
+    /// reuse results of identical queries for this many milliseconds; 0 disables (server mode)
+    #[argh(option, default = "0")]
+    cache_ttl: u64,
+
     /// websocket endpoint for subscriptions (e.g. ws://host:port/graphql or unix://path#/graphql)
     #[argh(option)]
     endpoint: Option<String>,
 
+    /// keep retrying with backoff when the server is unreachable or the connection drops, reporting status as JSON lines on stderr (client mode)
+    #[argh(switch)]
+    reconnect: bool,
+
+    /// how to print results in client mode: json (default), shell for eval-able RIVERQL_* assignments, or table for aligned columns
+    #[argh(option, default = "format::OutputFormat::Json")]
+    format: format::OutputFormat,
+
+    /// print only the value of the query's root field, e.g. the event under data.events, instead of the whole payload (client mode)
+    #[argh(switch)]
+    raw: bool,
+
+    /// pass each result to fn on_event(event) in this rhai script and print what it returns; () drops the result (client mode)
+    #[argh(option)]
+    script: Option<PathBuf>,
+
+    /// read `subscribe ID QUERY` and `unsubscribe ID` lines from the FIFO at PATH and run those operations on one connection, tagging each result with its id (client mode)
+    #[argh(option)]
+    control: Option<PathBuf>,
+
+    /// exit after printing this many results (client mode)
+    #[argh(option)]
+    count: Option<u64>,
+
+    /// on exit, print a JSON summary to stderr with the reason, results received, duration and reconnects (client mode)
+    #[argh(switch)]
+    summary: bool,
+
+    /// instead of a query, subscribe to these comma-separated event types, e.g. OutputFocusedTags,SeatMode (client mode)
+    #[argh(option)]
+    types: Option<String>,
+
+    /// with or instead of --types, select these comma-separated fields on each event type that has them, e.g. tags,name,output_id (client mode)
+    #[argh(option)]
+    fields: Option<String>,
+
     /// inline query or @file for subscription mode; defaults to stdin when omitted
     #[argh(positional)]
     query: Option<String>,
 
+    /// tokio worker threads; raise for many subscribers (default 2)
+    #[argh(option, default = "2")]
+    workers: usize,
+
     /// show version information
     #[argh(switch)]
     version: bool,
@@ -171,10 +341,99 @@ struct Cli {
     /// print GraphQL schema to stdout
     #[argh(switch)]
     printschema: bool,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(subcommand)]
+enum Command {
+    Bar(BarCommand),
+    Subscribe(SubscribeCommand),
+    Repl(ReplCommand),
+    Snapshot(SnapshotCommand),
+    InstallService(InstallServiceCommand),
+}
+
+#[derive(FromArgs, Debug)]
+/// Interactive prompt with completion; runs queries and subscriptions inline.
+#[argh(subcommand, name = "repl")]
+struct ReplCommand {}
+
+#[derive(FromArgs, Debug)]
+/// Print the current status once as a JSON document and exit.
+#[argh(subcommand, name = "snapshot")]
+struct SnapshotCommand {
+    /// read the compositor directly instead of a server; --backend picks which one
+    #[argh(switch)]
+    standalone: bool,
+}
+
+#[derive(FromArgs, Debug)]
+/// Write systemd user units that run the server with the flags given before this subcommand.
+#[argh(subcommand, name = "install-service")]
+struct InstallServiceCommand {
+    /// directory for the units (default $XDG_CONFIG_HOME/systemd/user)
+    #[argh(option)]
+    dir: Option<PathBuf>,
+
+    /// reload the user manager and enable and start riverql.socket
+    #[argh(switch)]
+    enable: bool,
+
+    /// replace unit files that already exist
+    #[argh(switch)]
+    force: bool,
+}
+
+#[derive(FromArgs, Debug)]
+/// Run a named operation from $XDG_CONFIG_HOME/riverql/queries.
+#[argh(subcommand, name = "subscribe")]
+struct SubscribeCommand {
+    /// file name in the query library, without the .graphql extension
+    #[argh(positional)]
+    name: String,
+
+    /// override a default variable as name=value (value parsed as JSON, else a string)
+    #[argh(option, long = "var")]
+    vars: Vec<String>,
+}
+
+#[derive(FromArgs, Debug)]
+/// Render a status line for lemonbar/dzen-style bars on every change.
+#[argh(subcommand, name = "bar")]
+struct BarCommand {
+    /// line template with {tags}, {layout}, {title}, {mode} and {output} placeholders
+    #[argh(option, default = "bar::DEFAULT_FORMAT.to_string()")]
+    format: String,
+
+    /// follow this output instead of the focused one
+    #[argh(option)]
+    output: Option<String>,
+
+    /// number of tags rendered by {tags} (default 9)
+    #[argh(option, default = "9")]
+    tag_count: u32,
+
+    /// marker for focused tags; {} is the tag number (default "[{}]")
+    #[argh(option, default = "\"[{}]\".to_string()")]
+    focused: String,
+
+    /// marker for tags with views (default " {} ")
+    #[argh(option, default = "\" {} \".to_string()")]
+    occupied: String,
+
+    /// marker for urgent tags (default "!{}!")
+    #[argh(option, default = "\"!{}!\".to_string()")]
+    urgent: String,
+
+    /// marker for empty, unfocused tags (default: hidden)
+    #[argh(option, default = "String::new()")]
+    empty: String,
 }
 
-#[tokio::main(flavor = "multi_thread", worker_threads = 2)]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
@@ -184,14 +443,54 @@ async fn main() -> Result<()> {
         .compact()
         .init();
 
+    let cli: Cli = argh::from_env();
+    if cli.workers == 0 {
+        bail!("--workers must be at least 1");
+    }
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(cli.workers)
+        .enable_all()
+        .build()?
+        .block_on(run(cli))
+}
+
+async fn run(cli: Cli) -> Result<()> {
     let Cli {
         server,
         listen,
+        dedup,
+        mock,
+        mock_scenario,
+        backend,
+        cache_ttl,
+        debug,
+        layout_namespace,
+        displays,
+        idle_timeout,
+        max_subscriptions,
+        ws_deflate,
+        datagrams,
+        journal,
+        pipes,
+        heartbeat,
+        suppress_events,
+        event_geometry,
         endpoint,
+        reconnect,
+        format,
+        raw,
+        control,
+        script,
+        count,
+        summary,
+        types,
+        fields,
         query,
         version,
         printschema,
-    } = argh::from_env();
+        command,
+        workers: _,
+    } = cli;
 
     if version {
         println!("riverql {}", env!("CARGO_PKG_VERSION"));
@@ -199,27 +498,248 @@ async fn main() -> Result<()> {
     }
 
     if printschema {
-        let schema: Schema<gql::QueryRoot, async_graphql::EmptyMutation, gql::SubscriptionRoot> =
-            Schema::build(
-                gql::QueryRoot,
-                async_graphql::EmptyMutation,
-                gql::SubscriptionRoot,
-            )
-            .finish();
+        let schema: gql::AppSchema =
+            Schema::build(gql::QueryRoot, gql::MutationRoot, gql::SubscriptionRoot).finish();
         println!("{}", schema.sdl());
         return Ok(());
     }
 
+    if let Some(Command::Bar(bar)) = &command {
+        if server
+            || reconnect
+            || query.is_some()
+            || types.is_some()
+            || fields.is_some()
+            || count.is_some()
+            || summary
+            || control.is_some()
+            || script.is_some()
+            || !pipes.is_empty()
+            || raw
+        {
+            bail!(
+                "bar does not take --server, --reconnect, --types, --fields, --count, --summary, --control, --script, --pipe-to, --raw or query arguments"
+            );
+        }
+        let endpoint_value = endpoint.unwrap_or_else(default_endpoint);
+        return bar::run(bar::BarOptions {
+            endpoint: parse_endpoint(&endpoint_value)?,
+            format: bar.format.clone(),
+            output: bar.output.clone(),
+            tags: gql::TagIndicatorFormat {
+                focused: bar.focused.clone(),
+                occupied: bar.occupied.clone(),
+                urgent: bar.urgent.clone(),
+                empty: bar.empty.clone(),
+                separator: String::new(),
+                tag_count: bar.tag_count,
+            },
+        })
+        .await;
+    }
+
+    if let Some(Command::Snapshot(snapshot)) = &command {
+        if server
+            || reconnect
+            || query.is_some()
+            || types.is_some()
+            || fields.is_some()
+            || count.is_some()
+            || summary
+            || control.is_some()
+            || script.is_some()
+            || !pipes.is_empty()
+            || raw
+        {
+            bail!(
+                "snapshot does not take --server, --reconnect, --types, --fields, --count, --summary, --control, --script, --pipe-to, --raw or query arguments"
+            );
+        }
+        let source = if snapshot.standalone {
+            if endpoint.is_some() {
+                bail!("snapshot --standalone reads the compositor; drop --endpoint");
+            }
+            let kind = backend.unwrap_or_else(backend::detect);
+            oneshot::Source::Standalone(backend::compositor(kind, Default::default())?)
+        } else {
+            let endpoint_value = endpoint.unwrap_or_else(default_endpoint);
+            oneshot::Source::Endpoint(parse_endpoint(&endpoint_value)?)
+        };
+        return oneshot::run(source).await;
+    }
+
+    if let Some(Command::InstallService(install)) = command {
+        if endpoint.is_some()
+            || reconnect
+            || query.is_some()
+            || types.is_some()
+            || fields.is_some()
+            || count.is_some()
+            || summary
+            || control.is_some()
+            || script.is_some()
+            || raw
+        {
+            bail!("install-service takes server flags, not client arguments");
+        }
+        return service::install(service::InstallOptions {
+            listen: parse_listen_addr(&listen)?,
+            server_args: service::server_args(env::args()),
+            dir: install.dir,
+            enable: install.enable,
+            force: install.force,
+        })
+        .await;
+    }
+
     if server {
-        if endpoint.is_some() || query.is_some() {
-            bail!("--server does not take endpoint or query arguments");
+        if endpoint.is_some()
+            || reconnect
+            || query.is_some()
+            || command.is_some()
+            || types.is_some()
+            || fields.is_some()
+            || count.is_some()
+            || summary
+            || control.is_some()
+            || script.is_some()
+            || raw
+        {
+            bail!("--server does not take client arguments");
         }
         let listen = parse_listen_addr(&listen)?;
-        server::run(listen).await?
+        let mock = match mock_scenario {
+            Some(path) => Some(mock::Source::Scenario(mock::Scenario::load(&path)?)),
+            None => mock.then_some(mock::Source::Generated),
+        };
+        if mock.is_some() && !displays.is_empty() {
+            bail!("--display cannot be combined with --mock or --mock-scenario");
+        }
+        let status = river::StatusOptions {
+            layout_namespace,
+            display: None,
+        };
+        let backend: Arc<dyn StatusBackend> = match mock {
+            Some(source) => Arc::new(mock::MockBackend::new(source)),
+            None => {
+                let kind = backend.unwrap_or_else(backend::detect);
+                if kind != gql::Backend::River
+                    && (!displays.is_empty() || status.layout_namespace.is_some())
+                {
+                    bail!(
+                        "--display and --layout-namespace need the river backend (--backend river)"
+                    );
+                }
+                backend::compositor(kind, status.clone())?
+            }
+        };
+        let displays = parse_displays(&displays)?
+            .into_iter()
+            .map(|(name, socket)| {
+                let display: Arc<dyn StatusBackend> =
+                    Arc::new(river::RiverBackend::new(river::StatusOptions {
+                        display: Some(socket),
+                        ..status.clone()
+                    }));
+                (name, display)
+            })
+            .collect();
+        server::run(server::ServerOptions {
+            listen,
+            dedup,
+            backend,
+            cache_ttl: Duration::from_millis(cache_ttl),
+            displays,
+            ws_limits: ws::ConnectionLimits {
+                idle_timeout: (idle_timeout > 0).then(|| Duration::from_secs(idle_timeout)),
+                max_operations: (max_subscriptions > 0).then_some(max_subscriptions),
+            },
+            ws_deflate,
+            datagrams,
+            journal,
+            pipes,
+            heartbeat: (heartbeat > 0).then(|| Duration::from_secs(heartbeat)),
+            events: gql::EventOptions {
+                geometry: event_geometry,
+                suppress: parse_suppressed(suppress_events.as_deref())?,
+            },
+            debug,
+        })
+        .await?
     } else {
         let endpoint_value = endpoint.unwrap_or_else(default_endpoint);
         let endpoint = parse_endpoint(&endpoint_value)?;
-        client::run(endpoint, query).await?
+        if count == Some(0) {
+            bail!("--count must be at least 1");
+        }
+        if raw && (format != format::OutputFormat::Json || script.is_some()) {
+            bail!("--raw prints JSON; it does not take --format shell, --format table or --script");
+        }
+        let options = client::ClientOptions {
+            reconnect,
+            format,
+            raw,
+            count,
+            summary,
+            script,
+            pipes,
+        };
+        let generated = types.is_some() || fields.is_some();
+        if generated && (query.is_some() || command.is_some()) {
+            bail!("--types and --fields replace the query argument and subcommands");
+        }
+        let query = if generated {
+            Some(generate::subscription(types.as_deref(), fields.as_deref()).await?)
+        } else {
+            query
+        };
+        if control.is_some()
+            && (command.is_some()
+                || reconnect
+                || count.is_some()
+                || summary
+                || options.script.is_some()
+                || !options.pipes.is_empty()
+                || options.raw)
+        {
+            bail!(
+                "--control does not take --reconnect, --count, --summary, --script, --pipe-to, --raw or subcommands"
+            );
+        }
+        let result = match command {
+            Some(Command::Repl(_)) => {
+                if query.is_some()
+                    || reconnect
+                    || count.is_some()
+                    || summary
+                    || options.script.is_some()
+                    || !options.pipes.is_empty()
+                    || options.raw
+                {
+                    bail!(
+                        "repl does not take --reconnect, --count, --summary, --script, --pipe-to, --raw or query arguments"
+                    );
+                }
+                repl::run(endpoint).await
+            }
+            Some(Command::Subscribe(named)) => {
+                if query.is_some() {
+                    bail!("subscribe takes a query name, not a query argument");
+                }
+                client::run_named(endpoint, &named.name, &named.vars, options).await
+            }
+            _ => match &control {
+                Some(path) => control::run(endpoint, query, path, options).await,
+                None => client::run(endpoint, query, options).await,
+            },
+        };
+        if let Err(e) = result {
+            if let Some(close) = e.downcast_ref::<client::CloseError>() {
+                eprintln!("Error: {close}");
+                std::process::exit(close.exit_code());
+            }
+            return Err(e);
+        }
     };
 
     Ok(())
@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_graphql::resolver_utils::EnumType;
+
+use crate::gql::RiverEventType;
+
+pub type MetricsHandle = Arc<Metrics>;
+
+/// (subscription field, sorted event type filter)
+type SubscriptionKey = (&'static str, String);
+
+/// Upper bounds of the latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0,
+];
+
+/// Counters exported at `/metrics` in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<String, u64>>,
+    events: Mutex<HashMap<RiverEventType, u64>>,
+    subscriptions: Mutex<BTreeMap<SubscriptionKey, i64>>,
+    lagged: AtomicU64,
+    dropped: AtomicU64,
+    latency: Mutex<Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket of `LATENCY_BUCKETS`, not cumulative; the
+    /// extra slot counts those above the last bound.
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Metrics {
+    pub fn record_request(&self, route: &str) {
+        let mut requests = self.requests.lock().unwrap();
+        *requests.entry(route.to_string()).or_default() += 1;
+    }
+
+    /// Counts an event handed to the broadcast channel.
+    pub fn record_event(&self, kind: RiverEventType) {
+        *self.events.lock().unwrap().entry(kind).or_default() += 1;
+    }
+
+    /// Records how long an event took from the compositor connection to the
+    /// broadcast channel.
+    pub fn record_latency(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        let mut latency = self.latency.lock().unwrap();
+        latency.buckets[bucket] += 1;
+        latency.sum += seconds;
+    }
+
+    /// Counts events a slow subscriber skipped because it fell behind.
+    pub fn record_lagged(&self, missed: u64) {
+        self.lagged.fetch_add(missed, Ordering::Relaxed);
+    }
+
+    /// Counts river events dropped because the bridge's event queue was full.
+    pub fn record_dropped(&self, dropped: u64) {
+        self.dropped.fetch_add(dropped, Ordering::Relaxed);
+    }
+
+    /// Counts a subscription as active until the returned guard is dropped.
+    /// `types` is the effective filter; `None` means every event type.
+    pub fn track_subscription(
+        self: &Arc<Self>,
+        field: &'static str,
+        types: Option<&HashSet<RiverEventType>>,
+    ) -> SubscriptionGuard {
+        let filter = match types {
+            Some(types) => {
+                let mut names: Vec<_> = types.iter().map(|kind| type_name(*kind)).collect();
+                names.sort_unstable();
+                names.join(",")
+            }
+            None => "all".to_string(),
+        };
+        let key = (field, filter);
+        *self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default() += 1;
+        SubscriptionGuard {
+            metrics: self.clone(),
+            key,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "riverql_http_requests_total",
+            "counter",
+            "HTTP requests by route.",
+        );
+        for (route, count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "riverql_http_requests_total{{route=\"{}\"}} {count}",
+                escape(route)
+            );
+        }
+
+        header(
+            &mut out,
+            "riverql_events_total",
+            "counter",
+            "River events broadcast to subscribers, by event type.",
+        );
+        let events = self.events.lock().unwrap();
+        for item in RiverEventType::items() {
+            let count = events.get(&item.value).copied().unwrap_or(0);
+            let _ = writeln!(
+                out,
+                "riverql_events_total{{type=\"{}\"}} {count}",
+                item.name
+            );
+        }
+        drop(events);
+
+        header(
+            &mut out,
+            "riverql_subscriptions_active",
+            "gauge",
+            "Open event subscriptions by field and event type filter.",
+        );
+        for ((field, filter), count) in self.subscriptions.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "riverql_subscriptions_active{{field=\"{field}\",types=\"{}\"}} {count}",
+                escape(filter)
+            );
+        }
+
+        header(
+            &mut out,
+            "riverql_broadcast_lagged_events_total",
+            "counter",
+            "Events dropped for subscribers that fell behind the broadcast channel.",
+        );
+        let _ = writeln!(
+            out,
+            "riverql_broadcast_lagged_events_total {}",
+            self.lagged.load(Ordering::Relaxed)
+        );
+
+        header(
+            &mut out,
+            "riverql_river_dropped_events_total",
+            "counter",
+            "River events dropped because the bridge's event queue was full.",
+        );
+        let _ = writeln!(
+            out,
+            "riverql_river_dropped_events_total {}",
+            self.dropped.load(Ordering::Relaxed)
+        );
+
+        header(
+            &mut out,
+            "riverql_event_latency_seconds",
+            "histogram",
+            "Time from receiving an event from the compositor to broadcasting it.",
+        );
+        let latency = self.latency.lock().unwrap();
+        let mut count = 0;
+        for (bound, observed) in LATENCY_BUCKETS.iter().zip(latency.buckets) {
+            count += observed;
+            let _ = writeln!(
+                out,
+                "riverql_event_latency_seconds_bucket{{le=\"{bound}\"}} {count}"
+            );
+        }
+        count += latency.buckets[LATENCY_BUCKETS.len()];
+        let _ = writeln!(
+            out,
+            "riverql_event_latency_seconds_bucket{{le=\"+Inf\"}} {count}"
+        );
+        let _ = writeln!(out, "riverql_event_latency_seconds_sum {}", latency.sum);
+        let _ = writeln!(out, "riverql_event_latency_seconds_count {count}");
+
+        out
+    }
+}
+
+/// Keeps a subscription counted in `riverql_subscriptions_active`.
+pub struct SubscriptionGuard {
+    metrics: MetricsHandle,
+    key: SubscriptionKey,
+}
+
+impl Drop for SubscriptionGuard {
+    fn drop(&mut self) {
+        let mut subscriptions = self.metrics.subscriptions.lock().unwrap();
+        if let Some(count) = subscriptions.get_mut(&self.key) {
+            *count -= 1;
+        }
+    }
+}
+
+fn type_name(kind: RiverEventType) -> &'static str {
+    RiverEventType::items()
+        .iter()
+        .find(|item| item.value == kind)
+        .map_or("", |item| item.name)
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tracing::info;
+
+use crate::backend::StatusBackend;
+use crate::gql;
+use crate::river::{BoundGlobal, EVENT_QUEUE_CAPACITY, Event, OutputId, Protocols, Received};
+
+const OUTPUTS: [&str; 2] = ["MOCK-1", "MOCK-2"];
+const TITLES: [&str; 4] = ["Terminal", "Emacs", "Firefox", "riverql — GraphiQL"];
+const APP_IDS: [&str; 4] = ["foot", "emacs", "firefox", "firefox"];
+const LAYOUTS: [&str; 3] = ["rivertile - left", "rivertile - top", "monocle"];
+const MODES: [&str; 2] = ["normal", "passthrough"];
+/// Width of each mock output; they sit side by side in `OUTPUTS` order.
+const OUTPUT_WIDTH: i32 = 1920;
+const OUTPUT_HEIGHT: i32 = 1080;
+/// Seat named in seat events, unless a scenario step names another.
+const SEAT: &str = "seat0";
+const TICK: Duration = Duration::from_secs(1);
+
+/// Where mock events come from.
+#[derive(Clone)]
+pub enum Source {
+    /// Endless generated session with two outputs.
+    Generated,
+    /// Timed event sequence loaded from a scenario file.
+    Scenario(Scenario),
+}
+
+/// Synthetic events, so clients can be developed on machines without river.
+pub struct MockBackend {
+    source: Source,
+}
+
+impl MockBackend {
+    pub fn new(source: Source) -> Self {
+        Self { source }
+    }
+}
+
+impl StatusBackend for MockBackend {
+    fn kind(&self) -> gql::Backend {
+        gql::Backend::Mock
+    }
+
+    fn connect(&self) -> BoxFuture<'_, Result<(Receiver<Received>, Protocols)>> {
+        Box::pin(async move {
+            info!("using mock river events");
+            Ok((subscribe(self.source.clone()), protocols()))
+        })
+    }
+}
+
+/// Starts the synthetic event source.
+fn subscribe(source: Source) -> Receiver<Received> {
+    let (tx, rx) = mpsc::channel(EVENT_QUEUE_CAPACITY);
+    let tx = Events(tx);
+    tokio::spawn(async move {
+        let _ = match source {
+            Source::Generated => generate(tx).await,
+            Source::Scenario(scenario) => replay(scenario, tx).await,
+        };
+    });
+    rx
+}
+
+/// Queues synthetic events, each received the moment it is sent.
+struct Events(Sender<Received>);
+
+impl Events {
+    async fn send(&self, event: Event) -> Result<(), mpsc::error::SendError<Received>> {
+        self.0.send(event.into()).await
+    }
+}
+
+/// Versions the mock stands in for: a current river that sends every event.
+fn protocols() -> Protocols {
+    let global = |interface: &str, version| BoundGlobal {
+        interface: interface.to_string(),
+        version,
+    };
+    Protocols {
+        globals: vec![
+            global("wl_output", 4),
+            global("wl_seat", 5),
+            global(Protocols::STATUS_MANAGER, 4),
+            global(Protocols::TOPLEVEL_MANAGER, 3),
+            global(Protocols::XDG_OUTPUT_MANAGER, 3),
+        ],
+    }
+}
+
+/// Deterministic, timed event sequence for reproducing bar behavior.
+///
+/// ```json
+/// {
+///   "repeat": false,
+///   "steps": [
+///     { "type": "OutputAdded", "output": "DP-1" },
+///     { "type": "OutputFocusedTags", "output": "DP-1", "tags": 1 },
+///     { "delayMs": 500, "type": "OutputUrgentTags", "output": "DP-1", "tags": 4 }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Start over from the first step after the last one.
+    #[serde(default)]
+    repeat: bool,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Step {
+    /// Pause before emitting this step's event.
+    #[serde(default)]
+    delay_ms: u64,
+    #[serde(flatten)]
+    event: StepEvent,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+enum StepEvent {
+    OutputAdded {
+        output: String,
+    },
+    OutputFocusedTags {
+        output: String,
+        tags: u32,
+    },
+    OutputViewTags {
+        output: String,
+        tags: Vec<u32>,
+    },
+    OutputUrgentTags {
+        output: String,
+        tags: u32,
+    },
+    OutputLayoutName {
+        output: String,
+        layout: Option<String>,
+    },
+    OutputRemoved {
+        output: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    OutputLayoutDemand {
+        output: String,
+        view_count: u32,
+        usable_width: u32,
+        usable_height: u32,
+        #[serde(default)]
+        tags: u32,
+    },
+    OutputPosition {
+        output: String,
+        x: i32,
+        y: i32,
+        #[serde(default)]
+        width: Option<i32>,
+        #[serde(default)]
+        height: Option<i32>,
+        #[serde(default)]
+        scale: Option<i32>,
+    },
+    SeatFocusedOutput {
+        output: String,
+        #[serde(default)]
+        seat: Option<String>,
+    },
+    SeatUnfocusedOutput {
+        output: String,
+        #[serde(default)]
+        seat: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    SeatFocusedView {
+        title: String,
+        #[serde(default)]
+        app_id: Option<String>,
+        #[serde(default)]
+        view_id: Option<String>,
+        #[serde(default)]
+        seat: Option<String>,
+    },
+    SeatMode {
+        name: String,
+        #[serde(default)]
+        seat: Option<String>,
+    },
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading scenario {}", path.display()))?;
+        let scenario: Scenario = serde_json::from_str(&text)
+            .with_context(|| format!("parsing scenario {}", path.display()))?;
+        if scenario.steps.is_empty() {
+            bail!("scenario {} has no steps", path.display());
+        }
+        if scenario.repeat && scenario.steps.iter().all(|step| step.delay_ms == 0) {
+            bail!(
+                "scenario {} repeats without any delayMs; add a delay to at least one step",
+                path.display()
+            );
+        }
+        Ok(scenario)
+    }
+}
+
+/// Maps scenario output names to synthetic ids; a removed output gets a fresh
+/// id when it reappears, like a re-plugged monitor.
+#[derive(Default)]
+struct ScenarioOutputs {
+    ids: HashMap<String, OutputId>,
+    next: usize,
+}
+
+impl ScenarioOutputs {
+    fn id(&mut self, name: &str) -> OutputId {
+        if let Some(id) = self.ids.get(name) {
+            return id.clone();
+        }
+        self.next += 1;
+        let id = OutputId::synthetic(format!("mock_output@{}", self.next));
+        self.ids.insert(name.to_string(), id.clone());
+        id
+    }
+
+    fn remove(&mut self, name: &str) -> OutputId {
+        let id = self.id(name);
+        self.ids.remove(name);
+        id
+    }
+}
+
+async fn replay(scenario: Scenario, tx: Events) -> Result<(), mpsc::error::SendError<Received>> {
+    let mut outputs = ScenarioOutputs::default();
+    loop {
+        for step in &scenario.steps {
+            if step.delay_ms > 0 {
+                tokio::time::sleep(Duration::from_millis(step.delay_ms)).await;
+            }
+            tx.send(step_event(&step.event, &mut outputs)).await?;
+        }
+        if !scenario.repeat {
+            return Ok(());
+        }
+    }
+}
+
+fn step_event(step: &StepEvent, outputs: &mut ScenarioOutputs) -> Event {
+    let named = |output: &str| Some(output.to_string());
+    match step {
+        StepEvent::OutputAdded { output } => Event::OutputNamed {
+            id: outputs.id(output),
+            name: output.clone(),
+        },
+        StepEvent::OutputFocusedTags { output, tags } => Event::OutputFocusedTags {
+            id: outputs.id(output),
+            name: named(output),
+            tags: *tags,
+        },
+        StepEvent::OutputViewTags { output, tags } => Event::OutputViewTags {
+            id: outputs.id(output),
+            name: named(output),
+            tags: tags.clone(),
+        },
+        StepEvent::OutputUrgentTags { output, tags } => Event::OutputUrgentTags {
+            id: outputs.id(output),
+            name: named(output),
+            tags: *tags,
+        },
+        StepEvent::OutputLayoutName {
+            output,
+            layout: Some(layout),
+        } => Event::OutputLayoutName {
+            id: outputs.id(output),
+            name: named(output),
+            layout: layout.clone(),
+        },
+        StepEvent::OutputLayoutName {
+            output,
+            layout: None,
+        } => Event::OutputLayoutNameClear {
+            id: outputs.id(output),
+            name: named(output),
+        },
+        StepEvent::OutputRemoved { output } => Event::OutputRemoved {
+            id: outputs.remove(output),
+            name: named(output),
+        },
+        StepEvent::OutputLayoutDemand {
+            output,
+            view_count,
+            usable_width,
+            usable_height,
+            tags,
+        } => Event::OutputLayoutDemand {
+            id: outputs.id(output),
+            name: named(output),
+            view_count: *view_count,
+            usable_width: *usable_width,
+            usable_height: *usable_height,
+            tags: *tags,
+        },
+        StepEvent::OutputPosition {
+            output,
+            x,
+            y,
+            width,
+            height,
+            scale,
+        } => Event::OutputPosition {
+            id: outputs.id(output),
+            name: named(output),
+            x: *x,
+            y: *y,
+            width: *width,
+            height: *height,
+            scale: *scale,
+        },
+        StepEvent::SeatFocusedOutput { output, seat } => Event::SeatFocusedOutput {
+            id: outputs.id(output),
+            name: named(output),
+            seat: seat_name(seat),
+        },
+        StepEvent::SeatUnfocusedOutput { output, seat } => Event::SeatUnfocusedOutput {
+            id: outputs.id(output),
+            name: named(output),
+            seat: seat_name(seat),
+        },
+        StepEvent::SeatFocusedView {
+            title,
+            app_id,
+            view_id,
+            seat,
+        } => Event::SeatFocusedView {
+            title: title.clone(),
+            app_id: app_id.clone(),
+            view_id: view_id.clone(),
+            seat: seat_name(seat),
+        },
+        StepEvent::SeatMode { name, seat } => Event::SeatMode {
+            name: name.clone(),
+            seat: seat_name(seat),
+        },
+    }
+}
+
+fn seat_name(seat: &Option<String>) -> Option<String> {
+    Some(seat.as_deref().unwrap_or(SEAT).to_string())
+}
+
+fn output(index: usize) -> (OutputId, Option<String>) {
+    let name = OUTPUTS[index % OUTPUTS.len()];
+    (
+        OutputId::synthetic(format!("mock_output@{}", index + 1)),
+        Some(name.to_string()),
+    )
+}
+
+fn focused_view(index: usize) -> Event {
+    Event::SeatFocusedView {
+        title: TITLES[index].to_string(),
+        app_id: Some(APP_IDS[index].to_string()),
+        view_id: Some(format!("mock_view@{}", index + 1)),
+        seat: Some(SEAT.to_string()),
+    }
+}
+
+async fn generate(tx: Events) -> Result<(), mpsc::error::SendError<Received>> {
+    for index in 0..OUTPUTS.len() {
+        let (id, name) = output(index);
+        let tags = 1 << index;
+        tx.send(Event::OutputFocusedTags {
+            id: id.clone(),
+            name: name.clone(),
+            tags,
+        })
+        .await?;
+        tx.send(Event::OutputViewTags {
+            id: id.clone(),
+            name: name.clone(),
+            tags: vec![tags],
+        })
+        .await?;
+        tx.send(Event::OutputUrgentTags {
+            id: id.clone(),
+            name: name.clone(),
+            tags: 0,
+        })
+        .await?;
+        tx.send(Event::OutputLayoutName {
+            id: id.clone(),
+            name: name.clone(),
+            layout: LAYOUTS[0].to_string(),
+        })
+        .await?;
+        tx.send(Event::OutputPosition {
+            id,
+            name,
+            x: index as i32 * OUTPUT_WIDTH,
+            y: 0,
+            width: Some(OUTPUT_WIDTH),
+            height: Some(OUTPUT_HEIGHT),
+            scale: Some(1),
+        })
+        .await?;
+    }
+    let (id, name) = output(0);
+    tx.send(Event::SeatFocusedOutput {
+        id,
+        name,
+        seat: Some(SEAT.to_string()),
+    })
+    .await?;
+    tx.send(focused_view(0)).await?;
+    tx.send(Event::SeatMode {
+        name: MODES[0].to_string(),
+        seat: Some(SEAT.to_string()),
+    })
+    .await?;
+
+    let mut interval = tokio::time::interval(TICK);
+    interval.tick().await;
+    let mut focused = 0;
+    for tick in 1usize.. {
+        interval.tick().await;
+        let index = tick % OUTPUTS.len();
+        let (id, name) = output(index);
+        let tag = 1u32 << (tick % 9);
+
+        tx.send(Event::OutputFocusedTags {
+            id: id.clone(),
+            name: name.clone(),
+            tags: tag,
+        })
+        .await?;
+        tx.send(Event::OutputViewTags {
+            id: id.clone(),
+            name: name.clone(),
+            tags: vec![tag, 1 << ((tick + 3) % 9), tag],
+        })
+        .await?;
+
+        match tick % 5 {
+            0 => {
+                tx.send(Event::OutputUrgentTags {
+                    id: id.clone(),
+                    name: name.clone(),
+                    tags: 1 << ((tick + 5) % 9),
+                })
+                .await?
+            }
+            1 => {
+                tx.send(Event::OutputUrgentTags {
+                    id: id.clone(),
+                    name: name.clone(),
+                    tags: 0,
+                })
+                .await?
+            }
+            _ => {}
+        }
+
+        if tick % 7 == 0 {
+            tx.send(Event::OutputLayoutName {
+                id: id.clone(),
+                name: name.clone(),
+                layout: LAYOUTS[(tick / 7) % LAYOUTS.len()].to_string(),
+            })
+            .await?;
+        }
+
+        if tick % 3 == 0 && index != focused {
+            let (prev_id, prev_name) = output(focused);
+            tx.send(Event::SeatUnfocusedOutput {
+                id: prev_id,
+                name: prev_name,
+                seat: Some(SEAT.to_string()),
+            })
+            .await?;
+            tx.send(Event::SeatFocusedOutput {
+                id,
+                name,
+                seat: Some(SEAT.to_string()),
+            })
+            .await?;
+            focused = index;
+        }
+
+        tx.send(focused_view(tick % TITLES.len())).await?;
+
+        if tick % 10 == 0 {
+            tx.send(Event::SeatMode {
+                name: MODES[(tick / 10) % MODES.len()].to_string(),
+                seat: Some(SEAT.to_string()),
+            })
+            .await?;
+        }
+    }
+    Ok(())
+}
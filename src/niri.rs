@@ -0,0 +1,327 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Context, Result, anyhow, bail};
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tracing::{debug, info, warn};
+
+use crate::backend::{Command, StatusBackend};
+use crate::gql;
+use crate::river::{BoundGlobal, EVENT_QUEUE_CAPACITY, Event, Protocols, Received};
+use crate::snapshot::{FocusedView, OutputStatus, Rect, Reported, Snapshot, workspace_tag};
+
+/// niri over its IPC socket.
+pub struct NiriBackend {
+    socket: PathBuf,
+}
+
+impl NiriBackend {
+    /// Uses the IPC socket niri announces in the environment.
+    pub fn from_env() -> Result<Self> {
+        let socket = env::var_os("NIRI_SOCKET")
+            .map(PathBuf::from)
+            .context("NIRI_SOCKET is not set; is niri running?")?;
+        Ok(Self { socket })
+    }
+}
+
+impl StatusBackend for NiriBackend {
+    fn kind(&self) -> gql::Backend {
+        gql::Backend::Niri
+    }
+
+    fn connect(&self) -> BoxFuture<'_, Result<(Receiver<Received>, Protocols)>> {
+        Box::pin(async move {
+            info!(socket = %self.socket.display(), "connecting to niri IPC");
+            Ok((subscribe(&self.socket).await?, protocols()))
+        })
+    }
+
+    /// Switches the focused output, or `output` after focusing it, to the
+    /// workspace of a single tag.
+    fn execute(&self, command: &Command) -> BoxFuture<'_, Result<()>> {
+        let command = command.clone();
+        Box::pin(async move {
+            let Command::SetFocusedTags {
+                tags,
+                output,
+                focused,
+            } = command;
+            if tags.count_ones() != 1 {
+                bail!("niri shows one workspace per output; cannot focus tags {tags:#b}");
+            }
+            let index = tags.trailing_zeros() + 1;
+            if let Some(output) = output {
+                let action =
+                    serde_json::json!({ "Action": { "FocusMonitor": { "output": output } } });
+                let _: Value = request(&self.socket, &action).await?;
+            }
+            let action = serde_json::json!({
+                "Action": { "FocusWorkspace": { "reference": { "Index": index } } }
+            });
+            let _: Value = request(&self.socket, &action).await?;
+            if let Some(output) = focused {
+                let action =
+                    serde_json::json!({ "Action": { "FocusMonitor": { "output": output } } });
+                let _: Value = request(&self.socket, &action).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// What `serverInfo.protocols` lists for this backend.
+fn protocols() -> Protocols {
+    Protocols {
+        globals: vec![BoundGlobal {
+            interface: "niri-ipc".to_string(),
+            version: 1,
+        }],
+    }
+}
+
+/// Opens niri's event stream at `socket` and reports its state as river
+/// events: the workspace at index N of an output is tag N, each window is a
+/// view on its workspace's output, and the active workspace is the output's
+/// focused tag. niri has neither layout names nor binding modes, so those
+/// stay null.
+async fn subscribe(socket: &Path) -> Result<Receiver<Received>> {
+    let mut stream = connect(socket).await?;
+    stream.write_all(b"\"EventStream\"\n").await?;
+    let mut lines = BufReader::new(stream).lines();
+    let reply = lines
+        .next_line()
+        .await?
+        .context("niri closed the event stream")?;
+    let _: Value = parse_reply(&reply)?;
+    let mut state = Reported::new("niri_output");
+    let initial = state.update(fetch(socket).await?);
+
+    let (tx, rx) = mpsc::channel(EVENT_QUEUE_CAPACITY);
+    let socket = socket.to_path_buf();
+    tokio::spawn(async move {
+        if let Err(e) = forward(&socket, lines, state, initial, tx).await {
+            warn!("niri IPC connection ended: {e:#}");
+        }
+    });
+    Ok(rx)
+}
+
+/// Re-reads niri's state after every event and forwards what changed, until
+/// either side goes away.
+async fn forward(
+    socket: &Path,
+    mut lines: Lines<BufReader<UnixStream>>,
+    mut state: Reported,
+    mut pending: Vec<Event>,
+    tx: Sender<Received>,
+) -> Result<()> {
+    // Changes are timed from the event that revealed them.
+    let mut received = Instant::now();
+    loop {
+        for event in pending.drain(..) {
+            if tx
+                .send(Received {
+                    event,
+                    at: received,
+                    dropped: 0,
+                })
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+        let Some(line) = lines.next_line().await? else {
+            bail!("niri closed the event stream");
+        };
+        received = Instant::now();
+        let name = serde_json::from_str::<Value>(&line)
+            .ok()
+            .and_then(|event| event.as_object()?.keys().next().cloned());
+        debug!(event = ?name, "niri event");
+        pending = state.update(fetch(socket).await?);
+    }
+}
+
+async fn connect(socket: &Path) -> Result<UnixStream> {
+    UnixStream::connect(socket)
+        .await
+        .with_context(|| format!("connecting to niri IPC at {}", socket.display()))
+}
+
+/// Sends `request` on a new connection; niri answers one request per
+/// connection with `{"Ok": ...}` or `{"Err": ...}`.
+async fn request<T: DeserializeOwned>(socket: &Path, request: &Value) -> Result<T> {
+    let mut stream = connect(socket).await?;
+    stream.write_all(format!("{request}\n").as_bytes()).await?;
+    let reply = BufReader::new(stream)
+        .lines()
+        .next_line()
+        .await?
+        .with_context(|| format!("niri closed the connection before answering {request}"))?;
+    parse_reply(&reply).with_context(|| format!("niri's reply to {request}"))
+}
+
+fn parse_reply<T: DeserializeOwned>(reply: &str) -> Result<T> {
+    match serde_json::from_str(reply)? {
+        Reply::Ok(value) => Ok(value),
+        Reply::Err(message) => Err(anyhow!("niri refused the request: {message}")),
+    }
+}
+
+#[derive(Deserialize)]
+enum Reply<T> {
+    Ok(T),
+    Err(String),
+}
+
+/// Reads the outputs, workspaces and windows.
+async fn fetch(socket: &Path) -> Result<Snapshot> {
+    let Outputs { outputs } = request(socket, &Value::from("Outputs")).await?;
+    let Workspaces { workspaces } = request(socket, &Value::from("Workspaces")).await?;
+    let Windows { windows } = request(socket, &Value::from("Windows")).await?;
+    Ok(snapshot(outputs, &workspaces, &windows))
+}
+
+#[derive(Deserialize)]
+struct Outputs {
+    #[serde(rename = "Outputs")]
+    outputs: HashMap<String, Output>,
+}
+
+#[derive(Deserialize)]
+struct Workspaces {
+    #[serde(rename = "Workspaces")]
+    workspaces: Vec<Workspace>,
+}
+
+#[derive(Deserialize)]
+struct Windows {
+    #[serde(rename = "Windows")]
+    windows: Vec<Window>,
+}
+
+#[derive(Deserialize)]
+struct Output {
+    /// `None` while the output is disabled.
+    logical: Option<LogicalOutput>,
+}
+
+#[derive(Deserialize)]
+struct LogicalOutput {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    scale: f64,
+}
+
+#[derive(Deserialize)]
+struct Workspace {
+    id: u64,
+    /// Position on its output, from 1.
+    idx: i32,
+    output: Option<String>,
+    is_active: bool,
+    is_focused: bool,
+    /// Only reported by niri 25.05 and later.
+    #[serde(default)]
+    is_urgent: bool,
+}
+
+#[derive(Deserialize)]
+struct Window {
+    id: u64,
+    title: Option<String>,
+    app_id: Option<String>,
+    workspace_id: Option<u64>,
+    is_focused: bool,
+    #[serde(default)]
+    is_urgent: bool,
+}
+
+fn snapshot(
+    outputs: HashMap<String, Output>,
+    workspaces: &[Workspace],
+    windows: &[Window],
+) -> Snapshot {
+    let mut statuses: BTreeMap<String, OutputStatus> = outputs
+        .into_iter()
+        .filter_map(|(name, output)| {
+            let logical = output.logical?;
+            let status = OutputStatus {
+                rect: Rect {
+                    x: logical.x,
+                    y: logical.y,
+                    width: logical.width,
+                    height: logical.height,
+                },
+                // Like wl_output, round fractional scales up.
+                scale: Some(logical.scale.ceil() as i32),
+                ..OutputStatus::default()
+            };
+            Some((name, status))
+        })
+        .collect();
+    let mut focused_output = None;
+    for workspace in workspaces {
+        let Some(output) = &workspace.output else {
+            continue;
+        };
+        let Some(status) = statuses.get_mut(output) else {
+            continue;
+        };
+        let tag = workspace_tag(workspace.idx);
+        if workspace.is_active {
+            status.focused_tags |= tag;
+        }
+        if workspace.is_urgent {
+            status.urgent_tags |= tag;
+        }
+        if workspace.is_focused {
+            focused_output = Some(output.clone());
+        }
+    }
+    for window in windows {
+        let Some(workspace) = window
+            .workspace_id
+            .and_then(|id| workspaces.iter().find(|workspace| workspace.id == id))
+        else {
+            continue;
+        };
+        let Some(status) = workspace
+            .output
+            .as_ref()
+            .and_then(|output| statuses.get_mut(output))
+        else {
+            continue;
+        };
+        let tag = workspace_tag(workspace.idx);
+        status.view_tags.push(tag);
+        if window.is_urgent {
+            status.urgent_tags |= tag;
+        }
+    }
+    let focused_view = windows
+        .iter()
+        .find(|window| window.is_focused)
+        .map(|window| FocusedView {
+            title: window.title.clone().unwrap_or_default(),
+            app_id: window.app_id.clone(),
+            view_id: window.id.to_string(),
+        });
+    Snapshot {
+        outputs: statuses,
+        focused_output,
+        focused_view,
+    }
+}
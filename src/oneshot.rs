@@ -0,0 +1,74 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use async_graphql::Schema;
+use serde_json::{Map, Value};
+
+use crate::backend::StatusBackend;
+use crate::gql::{self, AppSchema, MutationRoot, QueryRoot, SubscriptionRoot};
+use crate::{EndpointTarget, client};
+
+/// Everything the bridge knows about the compositor, in one query.
+const QUERY: &str = "{ serverInfo { version backend capabilities { urgentTags seatMode layoutName } } \
+    outputs(orderBy: POSITION) { outputId name focusedTags viewTags urgentTags layoutName \
+        position { x y width height scale } } \
+    seatFocusedOutput { outputId name seat } \
+    seatFocusedView { title appId viewId seat } \
+    seatMode { name seat } }";
+
+/// How long the compositor may stay quiet before its initial state counts
+/// as complete.
+const SETTLE: Duration = Duration::from_millis(200);
+
+/// Where `riverql snapshot` reads the status from.
+pub enum Source {
+    /// A running server.
+    Endpoint(EndpointTarget),
+    /// The compositor itself, with no server involved.
+    Standalone(Arc<dyn StatusBackend>),
+}
+
+/// Prints the current status as one JSON document and returns.
+pub async fn run(source: Source) -> Result<()> {
+    let payload = match source {
+        Source::Endpoint(endpoint) => fetch(endpoint).await?,
+        Source::Standalone(backend) => standalone(backend).await?,
+    };
+    if let Some(message) = payload["errors"][0]["message"].as_str() {
+        bail!("snapshot query failed: {message}");
+    }
+    println!("{}", payload["data"]);
+    Ok(())
+}
+
+async fn fetch(endpoint: EndpointTarget) -> Result<Value> {
+    let mut payload = None;
+    client::subscribe(endpoint, QUERY, &Map::new(), |next| {
+        payload.get_or_insert(next);
+    })
+    .await?;
+    payload.context("the server closed the connection before answering")
+}
+
+/// Connects to the compositor, folds its events into a snapshot until it
+/// has gone quiet for [`SETTLE`], then answers the query from that.
+async fn standalone(backend: Arc<dyn StatusBackend>) -> Result<Value> {
+    let (mut events, protocols) = backend.connect().await?;
+    let state = gql::new_river_state();
+    let log = gql::ChangeLogHandle::default();
+    while let Ok(Some(received)) = tokio::time::timeout(SETTLE, events.recv()).await {
+        let ev = received.event;
+        let seq = log.record(&ev);
+        gql::update_river_state(&state, seq, |snapshot| backend.reduce(snapshot, &ev));
+    }
+    // Only what QUERY reads; there is no server behind this schema.
+    let schema: AppSchema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(state)
+        .data(gql::ServerInfo {
+            backend: backend.kind(),
+            protocols,
+        })
+        .finish();
+    Ok(serde_json::to_value(schema.execute(QUERY).await)?)
+}
@@ -0,0 +1,482 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use anyhow::{Result, bail};
+use crossterm::cursor::MoveToColumn;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::queue;
+use crossterm::terminal::{self, Clear, ClearType};
+use serde_json::{Map, Value};
+
+use crate::{EndpointTarget, client};
+
+const INTROSPECTION: &str = "{ __schema { queryType { name } subscriptionType { name } \
+    types { name possibleTypes { name } \
+    fields { name args { name } type { name ofType { name ofType { name ofType { name } } } } } } } }";
+
+const PROMPT: &str = "riverql> ";
+const CONTINUATION: &str = "     ... ";
+
+/// Runs an interactive prompt against `endpoint`. Operations are sent once
+/// their braces balance; piped input is run the same way without a prompt.
+pub async fn run(endpoint: EndpointTarget) -> Result<()> {
+    let schema = SchemaIndex::fetch(&endpoint).await?;
+    if !io::stdin().is_terminal() {
+        let mut document = String::new();
+        for line in io::stdin().lock().lines() {
+            document.push_str(&line?);
+            document.push('\n');
+            if is_complete(&document) {
+                execute(&endpoint, &document, false).await;
+                document.clear();
+            }
+        }
+        return Ok(());
+    }
+
+    println!("Tab completes fields and arguments, Ctrl-C stops a subscription, Ctrl-D exits.");
+    let mut editor = Editor::default();
+    let mut document = String::new();
+    loop {
+        let prompt = if document.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION
+        };
+        let line = tokio::task::block_in_place(|| {
+            editor.read_line(prompt, |line| schema.complete(&format!("{document}{line}")))
+        })?;
+        match line {
+            Line::Eof if document.is_empty() => return Ok(()),
+            Line::Eof | Line::Interrupted => document.clear(),
+            Line::Text(line) => {
+                if document.is_empty() && matches!(line.trim(), "exit" | "quit") {
+                    return Ok(());
+                }
+                document.push_str(&line);
+                document.push('\n');
+                if is_complete(&document) {
+                    editor.history.push(document.trim_end().replace('\n', " "));
+                    execute(&endpoint, &document, true).await;
+                    document.clear();
+                }
+            }
+        }
+    }
+}
+
+/// Whether `document` has something in it and every brace and paren is closed.
+fn is_complete(document: &str) -> bool {
+    let mut depth = 0i32;
+    let mut in_string = false;
+    let mut prev = '\0';
+    for c in document.chars() {
+        match c {
+            '"' if prev != '\\' => in_string = !in_string,
+            '{' | '(' if !in_string => depth += 1,
+            '}' | ')' if !in_string => depth -= 1,
+            _ => {}
+        }
+        prev = c;
+    }
+    depth <= 0 && !document.trim().is_empty()
+}
+
+/// Prints each result of `document`, indented on a terminal and one line each
+/// otherwise. Subscriptions run until the server completes them or, on a
+/// terminal, until Ctrl-C.
+async fn execute(endpoint: &EndpointTarget, document: &str, interactive: bool) {
+    if let Err(e) = client::validate_document(document) {
+        eprintln!("{e}");
+        return;
+    }
+    let print = |payload: Value| {
+        if interactive {
+            let text = serde_json::to_string_pretty(&payload).unwrap_or_default();
+            println!("{text}");
+        } else {
+            println!("{payload}");
+        }
+    };
+    let variables = Map::new();
+    let run = client::subscribe(endpoint.clone(), document, &variables, print);
+    let result = if interactive {
+        tokio::select! {
+            result = run => result,
+            _ = tokio::signal::ctrl_c() => {
+                eprintln!("stopped");
+                Ok(())
+            }
+        }
+    } else {
+        run.await
+    };
+    if let Err(e) = result {
+        eprintln!("error: {e}");
+    }
+}
+
+/// Field, argument and type names from introspection, for completion.
+struct SchemaIndex {
+    query: String,
+    subscription: Option<String>,
+    /// Fields by parent type name.
+    fields: HashMap<String, Vec<FieldInfo>>,
+    /// Members of unions and implementations of interfaces.
+    possible: HashMap<String, Vec<String>>,
+}
+
+struct FieldInfo {
+    name: String,
+    args: Vec<String>,
+    /// The named type under any list and non-null wrappers.
+    type_name: Option<String>,
+}
+
+impl SchemaIndex {
+    async fn fetch(endpoint: &EndpointTarget) -> Result<Self> {
+        let mut result = None;
+        client::subscribe(endpoint.clone(), INTROSPECTION, &Map::new(), |payload| {
+            result = Some(payload);
+        })
+        .await?;
+        let Some(schema) = result.as_ref().and_then(|p| p.pointer("/data/__schema")) else {
+            bail!("the server did not answer the introspection query");
+        };
+        Ok(Self::from_introspection(schema))
+    }
+
+    fn from_introspection(schema: &Value) -> Self {
+        let name = |value: &Value| value["name"].as_str().map(str::to_string);
+        let mut fields = HashMap::new();
+        let mut possible = HashMap::new();
+        for ty in schema["types"].as_array().into_iter().flatten() {
+            let Some(type_name) = name(ty) else { continue };
+            if let Some(members) = ty["possibleTypes"].as_array() {
+                possible.insert(type_name.clone(), members.iter().filter_map(name).collect());
+            }
+            let Some(list) = ty["fields"].as_array() else {
+                continue;
+            };
+            let infos = list
+                .iter()
+                .filter_map(|field| {
+                    let mut ty = &field["type"];
+                    while ty["name"].is_null() && ty["ofType"].is_object() {
+                        ty = &ty["ofType"];
+                    }
+                    Some(FieldInfo {
+                        name: name(field)?,
+                        args: field["args"]
+                            .as_array()
+                            .into_iter()
+                            .flatten()
+                            .filter_map(name)
+                            .collect(),
+                        type_name: name(ty),
+                    })
+                })
+                .collect();
+            fields.insert(type_name, infos);
+        }
+        Self {
+            query: name(&schema["queryType"]).unwrap_or_else(|| "Query".to_string()),
+            subscription: name(&schema["subscriptionType"]),
+            fields,
+            possible,
+        }
+    }
+
+    fn field(&self, parent: &str, name: &str) -> Option<&FieldInfo> {
+        self.fields.get(parent)?.iter().find(|f| f.name == name)
+    }
+
+    /// Returns the word being typed at the end of `text` and the names that
+    /// could complete it.
+    fn complete(&self, text: &str) -> (String, Vec<String>) {
+        let start = text
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let (before, word) = text.split_at(start);
+        let candidates: BTreeSet<String> = match self.context(before) {
+            Context::Operation => ["query", "subscription"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            Context::Fields(parent) => self
+                .fields
+                .get(&parent)
+                .into_iter()
+                .flatten()
+                .map(|f| f.name.clone())
+                .chain(["__typename".to_string()])
+                .collect(),
+            Context::Args(parent, field) => self
+                .field(&parent, &field)
+                .map(|f| f.args.clone())
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            Context::TypeCondition(parent) => self
+                .possible
+                .get(&parent)
+                .cloned()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            Context::None => BTreeSet::new(),
+        };
+        let matches = candidates
+            .into_iter()
+            .filter(|name| name.starts_with(word))
+            .collect();
+        (word.to_string(), matches)
+    }
+
+    /// Works out what kind of name belongs after `text` by following its
+    /// selection sets from the operation's root type.
+    fn context(&self, text: &str) -> Context {
+        // Enclosing selection sets, by type; `None` when the type is unknown.
+        let mut stack: Vec<Option<String>> = Vec::new();
+        let mut root = self.query.clone();
+        let mut last_field: Option<String> = None;
+        let mut type_condition: Option<String> = None;
+        let mut after_on = false;
+        let mut in_args = false;
+        for token in tokens(text) {
+            let current = stack.last().cloned().flatten();
+            match token {
+                Token::Punct('{') => {
+                    let next = if stack.is_empty() {
+                        Some(root.clone())
+                    } else if let Some(ty) = type_condition.take() {
+                        Some(ty)
+                    } else {
+                        current
+                            .as_deref()
+                            .zip(last_field.as_deref())
+                            .and_then(|(parent, name)| self.field(parent, name)?.type_name.clone())
+                    };
+                    stack.push(next);
+                    last_field = None;
+                }
+                Token::Punct('}') => {
+                    stack.pop();
+                    last_field = None;
+                }
+                Token::Punct('(') => in_args = true,
+                Token::Punct(')') => in_args = false,
+                Token::Punct(_) => {}
+                Token::Name(name) if stack.is_empty() => {
+                    if name == "subscription" {
+                        if let Some(subscription) = &self.subscription {
+                            root = subscription.clone();
+                        }
+                    }
+                }
+                Token::Name(_) if in_args => {}
+                Token::Name(name) if after_on => {
+                    type_condition = Some(name);
+                    after_on = false;
+                }
+                Token::Name(name) if name == "on" => after_on = true,
+                // With `alias: field`, the field is the name after the colon.
+                Token::Name(name) => last_field = Some(name),
+            }
+        }
+        let current = stack.last().cloned().flatten();
+        match (stack.is_empty(), current) {
+            (true, _) => Context::Operation,
+            (false, None) => Context::None,
+            (false, Some(parent)) if after_on => Context::TypeCondition(parent),
+            (false, Some(parent)) if in_args => match last_field {
+                // After a colon comes a value, not an argument name.
+                Some(field) if !text.trim_end().ends_with(':') => Context::Args(parent, field),
+                _ => Context::None,
+            },
+            (false, Some(parent)) => Context::Fields(parent),
+        }
+    }
+}
+
+enum Context {
+    Operation,
+    Fields(String),
+    Args(String, String),
+    TypeCondition(String),
+    None,
+}
+
+enum Token {
+    Name(String),
+    Punct(char),
+}
+
+/// Splits a GraphQL document into names and punctuation, skipping strings
+/// and comments.
+fn tokens(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut prev = c;
+                for next in chars.by_ref() {
+                    if next == '"' && prev != '\\' {
+                        break;
+                    }
+                    prev = next;
+                }
+            }
+            '#' => {
+                for next in chars.by_ref() {
+                    if next == '\n' {
+                        break;
+                    }
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if !(next.is_ascii_alphanumeric() || next == '_') {
+                        break;
+                    }
+                    name.push(next);
+                    chars.next();
+                }
+                tokens.push(Token::Name(name));
+            }
+            '{' | '}' | '(' | ')' => tokens.push(Token::Punct(c)),
+            _ => {}
+        }
+    }
+    tokens
+}
+
+enum Line {
+    Text(String),
+    /// Ctrl-C: drop the operation being typed.
+    Interrupted,
+    /// Ctrl-D on an empty line.
+    Eof,
+}
+
+/// A single-line editor with history and tab completion.
+#[derive(Default)]
+struct Editor {
+    history: Vec<String>,
+}
+
+impl Editor {
+    /// Reads a line in raw mode. `complete` gets the text before the cursor
+    /// and returns the word being completed plus its candidates.
+    fn read_line(
+        &mut self,
+        prompt: &str,
+        complete: impl Fn(&str) -> (String, Vec<String>),
+    ) -> io::Result<Line> {
+        terminal::enable_raw_mode()?;
+        let result = self.edit(prompt, complete);
+        terminal::disable_raw_mode()?;
+        println!();
+        result
+    }
+
+    fn edit(
+        &mut self,
+        prompt: &str,
+        complete: impl Fn(&str) -> (String, Vec<String>),
+    ) -> io::Result<Line> {
+        let mut stdout = io::stdout();
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // Position in history while browsing with the arrow keys.
+        let mut recalled = self.history.len();
+        loop {
+            queue!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine))?;
+            let text: String = line.iter().collect();
+            write!(stdout, "{prompt}{text}")?;
+            queue!(stdout, MoveToColumn((prompt.len() + cursor) as u16))?;
+            stdout.flush()?;
+
+            let Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press,
+                ..
+            }) = event::read()?
+            else {
+                continue;
+            };
+            let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+            match code {
+                KeyCode::Enter => return Ok(Line::Text(text)),
+                KeyCode::Char('c') if ctrl => return Ok(Line::Interrupted),
+                KeyCode::Char('d') if ctrl && line.is_empty() => return Ok(Line::Eof),
+                KeyCode::Char('a') if ctrl => cursor = 0,
+                KeyCode::Char('e') if ctrl => cursor = line.len(),
+                KeyCode::Char('u') if ctrl => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                }
+                KeyCode::Char(_) if ctrl => {}
+                KeyCode::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                KeyCode::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                KeyCode::Delete if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                KeyCode::Left => cursor = cursor.saturating_sub(1),
+                KeyCode::Right => cursor = (cursor + 1).min(line.len()),
+                KeyCode::Home => cursor = 0,
+                KeyCode::End => cursor = line.len(),
+                KeyCode::Up | KeyCode::Down => {
+                    recalled = match code {
+                        KeyCode::Up => recalled.saturating_sub(1),
+                        _ => (recalled + 1).min(self.history.len()),
+                    };
+                    line = self
+                        .history
+                        .get(recalled)
+                        .map_or_else(Vec::new, |entry| entry.chars().collect());
+                    cursor = line.len();
+                }
+                KeyCode::Tab => {
+                    let before: String = line[..cursor].iter().collect();
+                    let (word, candidates) = complete(&before);
+                    let prefix = common_prefix(&candidates);
+                    if prefix.len() > word.len() {
+                        for c in prefix[word.len()..].chars() {
+                            line.insert(cursor, c);
+                            cursor += 1;
+                        }
+                    } else if candidates.len() > 1 {
+                        write!(stdout, "\r\n{}\r\n", candidates.join("  "))?;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn common_prefix(candidates: &[String]) -> &str {
+    let Some(first) = candidates.first() else {
+        return "";
+    };
+    let mut len = first.len();
+    for other in &candidates[1..] {
+        len = first
+            .bytes()
+            .zip(other.bytes())
+            .take(len)
+            .take_while(|(a, b)| a == b)
+            .count();
+    }
+    &first[..len]
+}
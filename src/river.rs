@@ -1,17 +1,29 @@
 use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use anyhow::{Context, anyhow, bail};
+use arc_swap::ArcSwap;
+use futures_util::future::BoxFuture;
 use tokio::sync::{
-    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    mpsc::{self, Receiver, Sender, error::TrySendError},
     oneshot,
 };
+use tracing::{info, warn};
 
 use wayland_client::protocol::{
     wl_output::{self, WlOutput},
     wl_registry,
     wl_registry::WlRegistry,
-    wl_seat::WlSeat,
+    wl_seat::{self, WlSeat},
+};
+use wayland_client::{
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle, delegate_noop, event_created_child,
 };
-use wayland_client::{Connection, Dispatch, EventQueue, Proxy, QueueHandle, delegate_noop};
 
 pub mod river_status {
     use wayland_client;
@@ -24,85 +36,474 @@ pub mod river_status {
     wayland_scanner::generate_client_code!("protocol/river-status-unstable-v1.xml");
 }
 
+pub mod river_control {
+    use wayland_client;
+    use wayland_client::protocol::*;
+    pub mod __interfaces {
+        use wayland_client::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("protocol/river-control-unstable-v1.xml");
+    }
+    use self::__interfaces::*;
+    wayland_scanner::generate_client_code!("protocol/river-control-unstable-v1.xml");
+}
+
+pub mod river_layout {
+    use wayland_client;
+    use wayland_client::protocol::*;
+    pub mod __interfaces {
+        use wayland_client::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("protocol/river-layout-v3.xml");
+    }
+    use self::__interfaces::*;
+    wayland_scanner::generate_client_code!("protocol/river-layout-v3.xml");
+}
+
+pub mod foreign_toplevel {
+    use wayland_client;
+    use wayland_client::protocol::*;
+    pub mod __interfaces {
+        use wayland_client::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!(
+            "protocol/wlr-foreign-toplevel-management-unstable-v1.xml"
+        );
+    }
+    use self::__interfaces::*;
+    wayland_scanner::generate_client_code!(
+        "protocol/wlr-foreign-toplevel-management-unstable-v1.xml"
+    );
+}
+
+pub mod xdg_output {
+    use wayland_client;
+    use wayland_client::protocol::*;
+    pub mod __interfaces {
+        use wayland_client::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("protocol/xdg-output-unstable-v1.xml");
+    }
+    use self::__interfaces::*;
+    wayland_scanner::generate_client_code!("protocol/xdg-output-unstable-v1.xml");
+}
+
+use foreign_toplevel::zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1};
+use foreign_toplevel::zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1};
+use river_control::zriver_command_callback_v1::{self, ZriverCommandCallbackV1};
+use river_control::zriver_control_v1::ZriverControlV1;
+use river_layout::river_layout_manager_v3::RiverLayoutManagerV3;
+use river_layout::river_layout_v3::RiverLayoutV3;
 use river_status::zriver_output_status_v1::ZriverOutputStatusV1;
 use river_status::zriver_seat_status_v1::ZriverSeatStatusV1;
 use river_status::zriver_status_manager_v1::ZriverStatusManagerV1;
 use wayland_backend::client::ObjectId;
+use xdg_output::zxdg_output_manager_v1::ZxdgOutputManagerV1;
+use xdg_output::zxdg_output_v1::{self, ZxdgOutputV1};
 
-#[derive(Debug, Clone)]
+use crate::backend::{Command, StatusBackend};
+use crate::gql;
+
+/// Identifies an output in emitted events. Wraps the Wayland object id for
+/// compositor-backed outputs; other sources mint their own with `synthetic`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OutputId(String);
+
+impl OutputId {
+    pub fn synthetic(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
+}
+
+impl From<&ObjectId> for OutputId {
+    fn from(id: &ObjectId) -> Self {
+        Self(id.to_string())
+    }
+}
+
+impl fmt::Display for OutputId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Event {
     OutputFocusedTags {
-        id: ObjectId,
+        id: OutputId,
         name: Option<String>,
         tags: u32,
     },
     OutputViewTags {
-        id: ObjectId,
+        id: OutputId,
         name: Option<String>,
         tags: Vec<u32>,
     },
     OutputUrgentTags {
-        id: ObjectId,
+        id: OutputId,
         name: Option<String>,
         tags: u32,
     },
     OutputLayoutName {
-        id: ObjectId,
+        id: OutputId,
         name: Option<String>,
         layout: String,
     },
     OutputLayoutNameClear {
-        id: ObjectId,
+        id: OutputId,
         name: Option<String>,
     },
     OutputRemoved {
-        id: ObjectId,
+        id: OutputId,
         name: Option<String>,
     },
+    OutputNamed {
+        id: OutputId,
+        name: String,
+    },
+    /// River asked for a layout of `view_count` views in the usable area.
+    OutputLayoutDemand {
+        id: OutputId,
+        name: Option<String>,
+        view_count: u32,
+        usable_width: u32,
+        usable_height: u32,
+        tags: u32,
+    },
+    /// Placement of the output in the global compositor space. The logical
+    /// size and scale are `None` until the compositor reports them.
+    OutputPosition {
+        id: OutputId,
+        name: Option<String>,
+        x: i32,
+        y: i32,
+        width: Option<i32>,
+        height: Option<i32>,
+        scale: Option<i32>,
+    },
 
+    /// `seat` is the seat's wl_seat name, `None` before wl_seat version 2.
     SeatFocusedOutput {
-        id: ObjectId,
+        id: OutputId,
         name: Option<String>,
+        seat: Option<String>,
     },
     SeatUnfocusedOutput {
-        id: ObjectId,
+        id: OutputId,
         name: Option<String>,
+        seat: Option<String>,
     },
+    /// `app_id` and `view_id` come from foreign-toplevel data and are `None`
+    /// when the compositor doesn't offer it or no toplevel matched.
     SeatFocusedView {
         title: String,
+        app_id: Option<String>,
+        view_id: Option<String>,
+        seat: Option<String>,
     },
     SeatMode {
         name: String,
+        seat: Option<String>,
+    },
+    /// Sent by the server's `--heartbeat` timer, never by river. `at` is in
+    /// milliseconds since the Unix epoch.
+    Heartbeat {
+        seq: u64,
+        at: u64,
     },
 }
 
+impl Event {
+    /// Output the event refers to; `None` for seat-wide events.
+    pub fn output_id(&self) -> Option<&OutputId> {
+        use Event::*;
+        match self {
+            OutputFocusedTags { id, .. }
+            | OutputViewTags { id, .. }
+            | OutputUrgentTags { id, .. }
+            | OutputLayoutName { id, .. }
+            | OutputLayoutNameClear { id, .. }
+            | OutputRemoved { id, .. }
+            | OutputNamed { id, .. }
+            | OutputLayoutDemand { id, .. }
+            | OutputPosition { id, .. }
+            | SeatFocusedOutput { id, .. }
+            | SeatUnfocusedOutput { id, .. } => Some(id),
+            SeatFocusedView { .. } | SeatMode { .. } | Heartbeat { .. } => None,
+        }
+    }
+}
+
+/// A global the bridge bound, at the version both sides agreed on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoundGlobal {
+    pub interface: String,
+    pub version: u32,
+}
+
+/// An event with the moment the bridge got it from the compositor, so the
+/// time it spends inside the bridge can be measured.
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub event: Event,
+    pub at: Instant,
+    /// Events dropped on a full queue just before this one.
+    pub dropped: u64,
+}
+
+impl From<Event> for Received {
+    /// Stamps `event` as received now.
+    fn from(event: Event) -> Self {
+        Self {
+            event,
+            at: Instant::now(),
+            dropped: 0,
+        }
+    }
+}
+
+/// Protocol versions negotiated with the compositor, reported once the
+/// status manager is bound.
+#[derive(Debug, Clone, Default)]
+pub struct Protocols {
+    pub globals: Vec<BoundGlobal>,
+}
+
+impl Protocols {
+    pub const STATUS_MANAGER: &'static str = "zriver_status_manager_v1";
+    pub const LAYOUT_MANAGER: &'static str = "river_layout_manager_v3";
+    pub const TOPLEVEL_MANAGER: &'static str = "zwlr_foreign_toplevel_manager_v1";
+    pub const XDG_OUTPUT_MANAGER: &'static str = "zxdg_output_manager_v1";
+    pub const CONTROL: &'static str = "zriver_control_v1";
+
+    pub fn version(&self, interface: &str) -> Option<u32> {
+        self.globals
+            .iter()
+            .find(|global| global.interface == interface)
+            .map(|global| global.version)
+    }
+
+    /// Bound river-status version; event availability follows from it.
+    pub fn status_version(&self) -> u32 {
+        self.version(Self::STATUS_MANAGER).unwrap_or(0)
+    }
+
+    /// Records the first bind of each interface; repeated globals such as
+    /// additional outputs share the version of the first.
+    fn record(&mut self, interface: &str, version: u32) {
+        if self.version(interface).is_none() {
+            self.globals.push(BoundGlobal {
+                interface: interface.to_string(),
+                version,
+            });
+        }
+    }
+}
+
+/// A global bound from the Wayland registry, as `waylandGlobals` lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryGlobal {
+    pub interface: String,
+    /// The compositor's registry name for the global.
+    pub name: u32,
+    pub version: u32,
+    /// The bridge's object, e.g. `wl_output@12`.
+    pub object_id: String,
+}
+
+/// What the bridge has bound on its current connection.
+#[derive(Debug, Clone, Default)]
+pub struct RegistryView {
+    pub globals: Vec<RegistryGlobal>,
+    /// Live river-status objects; zero of either means river sends nothing
+    /// for outputs or seats.
+    pub output_statuses: usize,
+    pub seat_statuses: usize,
+}
+
+/// Shared with the server; replaced after every registry event.
+pub type RegistryHandle = Arc<ArcSwap<RegistryView>>;
+
+/// How the bridge talks to the compositor.
+#[derive(Debug, Clone, Default)]
+pub struct StatusOptions {
+    /// Register as a river-layout-v3 generator under this namespace to receive
+    /// layout demands. River only sends demands to the generator an output is
+    /// using, so this requires `riverctl default-layout <namespace>`.
+    pub layout_namespace: Option<String>,
+    /// Wayland socket to connect to instead of `WAYLAND_DISPLAY`: a name in
+    /// `XDG_RUNTIME_DIR` or an absolute path.
+    pub display: Option<String>,
+}
+
+/// A toplevel as last reported by foreign-toplevel management.
+#[derive(Debug, Default, Clone)]
+struct Toplevel {
+    title: Option<String>,
+    app_id: Option<String>,
+    activated: bool,
+}
+
+/// Where a river-control command's answer goes; taken by the first event.
+type CommandReply = Mutex<Option<oneshot::Sender<Result<(), String>>>>;
+
+/// River-control on the status connection, usable from the async side.
+#[derive(Clone)]
+pub struct ControlLink {
+    conn: Connection,
+    qh: QueueHandle<State>,
+    control: ZriverControlV1,
+    seat: WlSeat,
+}
+
+/// Set while the compositor offers river-control and a seat.
+pub type ControlHandle = Arc<Mutex<Option<ControlLink>>>;
+
+impl ControlLink {
+    /// Sends every command with a single flush, so river reads them in one
+    /// go and runs them in order. The receivers get each command's answer.
+    fn send(
+        &self,
+        commands: &[Vec<String>],
+    ) -> anyhow::Result<Vec<oneshot::Receiver<Result<(), String>>>> {
+        let mut replies = Vec::new();
+        for args in commands {
+            for arg in args {
+                self.control.add_argument(arg.clone());
+            }
+            let (reply, answer) = oneshot::channel();
+            let reply: CommandReply = Mutex::new(Some(reply));
+            self.control.run_command(&self.seat, &self.qh, reply);
+            replies.push(answer);
+        }
+        self.conn
+            .flush()
+            .context("sending river-control commands")?;
+        Ok(replies)
+    }
+}
+
+/// Layout name committed for demands answered by the bridge.
+const LAYOUT_NAME: &str = "monocle";
+
 struct State {
     outputs: HashMap<u32, WlOutput>,
     seats: HashMap<u32, WlSeat>,
     manager: Option<ZriverStatusManagerV1>,
+    layout_namespace: Option<String>,
+    layout_manager: Option<RiverLayoutManagerV3>,
+    layouts: Vec<RiverLayoutV3>,
+    xdg_output_manager: Option<ZxdgOutputManagerV1>,
+    xdg_outputs: Vec<ZxdgOutputV1>,
+    toplevel_manager: Option<ZwlrForeignToplevelManagerV1>,
+    toplevels: HashMap<ObjectId, Toplevel>,
+    control: Option<ZriverControlV1>,
+    control_link: ControlHandle,
+    /// River's latest focused view title, re-announced with toplevel details.
+    focused_title: Option<String>,
+    /// Seat that reported `focused_title`.
+    focused_seat: Option<String>,
+    /// wl_seat names by registry name.
+    seat_names: HashMap<u32, String>,
+    last_focused_view: Option<Event>,
     output_statuses: Vec<ZriverOutputStatusV1>,
     seat_statuses: Vec<ZriverSeatStatusV1>,
-    tx: UnboundedSender<Event>,
+    tx: EventSender,
     output_info: HashMap<u32, OutputInfo>,
     output_status_owner: HashMap<u32, ObjectId>,
-    ready: Option<oneshot::Sender<()>>,
+    protocols: Protocols,
+    /// Every global bound, kept in sync with `registry`.
+    bound: Vec<RegistryGlobal>,
+    registry: RegistryHandle,
+    /// Set after the initial roundtrip, once every advertised global is bound.
+    synced: bool,
+    ready: Option<oneshot::Sender<Protocols>>,
 }
 
 impl State {
-    fn new(tx: UnboundedSender<Event>, ready: oneshot::Sender<()>) -> Self {
+    fn new(
+        tx: EventSender,
+        ready: oneshot::Sender<Protocols>,
+        options: StatusOptions,
+        registry: RegistryHandle,
+        control_link: ControlHandle,
+    ) -> Self {
+        registry.store(Arc::default());
         Self {
             outputs: HashMap::new(),
             seats: HashMap::new(),
             manager: None,
+            layout_namespace: options.layout_namespace,
+            layout_manager: None,
+            layouts: Vec::new(),
+            xdg_output_manager: None,
+            xdg_outputs: Vec::new(),
+            toplevel_manager: None,
+            toplevels: HashMap::new(),
+            control: None,
+            control_link,
+            focused_title: None,
+            focused_seat: None,
+            seat_names: HashMap::new(),
+            last_focused_view: None,
             output_statuses: Vec::new(),
             seat_statuses: Vec::new(),
             tx,
             output_info: HashMap::new(),
             output_status_owner: HashMap::new(),
+            protocols: Protocols::default(),
+            bound: Vec::new(),
+            registry,
+            synced: false,
             ready: Some(ready),
         }
     }
 
+    /// Signals readiness with the negotiated versions, once both the initial
+    /// globals are known and the status manager is bound.
+    fn report_ready(&mut self) {
+        if !self.synced || self.manager.is_none() {
+            return;
+        }
+        if let Some(sender) = self.ready.take() {
+            let _ = sender.send(self.protocols.clone());
+        }
+    }
+
+    fn record_bound(&mut self, name: u32, id: ObjectId, version: u32) {
+        self.bound.push(RegistryGlobal {
+            interface: id.interface().name.to_string(),
+            name,
+            version,
+            object_id: id.to_string(),
+        });
+    }
+
+    /// Points `control_link` at the control object and the first seat, or
+    /// clears it while either is missing.
+    fn link_control(&self, conn: &Connection, qh: &QueueHandle<Self>) {
+        let seat = self
+            .seats
+            .iter()
+            .min_by_key(|(name, _)| **name)
+            .map(|(_, seat)| seat.clone());
+        let link = self
+            .control
+            .clone()
+            .zip(seat)
+            .map(|(control, seat)| ControlLink {
+                conn: conn.clone(),
+                qh: qh.clone(),
+                control,
+                seat,
+            });
+        *self.control_link.lock().unwrap() = link;
+    }
+
+    fn publish_registry(&self) {
+        self.registry.store(Arc::new(RegistryView {
+            globals: self.bound.clone(),
+            output_statuses: self.output_statuses.len(),
+            seat_statuses: self.seat_statuses.len(),
+        }));
+    }
+
     fn maybe_create_status_for_output(&mut self, qh: &QueueHandle<Self>, out: &WlOutput) {
         if let Some(ref mgr) = self.manager {
             let status = mgr.get_river_output_status(out, qh, ());
@@ -115,13 +516,41 @@ impl State {
         self.output_info.entry(id).or_default();
     }
 
+    fn maybe_create_layout_for_output(&mut self, qh: &QueueHandle<Self>, out: &WlOutput) {
+        if let (Some(mgr), Some(namespace)) = (&self.layout_manager, &self.layout_namespace) {
+            let layout = mgr.get_layout(out, namespace.clone(), qh, out.id());
+            self.layouts.push(layout);
+        }
+    }
+
+    fn maybe_create_xdg_output(&mut self, qh: &QueueHandle<Self>, out: &WlOutput) {
+        if let Some(mgr) = &self.xdg_output_manager {
+            let xdg_output = mgr.get_xdg_output(out, qh, out.id());
+            self.xdg_outputs.push(xdg_output);
+        }
+    }
+
     fn maybe_create_status_for_seat(&mut self, qh: &QueueHandle<Self>, seat: &WlSeat) {
         if let Some(ref mgr) = self.manager {
-            let st = mgr.get_river_seat_status(seat, qh, ());
+            let registry_name = *seat.data::<u32>().expect("seat registry name");
+            let st = mgr.get_river_seat_status(seat, qh, registry_name);
             self.seat_statuses.push(st);
         }
     }
 
+    /// Replaces every river status object. River sends the current state to
+    /// new ones, which restores what a full event queue dropped.
+    fn resync_status(&mut self, qh: &QueueHandle<Self>) {
+        for status in self.output_statuses.drain(..) {
+            status.destroy();
+        }
+        for status in self.seat_statuses.drain(..) {
+            status.destroy();
+        }
+        self.output_status_owner.clear();
+        self.create_status_for_all(qh);
+    }
+
     fn create_status_for_all(&mut self, qh: &QueueHandle<Self>) {
         if self.manager.is_some() {
             let outputs: Vec<_> = self.outputs.values().cloned().collect();
@@ -136,13 +565,86 @@ impl State {
     }
 
     fn update_output_info(&mut self, id: &ObjectId, update: impl FnOnce(&mut OutputInfo)) {
-        let entry = self
-            .output_info
-            .entry(id.protocol_id())
-            .or_insert_with(OutputInfo::default);
+        let entry = self.output_info.entry(id.protocol_id()).or_default();
         update(entry);
     }
 
+    /// Emits `OutputNamed` once the output's metadata settles on a new label,
+    /// so consumers can fix up entries recorded before the name was known.
+    fn announce_output_label(&mut self, id: &ObjectId) {
+        let Some(info) = self.output_info.get_mut(&id.protocol_id()) else {
+            return;
+        };
+        let label = info.label();
+        if label.is_none() || label == info.announced_label {
+            return;
+        }
+        info.announced_label = label.clone();
+        if let Some(name) = label {
+            self.tx.send(Event::OutputNamed {
+                id: id.into(),
+                name,
+            });
+        }
+    }
+
+    /// Emits `OutputPosition` when the output's logical position, size or
+    /// scale changed since it was last announced.
+    fn announce_output_position(&mut self, id: &ObjectId) {
+        let Some(info) = self.output_info.get_mut(&id.protocol_id()) else {
+            return;
+        };
+        let Some((x, y)) = info.position else {
+            return;
+        };
+        let geometry = (info.position, info.size, info.scale);
+        if info.announced_geometry == Some(geometry) {
+            return;
+        }
+        info.announced_geometry = Some(geometry);
+        let name = info.label();
+        self.tx.send(Event::OutputPosition {
+            id: id.into(),
+            name,
+            x,
+            y,
+            width: info.size.map(|(width, _)| width),
+            height: info.size.map(|(_, height)| height),
+            scale: info.scale,
+        });
+    }
+
+    /// River's seat status only names the focused view by title; pair it with
+    /// the activated toplevel, preferring one whose title agrees.
+    fn focused_toplevel(&self, title: &str) -> Option<(&ObjectId, &Toplevel)> {
+        let mut activated = self.toplevels.iter().filter(|(_, t)| t.activated);
+        let first = activated.clone().next();
+        activated
+            .find(|(_, t)| t.title.as_deref() == Some(title))
+            .or(first)
+    }
+
+    /// Emits `SeatFocusedView` for river's current title. Toplevel updates
+    /// only re-announce when they change what was last sent; river's own
+    /// focus events always go out, since two views can share a title.
+    fn announce_focused_view(&mut self, force: bool) {
+        let Some(title) = self.focused_title.clone() else {
+            return;
+        };
+        let toplevel = self.focused_toplevel(&title);
+        let event = Event::SeatFocusedView {
+            app_id: toplevel.and_then(|(_, t)| t.app_id.clone()),
+            view_id: toplevel.map(|(id, _)| id.to_string()),
+            title,
+            seat: self.focused_seat.clone(),
+        };
+        if !force && self.last_focused_view.as_ref() == Some(&event) {
+            return;
+        }
+        self.last_focused_view = Some(event.clone());
+        self.tx.send(event);
+    }
+
     fn output_label(&self, id: &ObjectId) -> Option<String> {
         self.output_info
             .get(&id.protocol_id())
@@ -150,12 +652,99 @@ impl State {
     }
 }
 
+/// Capacity of the queue between the Wayland dispatch thread and the async side.
+pub const EVENT_QUEUE_CAPACITY: usize = 1024;
+
+/// How long the dispatch thread waits for room in a full queue before dropping.
+const EVENT_QUEUE_OVERFLOW_WAIT: Duration = Duration::from_millis(50);
+
+/// Bounded event queue sender used by the Wayland dispatch thread.
+///
+/// When the queue fills up the dispatch thread blocks once, for at most
+/// `EVENT_QUEUE_OVERFLOW_WAIT`. Events that don't fit are then dropped and
+/// counted without waiting until the queue has room again, so a stalled
+/// consumer can neither grow memory nor hold up the Wayland connection.
+///
+/// The first event queued after a drop carries the count, and the dispatch
+/// thread then asks river to resend its status, since the dropped events
+/// are never repeated otherwise.
+struct EventSender {
+    tx: Sender<Received>,
+    /// Runs the bounded wait; the dispatch thread is outside the runtime.
+    runtime: tokio::runtime::Handle,
+    dropped: u64,
+    /// Set once the queue takes events again after dropping some.
+    resync: bool,
+}
+
+impl EventSender {
+    fn new(tx: Sender<Received>, runtime: tokio::runtime::Handle) -> Self {
+        Self {
+            tx,
+            runtime,
+            dropped: 0,
+            resync: false,
+        }
+    }
+
+    /// Queues `event`, stamped with the time of the dispatch that produced it.
+    fn send(&mut self, event: Event) {
+        let received = Received {
+            dropped: self.dropped,
+            ..event.into()
+        };
+        let event = match self.tx.try_send(received) {
+            Ok(()) => return self.report_recovered(),
+            Err(TrySendError::Closed(_)) => return,
+            Err(TrySendError::Full(event)) => event,
+        };
+        if self.dropped == 0 {
+            // The timer has to be created inside the runtime, not on this thread.
+            let wait = async {
+                tokio::time::timeout(EVENT_QUEUE_OVERFLOW_WAIT, self.tx.send(event)).await
+            };
+            // A closed queue has nobody left to warn.
+            if let Ok(_sent_or_closed) = self.runtime.block_on(wait) {
+                return;
+            }
+            warn!("river event queue is full; dropping events");
+        }
+        self.dropped += 1;
+    }
+
+    fn report_recovered(&mut self) {
+        if self.dropped > 0 {
+            warn!(
+                dropped = self.dropped,
+                "river event queue recovered; resyncing"
+            );
+            self.dropped = 0;
+            self.resync = true;
+        }
+    }
+
+    /// Whether river's status has to be resent, clearing the request.
+    fn take_resync(&mut self) -> bool {
+        std::mem::take(&mut self.resync)
+    }
+}
+
+/// (position, size, scale) as last announced.
+type OutputGeometry = (Option<(i32, i32)>, Option<(i32, i32)>, Option<i32>);
+
 #[derive(Debug, Default, Clone)]
 struct OutputInfo {
     name: Option<String>,
     description: Option<String>,
     make: Option<String>,
     model: Option<String>,
+    announced_label: Option<String>,
+    /// Logical position and size from xdg-output.
+    position: Option<(i32, i32)>,
+    size: Option<(i32, i32)>,
+    /// From wl_output, which sends it from version 2.
+    scale: Option<i32>,
+    announced_geometry: Option<OutputGeometry>,
 }
 
 impl OutputInfo {
@@ -186,7 +775,7 @@ impl Dispatch<WlRegistry, ()> for State {
         registry: &WlRegistry,
         event: wl_registry::Event,
         _: &(),
-        _conn: &Connection,
+        conn: &Connection,
         qh: &QueueHandle<Self>,
     ) {
         match event {
@@ -197,21 +786,69 @@ impl Dispatch<WlRegistry, ()> for State {
             } => match interface.as_str() {
                 "wl_output" => {
                     let output = registry.bind::<WlOutput, _, _>(name, version.min(4), qh, ());
+                    state.protocols.record(&interface, output.version());
+                    state.record_bound(name, output.id(), output.version());
                     state.maybe_create_status_for_output(qh, &output);
+                    state.maybe_create_layout_for_output(qh, &output);
+                    state.maybe_create_xdg_output(qh, &output);
                     state.outputs.insert(name, output);
                 }
                 "wl_seat" => {
-                    let seat = registry.bind::<WlSeat, _, _>(name, version.min(5), qh, ());
+                    let seat = registry.bind::<WlSeat, _, _>(name, version.min(5), qh, name);
+                    state.protocols.record(&interface, seat.version());
+                    state.record_bound(name, seat.id(), seat.version());
                     state.maybe_create_status_for_seat(qh, &seat);
                     state.seats.insert(name, seat);
+                    state.link_control(conn, qh);
+                }
+                Protocols::CONTROL => {
+                    let control =
+                        registry.bind::<ZriverControlV1, _, _>(name, version.min(1), qh, ());
+                    state.protocols.record(&interface, control.version());
+                    state.record_bound(name, control.id(), control.version());
+                    state.control = Some(control);
+                    state.link_control(conn, qh);
                 }
-                "zriver_status_manager_v1" => {
+                Protocols::STATUS_MANAGER => {
                     let mgr =
                         registry.bind::<ZriverStatusManagerV1, _, _>(name, version.min(4), qh, ());
+                    state.protocols.record(&interface, mgr.version());
+                    state.record_bound(name, mgr.id(), mgr.version());
                     state.manager = Some(mgr);
                     state.create_status_for_all(qh);
-                    if let Some(sender) = state.ready.take() {
-                        let _ = sender.send(());
+                    state.report_ready();
+                }
+                Protocols::XDG_OUTPUT_MANAGER => {
+                    let mgr =
+                        registry.bind::<ZxdgOutputManagerV1, _, _>(name, version.min(3), qh, ());
+                    state.protocols.record(&interface, mgr.version());
+                    state.record_bound(name, mgr.id(), mgr.version());
+                    state.xdg_output_manager = Some(mgr);
+                    let outputs: Vec<_> = state.outputs.values().cloned().collect();
+                    for output in &outputs {
+                        state.maybe_create_xdg_output(qh, output);
+                    }
+                }
+                Protocols::TOPLEVEL_MANAGER => {
+                    let mgr = registry.bind::<ZwlrForeignToplevelManagerV1, _, _>(
+                        name,
+                        version.min(3),
+                        qh,
+                        (),
+                    );
+                    state.protocols.record(&interface, mgr.version());
+                    state.record_bound(name, mgr.id(), mgr.version());
+                    state.toplevel_manager = Some(mgr);
+                }
+                Protocols::LAYOUT_MANAGER if state.layout_namespace.is_some() => {
+                    let mgr =
+                        registry.bind::<RiverLayoutManagerV3, _, _>(name, version.min(2), qh, ());
+                    state.protocols.record(&interface, mgr.version());
+                    state.record_bound(name, mgr.id(), mgr.version());
+                    state.layout_manager = Some(mgr);
+                    let outputs: Vec<_> = state.outputs.values().cloned().collect();
+                    for output in &outputs {
+                        state.maybe_create_layout_for_output(qh, output);
                     }
                 }
                 _ => {}
@@ -219,10 +856,21 @@ impl Dispatch<WlRegistry, ()> for State {
             wl_registry::Event::GlobalRemove { name } => {
                 if !state.remove_output(name) {
                     state.seats.remove(&name);
+                    state.seat_names.remove(&name);
+                    state.seat_statuses.retain(|status| {
+                        let owned = status.data::<u32>() == Some(&name);
+                        if owned {
+                            status.destroy();
+                        }
+                        !owned
+                    });
+                    state.link_control(conn, qh);
                 }
+                state.bound.retain(|global| global.name != name);
             }
             _ => {}
         }
+        state.publish_registry();
     }
 }
 
@@ -249,6 +897,36 @@ impl Dispatch<WlOutput, ()> for State {
                     info.model = Some(model);
                 });
             }
+            wl_output::Event::Scale { factor } => {
+                state.update_output_info(&id, |info| info.scale = Some(factor));
+            }
+            wl_output::Event::Done => {
+                state.announce_output_label(&id);
+                state.announce_output_position(&id);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, ObjectId> for State {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZxdgOutputV1,
+        event: zxdg_output_v1::Event,
+        output_id: &ObjectId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                state.update_output_info(output_id, |info| info.position = Some((x, y)));
+            }
+            zxdg_output_v1::Event::LogicalSize { width, height } => {
+                state.update_output_info(output_id, |info| info.size = Some((width, height)));
+            }
+            // Version 3 replaced this with wl_output.done.
+            zxdg_output_v1::Event::Done => state.announce_output_position(output_id),
             _ => {}
         }
     }
@@ -272,9 +950,10 @@ impl Dispatch<ZriverOutputStatusV1, ()> for State {
             return;
         };
         let label = state.output_label(&output_id);
+        let output_id = OutputId::from(&output_id);
         match event {
             E::FocusedTags { tags } => {
-                let _ = state.tx.send(Event::OutputFocusedTags {
+                state.tx.send(Event::OutputFocusedTags {
                     id: output_id,
                     name: label,
                     tags,
@@ -282,28 +961,28 @@ impl Dispatch<ZriverOutputStatusV1, ()> for State {
             }
             E::ViewTags { tags } => {
                 let parsed = parse_u32_array(&tags);
-                let _ = state.tx.send(Event::OutputViewTags {
+                state.tx.send(Event::OutputViewTags {
                     id: output_id,
                     name: label,
                     tags: parsed,
                 });
             }
             E::UrgentTags { tags } => {
-                let _ = state.tx.send(Event::OutputUrgentTags {
+                state.tx.send(Event::OutputUrgentTags {
                     id: output_id,
                     name: label,
                     tags,
                 });
             }
             E::LayoutName { name } => {
-                let _ = state.tx.send(Event::OutputLayoutName {
+                state.tx.send(Event::OutputLayoutName {
                     id: output_id,
                     name: label,
                     layout: name,
                 });
             }
             E::LayoutNameClear => {
-                let _ = state.tx.send(Event::OutputLayoutNameClear {
+                state.tx.send(Event::OutputLayoutNameClear {
                     id: output_id,
                     name: label,
                 });
@@ -312,41 +991,195 @@ impl Dispatch<ZriverOutputStatusV1, ()> for State {
     }
 }
 
-impl Dispatch<ZriverSeatStatusV1, ()> for State {
+impl Dispatch<WlSeat, u32> for State {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlSeat,
+        event: wl_seat::Event,
+        registry_name: &u32,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wl_seat::Event::Name { name } = event {
+            state.seat_names.insert(*registry_name, name);
+        }
+    }
+}
+
+impl Dispatch<ZriverSeatStatusV1, u32> for State {
     fn event(
         state: &mut Self,
         _proxy: &ZriverSeatStatusV1,
         event: river_status::zriver_seat_status_v1::Event,
-        _: &(),
+        seat_registry_name: &u32,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
     ) {
         use river_status::zriver_seat_status_v1::Event as E;
+        let seat = state.seat_names.get(seat_registry_name).cloned();
         match event {
             E::FocusedOutput { output } => {
-                let id = output.id();
-                let label = state.output_label(&id);
-                let _ = state.tx.send(Event::SeatFocusedOutput { id, name: label });
+                let label = state.output_label(&output.id());
+                let id = OutputId::from(&output.id());
+                state.tx.send(Event::SeatFocusedOutput {
+                    id,
+                    name: label,
+                    seat,
+                });
             }
             E::UnfocusedOutput { output } => {
-                let id = output.id();
-                let label = state.output_label(&id);
-                let _ = state
-                    .tx
-                    .send(Event::SeatUnfocusedOutput { id, name: label });
+                let label = state.output_label(&output.id());
+                let id = OutputId::from(&output.id());
+                state.tx.send(Event::SeatUnfocusedOutput {
+                    id,
+                    name: label,
+                    seat,
+                });
             }
             E::FocusedView { title } => {
-                let _ = state.tx.send(Event::SeatFocusedView { title });
+                state.focused_title = Some(title);
+                state.focused_seat = seat;
+                state.announce_focused_view(true);
             }
             E::Mode { name } => {
-                let _ = state.tx.send(Event::SeatMode { name });
+                state.tx.send(Event::SeatMode { name, seat });
             }
         }
     }
 }
 
-delegate_noop!(State: ignore WlSeat);
+impl Dispatch<RiverLayoutV3, ObjectId> for State {
+    fn event(
+        state: &mut Self,
+        layout: &RiverLayoutV3,
+        event: river_layout::river_layout_v3::Event,
+        output_id: &ObjectId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        use river_layout::river_layout_v3::Event as E;
+        match event {
+            E::NamespaceInUse => {
+                warn!(
+                    namespace = state.layout_namespace.as_deref().unwrap_or_default(),
+                    "layout namespace is taken by another generator; layout demands unavailable"
+                );
+                layout.destroy();
+                state.layouts.retain(|l| l.id() != layout.id());
+            }
+            E::LayoutDemand {
+                view_count,
+                usable_width,
+                usable_height,
+                tags,
+                serial,
+            } => {
+                let label = state.output_label(output_id);
+                state.tx.send(Event::OutputLayoutDemand {
+                    id: OutputId::from(output_id),
+                    name: label,
+                    view_count,
+                    usable_width,
+                    usable_height,
+                    tags,
+                });
+                // River waits for an answer to every demand; give each view
+                // the whole usable area.
+                for _ in 0..view_count {
+                    layout.push_view_dimensions(0, 0, usable_width, usable_height, serial);
+                }
+                layout.commit(LAYOUT_NAME.to_string(), serial);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZwlrForeignToplevelManagerV1,
+        event: zwlr_foreign_toplevel_manager_v1::Event,
+        _: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } => {
+                state.toplevels.insert(toplevel.id(), Toplevel::default());
+            }
+            zwlr_foreign_toplevel_manager_v1::Event::Finished => {
+                state.toplevel_manager = None;
+                state.toplevels.clear();
+            }
+        }
+    }
+
+    event_created_child!(State, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        handle: &ZwlrForeignToplevelHandleV1,
+        event: zwlr_foreign_toplevel_handle_v1::Event,
+        _: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        use zwlr_foreign_toplevel_handle_v1::Event as E;
+        let id = handle.id();
+        match event {
+            E::Closed => {
+                state.toplevels.remove(&id);
+                handle.destroy();
+                state.announce_focused_view(false);
+                return;
+            }
+            E::Done => {
+                state.announce_focused_view(false);
+                return;
+            }
+            _ => {}
+        }
+        let toplevel = state.toplevels.entry(id).or_default();
+        match event {
+            E::Title { title } => toplevel.title = Some(title),
+            E::AppId { app_id } => toplevel.app_id = Some(app_id),
+            E::State { state: states } => {
+                let activated = u32::from(zwlr_foreign_toplevel_handle_v1::State::Activated);
+                toplevel.activated = parse_u32_array(&states).contains(&activated);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZriverCommandCallbackV1, CommandReply> for State {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZriverCommandCallbackV1,
+        event: zriver_command_callback_v1::Event,
+        reply: &CommandReply,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let result = match event {
+            zriver_command_callback_v1::Event::Success { .. } => Ok(()),
+            zriver_command_callback_v1::Event::Failure { failure_message } => Err(failure_message),
+        };
+        if let Some(reply) = reply.lock().unwrap().take() {
+            let _ = reply.send(result);
+        }
+    }
+}
+
 delegate_noop!(State: ignore ZriverStatusManagerV1);
+delegate_noop!(State: ignore ZriverControlV1);
+delegate_noop!(State: ignore RiverLayoutManagerV3);
+delegate_noop!(State: ignore ZxdgOutputManagerV1);
 
 fn parse_u32_array(bytes: &[u8]) -> Vec<u32> {
     let mut v = Vec::new();
@@ -378,8 +1211,25 @@ impl State {
             .retain(|status_id, _| !removed_status_ids.contains(status_id));
         self.output_statuses
             .retain(|status| !removed_status_ids.contains(&status.id().protocol_id()));
+        self.layouts.retain(|layout| {
+            let owned = layout.data::<ObjectId>() == Some(&id);
+            if owned {
+                layout.destroy();
+            }
+            !owned
+        });
+        self.xdg_outputs.retain(|xdg_output| {
+            let owned = xdg_output.data::<ObjectId>() == Some(&id);
+            if owned {
+                xdg_output.destroy();
+            }
+            !owned
+        });
         self.output_info.remove(&protocol_id);
-        let _ = self.tx.send(Event::OutputRemoved { id, name: label });
+        self.tx.send(Event::OutputRemoved {
+            id: OutputId::from(&id),
+            name: label,
+        });
         true
     }
 }
@@ -387,13 +1237,26 @@ impl State {
 pub struct RiverStatus;
 
 impl RiverStatus {
-    pub fn subscribe()
-    -> Result<(UnboundedReceiver<Event>, oneshot::Receiver<()>), Box<dyn std::error::Error>> {
-        let conn = Connection::connect_to_env()?;
-        let (tx, rx) = mpsc::unbounded_channel();
+    pub fn subscribe(
+        options: StatusOptions,
+        registry: RegistryHandle,
+        control_link: ControlHandle,
+    ) -> Result<(Receiver<Received>, oneshot::Receiver<Protocols>), Box<dyn std::error::Error>>
+    {
+        let conn = match &options.display {
+            Some(display) => connect_to_display(display)?,
+            None => Connection::connect_to_env()?,
+        };
+        let (tx, rx) = mpsc::channel(EVENT_QUEUE_CAPACITY);
         let (ready_tx, ready_rx) = oneshot::channel();
 
-        let mut state = State::new(tx, ready_tx);
+        let mut state = State::new(
+            EventSender::new(tx, tokio::runtime::Handle::current()),
+            ready_tx,
+            options,
+            registry,
+            control_link,
+        );
         let mut event_queue: EventQueue<State> = conn.new_event_queue();
         let qh = event_queue.handle();
 
@@ -401,6 +1264,8 @@ impl RiverStatus {
         let _registry = display.get_registry(&qh, ());
 
         event_queue.roundtrip(&mut state)?;
+        state.synced = true;
+        state.report_ready();
 
         std::thread::spawn(move || {
             let mut blocking_queue = event_queue;
@@ -408,9 +1273,128 @@ impl RiverStatus {
                 if let Err(_e) = blocking_queue.blocking_dispatch(&mut state) {
                     break;
                 }
+                if state.tx.take_resync() {
+                    state.resync_status(&blocking_queue.handle());
+                }
             }
+            // Commands waiting for an answer fail once the connection goes.
+            state.control_link.lock().unwrap().take();
         });
 
         Ok((rx, ready_rx))
     }
 }
+
+/// River over the Wayland status protocols.
+pub struct RiverBackend {
+    options: StatusOptions,
+    registry: RegistryHandle,
+    control: ControlHandle,
+}
+
+impl RiverBackend {
+    pub fn new(options: StatusOptions) -> Self {
+        Self {
+            options,
+            registry: RegistryHandle::default(),
+            control: ControlHandle::default(),
+        }
+    }
+}
+
+impl StatusBackend for RiverBackend {
+    fn kind(&self) -> gql::Backend {
+        gql::Backend::River
+    }
+
+    fn connect(&self) -> BoxFuture<'_, anyhow::Result<(Receiver<Received>, Protocols)>> {
+        Box::pin(async move {
+            info!(display = ?self.options.display, "connecting to river status stream");
+            let (river_rx, river_ready) = RiverStatus::subscribe(
+                self.options.clone(),
+                self.registry.clone(),
+                self.control.clone(),
+            )
+            .map_err(|e| anyhow!(e.to_string()))?;
+            let protocols = river_ready
+                .await
+                .map_err(|e| anyhow!("river status initialization failed: {}", e))?;
+            info!(
+                version = protocols.status_version(),
+                "river status stream connected"
+            );
+            warn_missing_capabilities(protocols.status_version());
+            Ok((river_rx, protocols))
+        })
+    }
+
+    fn registry(&self) -> Option<RegistryHandle> {
+        Some(self.registry.clone())
+    }
+
+    /// Runs the `riverctl` equivalents over river-control on the status
+    /// connection.
+    fn execute(&self, command: &Command) -> BoxFuture<'_, anyhow::Result<()>> {
+        let mut commands = Vec::new();
+        match command {
+            Command::SetFocusedTags {
+                tags,
+                output,
+                focused,
+            } => {
+                if let Some(output) = output {
+                    commands.push(vec!["focus-output".to_string(), output.clone()]);
+                }
+                commands.push(vec!["set-focused-tags".to_string(), tags.to_string()]);
+                if let Some(focused) = focused {
+                    commands.push(vec!["focus-output".to_string(), focused.clone()]);
+                }
+            }
+        }
+        // Held while sending, so the arguments of concurrent commands never
+        // interleave.
+        let sent = match &*self.control.lock().unwrap() {
+            Some(link) => link.send(&commands),
+            None => Err(anyhow!("river does not offer {}", Protocols::CONTROL)),
+        };
+        Box::pin(async move {
+            for (args, answer) in commands.iter().zip(sent?) {
+                match answer.await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(message)) => bail!("river refused `{}`: {message}", args.join(" ")),
+                    Err(_) => bail!("river closed the connection before answering"),
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+fn warn_missing_capabilities(status_version: u32) {
+    let missing: Vec<&str> = [(2, "urgentTags"), (3, "seatMode"), (4, "layoutName")]
+        .into_iter()
+        .filter(|(since, _)| status_version < *since)
+        .map(|(_, field)| field)
+        .collect();
+    if !missing.is_empty() {
+        warn!(
+            version = status_version,
+            unavailable = missing.join(", "),
+            "river status protocol is older than v4; these fields will stay null"
+        );
+    }
+}
+
+/// Resolves `display` the way `WAYLAND_DISPLAY` is resolved.
+fn connect_to_display(display: &str) -> Result<Connection, Box<dyn std::error::Error>> {
+    let path = Path::new(display);
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        let runtime_dir = env::var_os("XDG_RUNTIME_DIR").ok_or("XDG_RUNTIME_DIR is not set")?;
+        Path::new(&runtime_dir).join(display)
+    };
+    let stream =
+        UnixStream::connect(&path).map_err(|e| format!("connecting to {}: {e}", path.display()))?;
+    Ok(Connection::from_socket(stream)?)
+}
@@ -0,0 +1,73 @@
+use std::path::Path;
+
+use anyhow::{Result, anyhow, bail};
+use rhai::{AST, CallFnOptions, Dynamic, Engine, Map, Scope};
+use serde_json::Value;
+
+use crate::format;
+
+/// The function every result is handed to.
+const HANDLER: &str = "on_event";
+
+/// A rhai script that turns each client-mode result into the line to print.
+///
+/// `on_event(event)` gets the result's data, unwrapped like `--format shell`
+/// does, so `events { ... }` subscriptions see the event itself. A string is
+/// printed as is, `()` drops the result and any other value is printed as
+/// JSON. `this` is a map kept across calls for counters and other state.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Dynamic,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut engine = Engine::new();
+        // stdout carries the results; script output goes next to the logs.
+        engine.on_print(|text| eprintln!("{text}"));
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| anyhow!("loading script {}: {e}", path.display()))?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == HANDLER && f.params.len() == 1)
+        {
+            bail!("script {} must define fn {HANDLER}(event)", path.display());
+        }
+        // Top-level statements run once, before the first result.
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| anyhow!("running script {}: {e}", path.display()))?;
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            state: Dynamic::from_map(Map::new()),
+        })
+    }
+
+    /// What to print for `payload`, or `None` to drop it.
+    pub fn render(&mut self, payload: &Value) -> Result<Option<String>> {
+        let event = rhai::serde::to_dynamic(format::unwrapped_data(payload))
+            .map_err(|e| anyhow!("passing the result to {HANDLER}: {e}"))?;
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        let output: Dynamic = self
+            .engine
+            .call_fn_with_options(options, &mut self.scope, &self.ast, HANDLER, (event,))
+            .map_err(|e| anyhow!("{HANDLER} failed: {e}"))?;
+        if output.is_unit() {
+            return Ok(None);
+        }
+        if output.is_string() {
+            return Ok(Some(output.to_string()));
+        }
+        let json: Value = rhai::serde::from_dynamic(&output)
+            .map_err(|e| anyhow!("{HANDLER} returned {}: {e}", output.type_name()))?;
+        Ok(Some(json.to_string()))
+    }
+}
@@ -1,90 +1,525 @@
 use crate::{
     ListenTarget,
-    gql::{self, AppSchema, QueryRoot, SubscriptionRoot},
-    river,
+    backend::StatusBackend,
+    cache::CachingExecutor,
+    discovery::Discovery,
+    dispatch::Dispatcher,
+    gql::{self, AppSchema, MutationRoot, QueryRoot, SubscriptionRoot},
+    live::LiveExecutor,
+    metrics::MetricsHandle,
+    river, sink, ws,
 };
-use anyhow::{Result, anyhow};
-use async_graphql::{EmptyMutation, Schema};
+use anyhow::Result;
+use async_graphql::Schema;
 use async_graphql_axum::{GraphQL, GraphQLSubscription};
 use axum::{
     Router,
-    extract::State,
+    extract::{MatchedPath, Request, State},
     http::{self, header},
-    response::Html,
-    routing::{get, get_service},
+    middleware::{self, Next},
+    response::{Html, Response},
+    routing::get,
 };
-use tokio::sync::broadcast;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::{broadcast, mpsc};
+use tower::ServiceExt;
 use tracing::{debug, info, warn};
 
+use std::collections::{HashMap, HashSet};
 #[cfg(unix)]
 use std::fs;
+use std::mem::{self, Discriminant};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub async fn run(listen: ListenTarget) -> Result<()> {
-    let (tx, _rx) = broadcast::channel::<river::Event>(1024);
+pub struct ServerOptions {
+    pub listen: ListenTarget,
+    /// Drop events identical to the previous one for the same output and type.
+    pub dedup: bool,
+    /// Where the root fields' events come from: a compositor or the mock.
+    pub backend: Arc<dyn StatusBackend>,
+    /// How long identical queries are answered from cache; zero disables it.
+    pub cache_ttl: Duration,
+    /// Extra compositors served under `display(name:)`.
+    pub displays: Vec<(String, Arc<dyn StatusBackend>)>,
+    /// Idle timeout and operation cap for each websocket connection.
+    pub ws_limits: ws::ConnectionLimits,
+    /// Negotiate `permessage-deflate` on GraphQL websockets.
+    pub ws_deflate: bool,
+    /// Unix datagram sockets that receive every event as JSON.
+    pub datagrams: Vec<PathBuf>,
+    /// Also log every event to the systemd journal.
+    pub journal: bool,
+    /// Shell commands that get every event as a JSON line on stdin.
+    pub pipes: Vec<String>,
+    /// Broadcast a `Heartbeat` event this often.
+    pub heartbeat: Option<Duration>,
+    /// What subscription events carry beyond the compositor's own fields,
+    /// and which types are suppressed.
+    pub events: gql::EventOptions,
+    /// Serve `waylandGlobals`.
+    pub debug: bool,
+}
+
+#[derive(Clone)]
+struct AppState {
+    schema: AppSchema,
+    queries: CachingExecutor,
+    live: LiveExecutor,
+    metrics: MetricsHandle,
+    ws_limits: ws::ConnectionLimits,
+    ws_deflate: bool,
+}
+
+pub async fn run(options: ServerOptions) -> Result<()> {
+    let ServerOptions {
+        listen,
+        dedup,
+        backend,
+        cache_ttl,
+        displays: display_targets,
+        ws_limits,
+        ws_deflate,
+        datagrams,
+        journal,
+        pipes,
+        heartbeat,
+        events,
+        debug,
+    } = options;
+    let (river_rx, info) = connect(backend.as_ref()).await?;
+
+    let tx = Dispatcher::default();
+    let _rx = tx.subscribe();
     let river_state = gql::new_river_state();
-    let schema: AppSchema = Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+    let changes_log = gql::ChangeLogHandle::default();
+    let metrics = MetricsHandle::default();
+
+    // Live queries may read any display, so with extra displays they
+    // re-run on a channel every display feeds.
+    let changes = if display_targets.is_empty() {
+        None
+    } else {
+        Some(broadcast::channel::<river::Event>(1024).0)
+    };
+    let mut displays = gql::Displays::new();
+    let mut display_rxs = Vec::new();
+    // Like `_rx` above, keeps each channel open while nobody is subscribed.
+    let mut idle_rxs = Vec::new();
+    for (name, display_backend) in display_targets {
+        info!(display = %name, "connecting to extra display");
+        let (display_rx, info) = connect(display_backend.as_ref()).await?;
+        let events = Dispatcher::default();
+        idle_rxs.push(events.subscribe());
+        let source = gql::DisplaySource {
+            state: gql::new_river_state(),
+            changes: gql::ChangeLogHandle::default(),
+            events,
+            info,
+            backend: display_backend.clone(),
+        };
+        display_rxs.push((display_rx, display_backend, source.clone()));
+        displays.insert(name, source);
+    }
+
+    let suppress = events.suppress.clone();
+    if !suppress.is_empty() {
+        info!(count = suppress.len(), "suppressing event types");
+    }
+    let mut schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot);
+    if debug {
+        info!("serving waylandGlobals for debugging");
+        schema = schema.data(gql::DebugRegistry(backend.registry()));
+    }
+    let schema: AppSchema = schema
         .data(tx.clone())
         .data(river_state.clone())
+        .data(changes_log.clone())
+        .data(info)
+        .data(metrics.clone())
+        .data(displays)
+        .data(events)
+        .data(backend.clone())
         .finish();
 
-    info!("connecting to river status stream");
-    let (mut river_rx, river_ready) =
-        river::RiverStatus::subscribe().map_err(|e| anyhow!(e.to_string()))?;
-    river_ready
-        .await
-        .map_err(|e| anyhow!("river status initialization failed: {}", e))?;
-    info!("river status stream connected");
-    let tx_for_events = tx.clone();
-    let state_for_events = river_state.clone();
-    tokio::spawn(async move {
-        while let Some(ev) = river_rx.recv().await {
-            gql::update_river_state(&state_for_events, &ev);
-            match tx_for_events.send(ev.clone()) {
-                Ok(_) => debug!(?ev, "river event broadcasted"),
-                Err(e) => warn!("failed to broadcast river event: {}", e),
-            }
-        }
-    });
+    let pump = EventPump {
+        dedup,
+        suppress,
+        changes: changes.clone(),
+        metrics: metrics.clone(),
+    };
+    pump.spawn(
+        backend,
+        river_rx,
+        river_state.clone(),
+        changes_log,
+        tx.clone(),
+    );
+    let mut heartbeat_txs = vec![tx.clone()];
+    let mut cached_states = vec![river_state.clone()];
+    for (display_rx, display_backend, source) in display_rxs {
+        heartbeat_txs.push(source.events.clone());
+        pump.spawn(
+            display_backend,
+            display_rx,
+            source.state.clone(),
+            source.changes,
+            source.events,
+        );
+        cached_states.push(source.state);
+    }
+
+    if let Some(period) = heartbeat {
+        spawn_heartbeat(period, heartbeat_txs, metrics.clone());
+    }
+
+    for path in datagrams {
+        info!(socket = %path.display(), "sending events as datagrams");
+        sink::spawn_datagram(&schema, path)?;
+    }
+    if journal {
+        info!("logging events to the systemd journal");
+        sink::spawn_journal(&schema)?;
+    }
+    for command in pipes {
+        info!(%command, "piping events to a command");
+        sink::spawn_pipe(&schema, command);
+    }
 
+    let sdl = schema.sdl();
+    let queries = CachingExecutor::new(schema.clone(), cached_states, cache_ttl);
+    let live = LiveExecutor::new(
+        schema.clone(),
+        changes.unwrap_or_else(|| tx.sender().clone()),
+    );
     let app = Router::new()
         .route("/graphiql", get(graphiql))
         .route("/schema", get(schema_sdl))
+        .route("/metrics", get(metrics_text))
         .route(
             "/graphql",
-            get_service(GraphQLSubscription::new(schema.clone()))
-                .post_service(GraphQL::new(schema.clone())),
+            get(graphql_get).post_service(GraphQL::new(queries.clone())),
         )
-        .with_state(schema);
+        .route_layer(middleware::from_fn_with_state(
+            metrics.clone(),
+            count_request,
+        ))
+        .with_state(AppState {
+            schema,
+            queries,
+            live,
+            metrics,
+            ws_limits,
+            ws_deflate,
+        });
 
-    match listen {
-        ListenTarget::Tcp(addr) => {
-            let listener = tokio::net::TcpListener::bind(addr).await?;
-            info!(protocol = "tcp", address = %addr, "server listening");
-            axum::serve(listener, app).await?;
-        }
-        #[cfg(unix)]
-        ListenTarget::Unix(path) => {
-            if let Some(parent) = path.parent() {
-                if !parent.exists() {
-                    tokio::fs::create_dir_all(parent).await?;
-                }
+    let listener = match activated_listener()? {
+        Some(listener) => listener,
+        None => Listener::bind(listen).await?,
+    };
+    let _published = match listener.target() {
+        Some(target) => Discovery::new(&target, &sdl).publish()?,
+        None => None,
+    };
+    tokio::select! {
+        served = listener.serve(app) => served?,
+        stop = shutdown_signal() => stop?,
+    }
+
+    Ok(())
+}
+
+enum Listener {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl Listener {
+    async fn bind(listen: ListenTarget) -> Result<Self> {
+        match listen {
+            ListenTarget::Tcp(addr) => {
+                let listener = tokio::net::TcpListener::bind(addr).await?;
+                info!(protocol = "tcp", address = %addr, "server listening");
+                Ok(Self::Tcp(listener))
             }
-            if path.exists() {
-                if let Err(e) = fs::remove_file(&path) {
-                    if e.kind() != std::io::ErrorKind::NotFound {
-                        return Err(e.into());
+            #[cfg(unix)]
+            ListenTarget::Unix(path) => {
+                if let Some(parent) = path.parent() {
+                    if !parent.exists() {
+                        tokio::fs::create_dir_all(parent).await?;
                     }
                 }
+                if path.exists() {
+                    if let Err(e) = fs::remove_file(&path) {
+                        if e.kind() != std::io::ErrorKind::NotFound {
+                            return Err(e.into());
+                        }
+                    }
+                }
+                let listener = tokio::net::UnixListener::bind(&path)?;
+                info!(protocol = "unix", socket = %path.display(), "server listening");
+                Ok(Self::Unix(listener))
             }
-            let listener = tokio::net::UnixListener::bind(&path)?;
-            info!(protocol = "unix", socket = %path.display(), "server listening");
-            axum::serve(listener, app).await?;
         }
     }
 
+    /// Where clients reach the listener; `None` for an unnamed socket.
+    fn target(&self) -> Option<ListenTarget> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().ok().map(ListenTarget::Tcp),
+            #[cfg(unix)]
+            Self::Unix(listener) => listener.local_addr().ok().and_then(|addr| {
+                addr.as_pathname()
+                    .map(|path| ListenTarget::Unix(path.into()))
+            }),
+        }
+    }
+
+    async fn serve(self, app: Router) -> std::io::Result<()> {
+        match self {
+            Self::Tcp(listener) => axum::serve(listener, app).await,
+            #[cfg(unix)]
+            Self::Unix(listener) => axum::serve(listener, app).await,
+        }
+    }
+}
+
+/// The socket systemd passed in as fd 3 when the server is socket
+/// activated, per `sd_listen_fds(3)`. Only the first socket is used.
+#[cfg(unix)]
+fn activated_listener() -> Result<Option<Listener>> {
+    use std::os::fd::FromRawFd;
+
+    const SD_LISTEN_FDS_START: libc::c_int = 3;
+
+    let ours = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<u32>().ok())
+        .unwrap_or(0);
+    if !ours || count == 0 {
+        return Ok(None);
+    }
+    if count > 1 {
+        warn!(count, "systemd passed several sockets; using the first");
+    }
+    let fd = SD_LISTEN_FDS_START;
+    let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of_val(&addr) as libc::socklen_t;
+    if unsafe { libc::getsockname(fd, (&raw mut addr).cast(), &mut len) } != 0 {
+        return Err(anyhow::Error::from(std::io::Error::last_os_error())
+            .context("reading the socket passed by systemd"));
+    }
+    let listener = if libc::c_int::from(addr.ss_family) == libc::AF_UNIX {
+        // Safety: systemd hands the fd over to this process alone.
+        let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::UnixListener::from_std(listener)?;
+        info!(protocol = "unix", socket = ?listener.local_addr()?, "server listening on systemd socket");
+        Listener::Unix(listener)
+    } else {
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        info!(protocol = "tcp", address = %listener.local_addr()?, "server listening on systemd socket");
+        Listener::Tcp(listener)
+    };
+    Ok(Some(listener))
+}
+
+#[cfg(not(unix))]
+fn activated_listener() -> Result<Option<Listener>> {
+    Ok(None)
+}
+
+/// Resolves on the first SIGINT or SIGTERM, so the server returns and its
+/// discovery file is removed. Open connections are dropped, not drained.
+async fn shutdown_signal() -> std::io::Result<()> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = interrupt.recv() => info!("interrupted; shutting down"),
+        _ = terminate.recv() => info!("terminated; shutting down"),
+    }
     Ok(())
 }
 
+async fn connect(
+    backend: &dyn StatusBackend,
+) -> Result<(mpsc::Receiver<river::Received>, gql::ServerInfo)> {
+    let (events, protocols) = backend.connect().await?;
+    let info = gql::ServerInfo {
+        backend: backend.kind(),
+        protocols,
+    };
+    Ok((events, info))
+}
+
+/// Moves events from a compositor connection into its snapshot and
+/// broadcast channels.
+#[derive(Clone)]
+struct EventPump {
+    dedup: bool,
+    /// Applied to the snapshot only: never broadcast, logged or counted.
+    suppress: HashSet<gql::RiverEventType>,
+    /// Also notified of every event, for live queries spanning displays.
+    changes: Option<broadcast::Sender<river::Event>>,
+    metrics: MetricsHandle,
+}
+
+impl EventPump {
+    fn spawn(
+        &self,
+        backend: Arc<dyn StatusBackend>,
+        mut river_rx: mpsc::Receiver<river::Received>,
+        state: gql::RiverStateHandle,
+        log: gql::ChangeLogHandle,
+        tx: Dispatcher,
+    ) {
+        let EventPump {
+            dedup,
+            suppress,
+            changes,
+            metrics,
+        } = self.clone();
+        tokio::spawn(async move {
+            let mut dedup = dedup.then(Deduplicator::default);
+            while let Some(river::Received {
+                event: ev,
+                at,
+                dropped,
+            }) = river_rx.recv().await
+            {
+                if dropped > 0 {
+                    // River resends its status after this, but `changesSince`
+                    // across the gap can't be complete.
+                    metrics.record_dropped(dropped);
+                    log.record_unkept();
+                }
+                if let Some(dedup) = dedup.as_mut() {
+                    if dedup.is_duplicate(&ev) {
+                        debug!(?ev, "duplicate river event dropped");
+                        continue;
+                    }
+                }
+                if suppress.contains(&gql::RiverEventType::from(&ev)) {
+                    // Queries still see the change. The log numbers it
+                    // without keeping it, so `changesSince` across it
+                    // reports incomplete instead of missing it.
+                    let seq = log.record_unkept();
+                    gql::update_river_state(&state, seq, |snapshot| backend.reduce(snapshot, &ev));
+                    continue;
+                }
+                // Log first, so no snapshot names a change the log lacks.
+                let seq = log.record(&ev);
+                gql::update_river_state(&state, seq, |snapshot| backend.reduce(snapshot, &ev));
+                metrics.record_event(gql::RiverEventType::from(&ev));
+                if let Some(changes) = &changes {
+                    let _ = changes.send(ev.clone());
+                }
+                match tx.send(ev.clone()) {
+                    Ok(_) => debug!(?ev, "river event broadcasted"),
+                    Err(e) => warn!("failed to broadcast river event: {}", e),
+                }
+                metrics.record_latency(at.elapsed());
+            }
+        });
+    }
+}
+
+/// Sends a `Heartbeat` to every display's subscribers each `period`. It skips
+/// the snapshot and change log, since it says nothing about the compositor.
+fn spawn_heartbeat(period: Duration, txs: Vec<Dispatcher>, metrics: MetricsHandle) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        for seq in 1.. {
+            ticks.tick().await;
+            let at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64);
+            let ev = river::Event::Heartbeat { seq, at };
+            metrics.record_event(gql::RiverEventType::Heartbeat);
+            for tx in &txs {
+                // Nobody listening is fine; there is nothing to keep alive.
+                let _ = tx.send(ev.clone());
+            }
+        }
+    });
+}
+
+/// Remembers the last event per (output, event type) so river's repeated
+/// state announcements on focus changes can be dropped. Seat events are
+/// keyed by seat instead: focus moving back to an output is a transition,
+/// not a repeat of that output's last focus event.
+#[derive(Default)]
+struct Deduplicator {
+    last: HashMap<DedupKey, river::Event>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum DedupKey {
+    Output(river::OutputId, Discriminant<river::Event>),
+    Seat(Option<String>, Discriminant<river::Event>),
+}
+
+impl Deduplicator {
+    fn is_duplicate(&mut self, ev: &river::Event) -> bool {
+        use river::Event::*;
+        let kind = mem::discriminant(ev);
+        let key = match ev {
+            OutputRemoved { id, .. } => {
+                self.last.retain(|_, last| last.output_id() != Some(id));
+                return false;
+            }
+            SeatFocusedOutput { seat, .. }
+            | SeatUnfocusedOutput { seat, .. }
+            | SeatFocusedView { seat, .. }
+            | SeatMode { seat, .. } => DedupKey::Seat(seat.clone(), kind),
+            Heartbeat { .. } => return false,
+            _ => match ev.output_id() {
+                Some(id) => DedupKey::Output(id.clone(), kind),
+                None => return false,
+            },
+        };
+        if self.last.get(&key) == Some(ev) {
+            return true;
+        }
+        self.last.insert(key, ev.clone());
+        false
+    }
+}
+
+/// GET carries websocket subscriptions, the plain event feed and
+/// GraphQL-over-HTTP reads (`?query=&variables=`); the upgrade and
+/// subprotocol headers tell them apart.
+async fn graphql_get(State(state): State<AppState>, req: Request) -> Response {
+    let is_websocket = req
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    if is_websocket && ws::wants_events(req.headers()) {
+        return ws::upgrade_events(req, state.schema).await;
+    }
+    if is_websocket && state.ws_deflate && ws::offers_deflate(req.headers()) {
+        return ws::upgrade_deflate(req, state.live, state.ws_limits).await;
+    }
+    if is_websocket && !state.ws_limits.is_unlimited() {
+        return ws::upgrade(req, state.live, state.ws_limits).await;
+    }
+    let Ok(response) = if is_websocket {
+        GraphQLSubscription::new(state.live).oneshot(req).await
+    } else {
+        GraphQL::new(state.queries).oneshot(req).await
+    };
+    response
+}
+
 async fn graphiql() -> Html<String> {
     let html = async_graphql::http::GraphiQLSource::build()
         .endpoint("/graphql")
@@ -93,12 +528,34 @@ async fn graphiql() -> Html<String> {
     Html(html)
 }
 
-async fn schema_sdl(State(schema): State<gql::AppSchema>) -> impl axum::response::IntoResponse {
+async fn schema_sdl(State(state): State<AppState>) -> impl axum::response::IntoResponse {
     (
         [(
             header::CONTENT_TYPE,
             http::HeaderValue::from_static("text/plain; charset=utf-8"),
         )],
-        schema.sdl(),
+        state.schema.sdl(),
+    )
+}
+
+/// Counts requests by matched route; unknown paths aren't recorded, which
+/// keeps the label set bounded.
+async fn count_request(
+    State(metrics): State<MetricsHandle>,
+    route: MatchedPath,
+    req: Request,
+    next: Next,
+) -> Response {
+    metrics.record_request(route.as_str());
+    next.run(req).await
+}
+
+async fn metrics_text(State(state): State<AppState>) -> impl axum::response::IntoResponse {
+    (
+        [(
+            header::CONTENT_TYPE,
+            http::HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+        )],
+        state.metrics.render(),
     )
 }
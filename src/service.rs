@@ -0,0 +1,165 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use tokio::process::Command;
+
+use crate::{ListenTarget, library};
+
+const SERVICE_UNIT: &str = "riverql.service";
+const SOCKET_UNIT: &str = "riverql.socket";
+
+/// What `install-service` writes and whether it turns the units on.
+pub struct InstallOptions {
+    pub listen: ListenTarget,
+    /// Server flags from the command line, without `--server` and `--listen`.
+    pub server_args: Vec<String>,
+    /// Where the units go; `$XDG_CONFIG_HOME/systemd/user` by default.
+    pub dir: Option<PathBuf>,
+    /// Reload systemd and enable and start the socket.
+    pub enable: bool,
+    /// Replace unit files that already exist.
+    pub force: bool,
+}
+
+/// Writes `riverql.service` and `riverql.socket`, so the user manager starts
+/// the server on the first connection and hands it the listening socket.
+pub async fn install(options: InstallOptions) -> Result<()> {
+    let dir = match options.dir {
+        Some(dir) => dir,
+        None => library::config_home()?.join("systemd").join("user"),
+    };
+    let exe = env::current_exe().context("locating the riverql executable")?;
+    let units = [
+        (
+            SERVICE_UNIT,
+            service_unit(&exe, &options.listen, &options.server_args),
+        ),
+        (SOCKET_UNIT, socket_unit(&options.listen)),
+    ];
+    if !options.force {
+        for (name, _) in &units {
+            let path = dir.join(name);
+            if path.exists() {
+                bail!(
+                    "{} already exists; pass --force to replace it",
+                    path.display()
+                );
+            }
+        }
+    }
+    fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    for (name, unit) in &units {
+        let path = dir.join(name);
+        fs::write(&path, unit).with_context(|| format!("writing {}", path.display()))?;
+        println!("wrote {}", path.display());
+    }
+
+    if !options.enable {
+        println!(
+            "enable with: systemctl --user daemon-reload && systemctl --user enable --now {SOCKET_UNIT}"
+        );
+        return Ok(());
+    }
+    systemctl(&["daemon-reload"]).await?;
+    systemctl(&["enable", "--now", SOCKET_UNIT]).await?;
+    println!("enabled {SOCKET_UNIT}");
+    Ok(())
+}
+
+async fn systemctl(args: &[&str]) -> Result<()> {
+    let status = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+        .await
+        .context("running systemctl")?;
+    if !status.success() {
+        bail!("systemctl --user {} failed: {status}", args.join(" "));
+    }
+    Ok(())
+}
+
+/// The server keeps `--listen` so it still binds the same address when
+/// started without the socket unit.
+fn service_unit(exe: &Path, listen: &ListenTarget, server_args: &[String]) -> String {
+    let mut command = vec![
+        exec_quote(&exe.to_string_lossy()),
+        "--server".to_string(),
+        "--listen".to_string(),
+        exec_quote(&listen.to_string()),
+    ];
+    command.extend(server_args.iter().map(|arg| exec_quote(arg)));
+    format!(
+        "[Unit]
+Description=riverql GraphQL bridge
+Documentation=https://github.com/typester/riverql
+Requires={SOCKET_UNIT}
+After={SOCKET_UNIT} graphical-session.target
+PartOf=graphical-session.target
+
+[Service]
+ExecStart={}
+Restart=on-failure
+RestartSec=2
+",
+        command.join(" ")
+    )
+}
+
+fn socket_unit(listen: &ListenTarget) -> String {
+    let address = match listen {
+        ListenTarget::Tcp(addr) => addr.to_string(),
+        #[cfg(unix)]
+        ListenTarget::Unix(path) => path.display().to_string(),
+    };
+    format!(
+        "[Unit]
+Description=riverql GraphQL bridge socket
+PartOf=graphical-session.target
+
+[Socket]
+ListenStream={}
+SocketMode=0600
+
+[Install]
+WantedBy=sockets.target
+",
+        address.replace('%', "%%")
+    )
+}
+
+/// Quotes `arg` for `ExecStart=`: plain words stay as they are, anything
+/// else is double-quoted with `\` and `"` escaped. `%` and `$` are doubled
+/// so systemd passes them through literally.
+fn exec_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.,:/=@+".contains(c));
+    let quoted = if plain {
+        arg.to_string()
+    } else {
+        format!("\"{}\"", arg.replace('\\', r"\\").replace('"', "\\\""))
+    };
+    quoted.replace('%', "%%").replace('$', "$$")
+}
+
+/// The arguments this process was started with that configure the server:
+/// those before `install-service`, less `--server` and `--listen VALUE`.
+pub fn server_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut kept = Vec::new();
+    let mut args = args.into_iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "install-service" => break,
+            "--server" => {}
+            "--listen" => {
+                args.next();
+            }
+            _ => kept.push(arg),
+        }
+    }
+    kept
+}
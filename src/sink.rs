@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use async_graphql::parser::parse_schema;
+use async_graphql::parser::types::{BaseType, TypeKind, TypeSystemDefinition};
+use async_graphql::{Request, Variables};
+use futures_util::{Stream, StreamExt};
+use serde_json::{Value, json};
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixDatagram;
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::format;
+use crate::gql::AppSchema;
+
+/// `events` selecting every field of every event type, tag lists included,
+/// so sinks see what the most thorough subscriber would. Built from the
+/// schema, so new fields reach the sinks without being listed here.
+fn event_selection(schema: &AppSchema) -> &'static str {
+    static SELECTION: OnceLock<String> = OnceLock::new();
+    SELECTION.get_or_init(|| {
+        let document = parse_schema(schema.sdl()).expect("the schema's own SDL parses");
+        let types: HashMap<&str, &TypeKind> = document
+            .definitions
+            .iter()
+            .filter_map(|definition| match definition {
+                TypeSystemDefinition::Type(ty) => Some((ty.node.name.node.as_str(), &ty.node.kind)),
+                _ => None,
+            })
+            .collect();
+        let Some(TypeKind::Union(events)) = types.get("RiverEvent") else {
+            panic!("the schema has no RiverEvent union");
+        };
+        let mut selection = String::from(
+            "subscription($filter: EventFilter) { events(filter: $filter, tagList: true) { __typename",
+        );
+        for member in &events.members {
+            let name = member.node.as_str();
+            selection.push_str(&format!(" ... on {name}{}", field_selection(&types, name)));
+        }
+        selection.push_str(" } }");
+        selection
+    })
+}
+
+/// `{ field ... }` for the object type `name`, descending into object-typed
+/// fields.
+fn field_selection(types: &HashMap<&str, &TypeKind>, name: &str) -> String {
+    let Some(TypeKind::Object(object)) = types.get(name) else {
+        return String::new();
+    };
+    let mut fields = Vec::new();
+    for field in &object.fields {
+        let mut ty = &field.node.ty.node;
+        let inner = loop {
+            match &ty.base {
+                BaseType::Named(inner) => break inner.as_str(),
+                BaseType::List(item) => ty = item,
+            }
+        };
+        fields.push(format!(
+            "{}{}",
+            field.node.name.node,
+            field_selection(types, inner)
+        ));
+    }
+    format!(" {{ {} }}", fields.join(" "))
+}
+
+/// Each event on the main display as the JSON object `events` would send,
+/// narrowed by `filter`, an `EventFilter` input as JSON (null for none). An
+/// error, such as a filter the schema rejects, is passed on as its message.
+pub fn filtered_events(
+    schema: &AppSchema,
+    filter: Value,
+) -> impl Stream<Item = Result<Value, String>> + '_ {
+    let request = Request::new(event_selection(schema))
+        .variables(Variables::from_json(json!({ "filter": filter })));
+    schema
+        .execute_stream(request)
+        .filter_map(|response| async move {
+            if let Some(error) = response.errors.first() {
+                return Some(Err(error.message.clone()));
+            }
+            match response.data.into_json() {
+                Ok(Value::Object(mut data)) => data.remove("events").map(Ok),
+                _ => None,
+            }
+        })
+}
+
+/// Every event on the main display, as [`filtered_events`] without a filter.
+fn event_objects(schema: &AppSchema) -> impl Stream<Item = Value> + '_ {
+    filtered_events(schema, Value::Null).filter_map(|event| async move {
+        event
+            .inspect_err(|error| warn!(%error, "event sink query failed"))
+            .ok()
+    })
+}
+
+/// Runs `send` on every event until the server exits.
+fn spawn_events(schema: &AppSchema, mut send: impl FnMut(&Value) + Send + 'static) {
+    let schema = schema.clone();
+    tokio::spawn(async move {
+        let mut events = std::pin::pin!(event_objects(&schema));
+        while let Some(event) = events.next().await {
+            send(&event);
+        }
+    });
+}
+
+/// Sends each event as one JSON datagram to the unix socket at `path`.
+/// Nothing is queued: events are dropped while no one is bound there or the
+/// receiver's buffer is full, so a slow reader only loses updates.
+pub fn spawn_datagram(schema: &AppSchema, path: PathBuf) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    spawn_events(schema, move |event| {
+        if let Err(e) = socket.try_send_to(event.to_string().as_bytes(), &path) {
+            debug!(socket = %path.display(), error = %e, "event datagram dropped");
+        }
+    });
+    Ok(())
+}
+
+/// Writes each event as a line of JSON to the stdin of `command`.
+pub fn spawn_pipe(schema: &AppSchema, command: String) {
+    let pipe = Pipe::spawn(command);
+    spawn_events(schema, move |event| pipe.send(event.to_string()));
+}
+
+/// Lines waiting for a pipe command; more are dropped.
+const PIPE_BACKLOG: usize = 1024;
+/// Pause before restarting a pipe command that exited.
+const PIPE_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// A shell command fed one line at a time on its stdin. It is started once
+/// and restarted whenever it exits, so it can keep state between lines
+/// without a process per line.
+pub struct Pipe {
+    lines: mpsc::Sender<String>,
+    task: JoinHandle<()>,
+}
+
+impl Pipe {
+    pub fn spawn(command: String) -> Self {
+        let (lines, rx) = mpsc::channel(PIPE_BACKLOG);
+        let task = tokio::spawn(feed(command, rx));
+        Self { lines, task }
+    }
+
+    /// Queues `line`; it is dropped while the command is too far behind.
+    pub fn send(&self, line: String) {
+        if self.lines.try_send(line).is_err() {
+            debug!("pipe command is behind; line dropped");
+        }
+    }
+
+    /// Delivers the queued lines, closes the command's stdin and waits for
+    /// it to exit.
+    pub async fn close(self) {
+        drop(self.lines);
+        let _ = self.task.await;
+    }
+}
+
+async fn feed(command: String, mut lines: mpsc::Receiver<String>) {
+    // A line whose write failed because the command had exited.
+    let mut unsent = None;
+    loop {
+        let (mut child, mut stdin) = match start(&command) {
+            Ok(started) => started,
+            Err(e) => {
+                warn!(%command, "cannot start pipe command: {e}");
+                tokio::time::sleep(PIPE_RESTART_DELAY).await;
+                continue;
+            }
+        };
+        info!(%command, "pipe command started");
+        loop {
+            let line = match unsent.take() {
+                Some(line) => line,
+                None => tokio::select! {
+                    line = lines.recv() => match line {
+                        Some(line) => line,
+                        None => {
+                            drop(stdin);
+                            let _ = child.wait().await;
+                            return;
+                        }
+                    },
+                    _ = child.wait() => break,
+                },
+            };
+            let written = stdin.write_all(format!("{line}\n").as_bytes()).await;
+            if written.is_err() {
+                unsent = Some(line);
+                break;
+            }
+        }
+        let status = child.wait().await;
+        warn!(%command, ?status, "pipe command exited; restarting");
+        tokio::time::sleep(PIPE_RESTART_DELAY).await;
+    }
+}
+
+fn start(command: &str) -> io::Result<(Child, ChildStdin)> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdin = child.stdin.take().expect("piped stdin");
+    Ok((child, stdin))
+}
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Writes each event to the systemd journal under the `riverql` identifier,
+/// with its fields as `RIVERQL_*` journal fields. `RIVERQL_JOURNAL_SOCKET`
+/// replaces the journal's socket path, e.g. for a journal in a container.
+pub fn spawn_journal(schema: &AppSchema) -> Result<()> {
+    let path = env::var_os("RIVERQL_JOURNAL_SOCKET")
+        .map_or_else(|| PathBuf::from(JOURNAL_SOCKET), PathBuf::from);
+    let socket = UnixDatagram::unbound()?;
+    socket
+        .connect(&path)
+        .with_context(|| format!("connecting to the systemd journal at {}", path.display()))?;
+    spawn_events(schema, move |event| {
+        // The journal has its own timestamps; heartbeats would only add noise.
+        if event["__typename"] == "Heartbeat" {
+            return;
+        }
+        if let Err(e) = socket.try_send(&journal_entry(event)) {
+            debug!(error = %e, "journal entry dropped");
+        }
+    });
+    Ok(())
+}
+
+/// Encodes `event` in the journal's native protocol. `__typename` becomes
+/// `RIVERQL_EVENT` and the output's name `RIVERQL_OUTPUT`; other fields keep
+/// their name in shell case, e.g. `viewCount` -> `RIVERQL_VIEW_COUNT`.
+fn journal_entry(event: &Value) -> Vec<u8> {
+    let Value::Object(fields) = event else {
+        return Vec::new();
+    };
+    let kind = fields["__typename"].as_str().unwrap_or_default();
+    let mut entry = Vec::new();
+    push_journal_field(&mut entry, "SYSLOG_IDENTIFIER", "riverql");
+    push_journal_field(&mut entry, "PRIORITY", "6");
+    push_journal_field(&mut entry, "MESSAGE", &format!("river event {kind}"));
+    for (key, value) in fields {
+        let name = match (key.as_str(), kind) {
+            ("__typename", _) => "EVENT".to_string(),
+            ("name", "SeatMode") => "MODE".to_string(),
+            ("name" | "outputName", _) => "OUTPUT".to_string(),
+            (key, _) => format::shell_name(key),
+        };
+        let value = match value {
+            Value::Null => continue,
+            Value::String(s) => s.clone(),
+            Value::Array(items) => items
+                .iter()
+                .map(Value::to_string)
+                .collect::<Vec<_>>()
+                .join(" "),
+            other => other.to_string(),
+        };
+        push_journal_field(&mut entry, &format!("RIVERQL_{name}"), &value);
+    }
+    entry
+}
+
+/// `KEY=value\n`, or the length-prefixed form when `value` has a newline.
+fn push_journal_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    entry.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
@@ -0,0 +1,188 @@
+//! State of compositors whose IPC answers queries instead of sending river's
+//! change events. Each backend builds a `Snapshot` after the compositor
+//! reports activity; `Reported` turns it into the events that changed.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::river::{Event, OutputId};
+
+/// Tag bit for workspace `num`; zero for workspaces outside 1..=32.
+pub fn workspace_tag(num: i32) -> u32 {
+    if (1..=32).contains(&num) {
+        1 << (num - 1)
+    } else {
+        0
+    }
+}
+
+/// Logical placement of an output.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// An output as river would describe it.
+#[derive(Clone, Default, PartialEq)]
+pub struct OutputStatus {
+    pub rect: Rect,
+    pub scale: Option<i32>,
+    pub focused_tags: u32,
+    pub view_tags: Vec<u32>,
+    pub urgent_tags: u32,
+    /// Layout of the visible workspace.
+    pub layout: Option<String>,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct FocusedView {
+    pub title: String,
+    pub app_id: Option<String>,
+    pub view_id: String,
+}
+
+/// The parts of a compositor's state that map onto river events.
+pub struct Snapshot {
+    pub outputs: BTreeMap<String, OutputStatus>,
+    pub focused_output: Option<String>,
+    pub focused_view: Option<FocusedView>,
+}
+
+/// What has been reported so far, to turn snapshots into change events.
+pub struct Reported {
+    /// Prefix of the synthetic output ids, e.g. `sway_output`.
+    prefix: &'static str,
+    outputs: BTreeMap<String, OutputStatus>,
+    focused_output: Option<String>,
+    focused_view: Option<FocusedView>,
+    mode: Option<String>,
+}
+
+impl Reported {
+    pub fn new(prefix: &'static str) -> Self {
+        Self {
+            prefix,
+            outputs: BTreeMap::new(),
+            focused_output: None,
+            focused_view: None,
+            mode: None,
+        }
+    }
+
+    fn output_id(&self, name: &str) -> OutputId {
+        OutputId::synthetic(format!("{}@{name}", self.prefix))
+    }
+
+    pub fn set_mode(&mut self, mode: String) -> Vec<Event> {
+        if self.mode.as_ref() == Some(&mode) {
+            return Vec::new();
+        }
+        self.mode = Some(mode.clone());
+        vec![Event::SeatMode {
+            name: mode,
+            seat: None,
+        }]
+    }
+
+    pub fn update(&mut self, snapshot: Snapshot) -> Vec<Event> {
+        let mut events = Vec::new();
+        for name in self.outputs.keys() {
+            if !snapshot.outputs.contains_key(name) {
+                events.push(Event::OutputRemoved {
+                    id: self.output_id(name),
+                    name: Some(name.clone()),
+                });
+            }
+        }
+        for (name, status) in &snapshot.outputs {
+            let old = self.outputs.get(name);
+            let id = self.output_id(name);
+            let named = Some(name.clone());
+            if old.is_none() {
+                events.push(Event::OutputNamed {
+                    id: id.clone(),
+                    name: name.clone(),
+                });
+            }
+            if old.is_none_or(|old| (old.rect, old.scale) != (status.rect, status.scale)) {
+                events.push(Event::OutputPosition {
+                    id: id.clone(),
+                    name: named.clone(),
+                    x: status.rect.x,
+                    y: status.rect.y,
+                    width: Some(status.rect.width),
+                    height: Some(status.rect.height),
+                    scale: status.scale,
+                });
+            }
+            if old.is_none_or(|old| old.focused_tags != status.focused_tags) {
+                events.push(Event::OutputFocusedTags {
+                    id: id.clone(),
+                    name: named.clone(),
+                    tags: status.focused_tags,
+                });
+            }
+            if old.is_none_or(|old| old.view_tags != status.view_tags) {
+                events.push(Event::OutputViewTags {
+                    id: id.clone(),
+                    name: named.clone(),
+                    tags: status.view_tags.clone(),
+                });
+            }
+            if old.is_none_or(|old| old.urgent_tags != status.urgent_tags) {
+                events.push(Event::OutputUrgentTags {
+                    id: id.clone(),
+                    name: named.clone(),
+                    tags: status.urgent_tags,
+                });
+            }
+            if old.is_none_or(|old| old.layout != status.layout) {
+                events.push(match &status.layout {
+                    Some(layout) => Event::OutputLayoutName {
+                        id,
+                        name: named,
+                        layout: layout.clone(),
+                    },
+                    None => Event::OutputLayoutNameClear { id, name: named },
+                });
+            }
+        }
+        if self.focused_output != snapshot.focused_output {
+            if let Some(name) = &self.focused_output {
+                if snapshot.outputs.contains_key(name) {
+                    events.push(Event::SeatUnfocusedOutput {
+                        id: self.output_id(name),
+                        name: Some(name.clone()),
+                        seat: None,
+                    });
+                }
+            }
+            if let Some(name) = &snapshot.focused_output {
+                events.push(Event::SeatFocusedOutput {
+                    id: self.output_id(name),
+                    name: Some(name.clone()),
+                    seat: None,
+                });
+            }
+        }
+        // Like river, nothing is sent when focus moves to an empty workspace.
+        if let Some(view) = &snapshot.focused_view {
+            if self.focused_view.as_ref() != Some(view) {
+                events.push(Event::SeatFocusedView {
+                    title: view.title.clone(),
+                    app_id: view.app_id.clone(),
+                    view_id: Some(view.view_id.clone()),
+                    seat: None,
+                });
+            }
+        }
+        self.outputs = snapshot.outputs;
+        self.focused_output = snapshot.focused_output;
+        self.focused_view = snapshot.focused_view;
+        events
+    }
+}
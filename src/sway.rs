@@ -0,0 +1,372 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Context, Result, bail};
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tracing::{debug, info, warn};
+
+use crate::backend::{Command, StatusBackend};
+use crate::gql;
+use crate::river::{BoundGlobal, EVENT_QUEUE_CAPACITY, Event, Protocols, Received};
+use crate::snapshot::{FocusedView, OutputStatus, Rect, Reported, Snapshot, workspace_tag};
+
+const MAGIC: &[u8] = b"i3-ipc";
+const RUN_COMMAND: u32 = 0;
+const GET_WORKSPACES: u32 = 1;
+const SUBSCRIBE: u32 = 2;
+const GET_OUTPUTS: u32 = 3;
+const GET_TREE: u32 = 4;
+const GET_BINDING_STATE: u32 = 12;
+/// Set in the type of messages the compositor sends on its own.
+const EVENT_FLAG: u32 = 1 << 31;
+const MODE_EVENT: u32 = EVENT_FLAG | 2;
+const SUBSCRIBED_EVENTS: &str = r#"["workspace","output","window","mode"]"#;
+
+/// sway (or i3) over its IPC socket.
+pub struct SwayBackend {
+    socket: PathBuf,
+}
+
+impl SwayBackend {
+    /// Uses the IPC socket sway (or i3) announces in the environment.
+    pub fn from_env() -> Result<Self> {
+        let socket = env::var_os("SWAYSOCK")
+            .or_else(|| env::var_os("I3SOCK"))
+            .map(PathBuf::from)
+            .context("SWAYSOCK is not set; is sway running?")?;
+        Ok(Self { socket })
+    }
+}
+
+impl StatusBackend for SwayBackend {
+    fn kind(&self) -> gql::Backend {
+        gql::Backend::Sway
+    }
+
+    fn connect(&self) -> BoxFuture<'_, Result<(Receiver<Received>, Protocols)>> {
+        Box::pin(async move {
+            info!(socket = %self.socket.display(), "connecting to sway IPC");
+            Ok((subscribe(&self.socket).await?, protocols()))
+        })
+    }
+
+    /// Switches to the workspace of a single tag, focusing its output first
+    /// in the same command list.
+    fn execute(&self, command: &Command) -> BoxFuture<'_, Result<()>> {
+        let command = command.clone();
+        Box::pin(async move {
+            let Command::SetFocusedTags {
+                tags,
+                output,
+                focused,
+            } = command;
+            if tags.count_ones() != 1 {
+                bail!("sway shows one workspace per output; cannot focus tags {tags:#b}");
+            }
+            let workspace = tags.trailing_zeros() + 1;
+            let mut run = format!("workspace number {workspace}");
+            if let Some(output) = output {
+                run = format!("focus output \"{output}\"; {run}");
+            }
+            if let Some(focused) = focused {
+                run = format!("{run}; focus output \"{focused}\"");
+            }
+            let mut conn = Connection::open(&self.socket).await?;
+            let replies: Vec<CommandReply> = conn.request(RUN_COMMAND, &run).await?;
+            match replies.into_iter().find(|reply| !reply.success) {
+                Some(reply) => bail!(
+                    "sway refused the command: {}",
+                    reply.error.unwrap_or_default()
+                ),
+                None => Ok(()),
+            }
+        })
+    }
+}
+
+/// What `serverInfo.protocols` lists for this backend.
+fn protocols() -> Protocols {
+    Protocols {
+        globals: vec![BoundGlobal {
+            interface: "sway-ipc".to_string(),
+            version: 1,
+        }],
+    }
+}
+
+/// Connects to the IPC socket at `socket` and reports sway's state as river
+/// events: workspace N is tag N (named workspaces without a number have no
+/// tag), each window is a view on its output, and the visible workspace is
+/// the output's focused tag. Seat events carry no seat name since the IPC
+/// doesn't say which seat acted.
+async fn subscribe(socket: &Path) -> Result<Receiver<Received>> {
+    let mut queries = Connection::open(socket).await?;
+    let mut events = Connection::open(socket).await?;
+    let reply: Value = events.request(SUBSCRIBE, SUBSCRIBED_EVENTS).await?;
+    if reply["success"] != true {
+        bail!("sway refused the event subscription: {reply}");
+    }
+    let mode: BindingState = queries.request(GET_BINDING_STATE, "").await?;
+    let mut state = Reported::new("sway_output");
+    let mut initial = state.set_mode(mode.name);
+    initial.extend(state.update(fetch(&mut queries).await?));
+
+    let (tx, rx) = mpsc::channel(EVENT_QUEUE_CAPACITY);
+    tokio::spawn(async move {
+        if let Err(e) = forward(queries, events, state, initial, tx).await {
+            warn!("sway IPC connection ended: {e:#}");
+        }
+    });
+    Ok(rx)
+}
+
+/// Re-reads sway's state after every event it sends and forwards what
+/// changed, until either side goes away.
+async fn forward(
+    mut queries: Connection,
+    mut events: Connection,
+    mut state: Reported,
+    mut pending: Vec<Event>,
+    tx: Sender<Received>,
+) -> Result<()> {
+    // Changes are timed from the event that revealed them.
+    let mut received = Instant::now();
+    loop {
+        for event in pending.drain(..) {
+            if tx
+                .send(Received {
+                    event,
+                    at: received,
+                    dropped: 0,
+                })
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+        let (kind, payload) = events.recv().await?;
+        received = Instant::now();
+        debug!(kind = kind & !EVENT_FLAG, "sway IPC event");
+        pending = if kind == MODE_EVENT {
+            let mode: ModeEvent = serde_json::from_slice(&payload)?;
+            state.set_mode(mode.change)
+        } else {
+            state.update(fetch(&mut queries).await?)
+        };
+    }
+}
+
+/// One IPC socket connection.
+struct Connection(UnixStream);
+
+impl Connection {
+    async fn open(path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(path)
+            .await
+            .with_context(|| format!("connecting to sway IPC at {}", path.display()))?;
+        Ok(Self(stream))
+    }
+
+    async fn send(&mut self, kind: u32, payload: &str) -> Result<()> {
+        let mut message = Vec::with_capacity(MAGIC.len() + 8 + payload.len());
+        message.extend_from_slice(MAGIC);
+        message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(payload.as_bytes());
+        self.0.write_all(&message).await?;
+        Ok(())
+    }
+
+    /// The next message as (type, payload).
+    async fn recv(&mut self) -> Result<(u32, Vec<u8>)> {
+        let mut header = [0; 14];
+        self.0.read_exact(&mut header).await?;
+        if &header[..MAGIC.len()] != MAGIC {
+            bail!("unexpected data on the sway IPC socket");
+        }
+        let len = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+        let kind = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+        let mut payload = vec![0; len as usize];
+        self.0.read_exact(&mut payload).await?;
+        Ok((kind, payload))
+    }
+
+    async fn request<T: DeserializeOwned>(&mut self, kind: u32, payload: &str) -> Result<T> {
+        self.send(kind, payload).await?;
+        let (reply, body) = self.recv().await?;
+        if reply != kind {
+            bail!("sway answered IPC message {kind} with message {reply}");
+        }
+        serde_json::from_slice(&body)
+            .with_context(|| format!("parsing the reply to sway IPC message {kind}"))
+    }
+}
+
+#[derive(Deserialize)]
+struct CommandReply {
+    success: bool,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BindingState {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ModeEvent {
+    change: String,
+}
+
+#[derive(Deserialize)]
+struct IpcOutput {
+    name: String,
+    #[serde(default = "active_default")]
+    active: bool,
+    rect: Rect,
+    /// Only sway reports a scale.
+    scale: Option<f64>,
+}
+
+fn active_default() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct IpcWorkspace {
+    num: i32,
+    output: String,
+    visible: bool,
+    focused: bool,
+    urgent: bool,
+    #[serde(default)]
+    layout: Option<String>,
+}
+
+/// A container in `GET_TREE`.
+#[derive(Deserialize)]
+struct Node {
+    id: i64,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    num: Option<i32>,
+    #[serde(default)]
+    focused: bool,
+    #[serde(default)]
+    app_id: Option<String>,
+    #[serde(default)]
+    window_properties: Option<WindowProperties>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    floating_nodes: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+struct WindowProperties {
+    class: Option<String>,
+}
+
+impl Node {
+    fn children(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter().chain(&self.floating_nodes)
+    }
+
+    fn is_window(&self) -> bool {
+        matches!(self.kind.as_str(), "con" | "floating_con") && self.children().next().is_none()
+    }
+
+    fn window_count(&self) -> usize {
+        if self.is_window() {
+            1
+        } else {
+            self.children().map(Node::window_count).sum()
+        }
+    }
+
+    fn focused_window(&self) -> Option<&Node> {
+        if self.focused {
+            return self.is_window().then_some(self);
+        }
+        self.children().find_map(Node::focused_window)
+    }
+}
+
+/// Reads the outputs, workspaces and windows.
+async fn fetch(conn: &mut Connection) -> Result<Snapshot> {
+    let outputs: Vec<IpcOutput> = conn.request(GET_OUTPUTS, "").await?;
+    let workspaces: Vec<IpcWorkspace> = conn.request(GET_WORKSPACES, "").await?;
+    let tree: Node = conn.request(GET_TREE, "").await?;
+    Ok(snapshot(outputs, workspaces, &tree))
+}
+
+fn snapshot(outputs: Vec<IpcOutput>, workspaces: Vec<IpcWorkspace>, tree: &Node) -> Snapshot {
+    let mut statuses: BTreeMap<String, OutputStatus> = outputs
+        .into_iter()
+        .filter(|output| output.active)
+        .map(|output| {
+            let status = OutputStatus {
+                rect: output.rect,
+                // Like wl_output, round fractional scales up.
+                scale: output.scale.map(|scale| scale.ceil() as i32),
+                ..OutputStatus::default()
+            };
+            (output.name, status)
+        })
+        .collect();
+    let mut focused_output = None;
+    for workspace in workspaces {
+        let Some(status) = statuses.get_mut(&workspace.output) else {
+            continue;
+        };
+        let tag = workspace_tag(workspace.num);
+        if workspace.visible {
+            status.focused_tags |= tag;
+            status.layout = workspace.layout;
+        }
+        if workspace.urgent {
+            status.urgent_tags |= tag;
+        }
+        if workspace.focused {
+            focused_output = Some(workspace.output);
+        }
+    }
+    for output in tree.nodes.iter().filter(|node| node.kind == "output") {
+        let Some(status) = output.name.as_ref().and_then(|name| statuses.get_mut(name)) else {
+            continue;
+        };
+        for workspace in output.nodes.iter().filter(|node| node.kind == "workspace") {
+            let tag = workspace_tag(workspace.num.unwrap_or(-1));
+            status
+                .view_tags
+                .extend(std::iter::repeat_n(tag, workspace.window_count()));
+        }
+    }
+    let focused_view = tree.focused_window().map(|window| FocusedView {
+        title: window.name.clone().unwrap_or_default(),
+        app_id: window.app_id.clone().or_else(|| {
+            window
+                .window_properties
+                .as_ref()
+                .and_then(|props| props.class.clone())
+        }),
+        view_id: window.id.to_string(),
+    });
+    Snapshot {
+        outputs: statuses,
+        focused_output,
+        focused_view,
+    }
+}
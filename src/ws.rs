@@ -0,0 +1,366 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_graphql::futures_util::stream::{self, BoxStream};
+use async_graphql::http::{ALL_WEBSOCKET_PROTOCOLS, WebSocketProtocols};
+use async_graphql::{Data, Executor, Request, Response, ServerError};
+use async_graphql_axum::{GraphQLProtocol, GraphQLWebSocket};
+use axum::body::Body;
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use axum::extract::{FromRequestParts, WebSocketUpgrade};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response as HttpResponse};
+use futures_util::future::{self, Either};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use hyper_util::rt::TokioIo;
+use serde_json::{Value, json};
+use soketto::extension::deflate::Deflate;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tracing::debug;
+
+use crate::gql::AppSchema;
+use crate::live::LiveExecutor;
+use crate::sink;
+
+/// Subprotocol for a plain event feed without GraphQL: one JSON event per
+/// text frame.
+pub const EVENTS_PROTOCOL: &str = "riverql-events";
+
+/// Limits applied to every websocket connection.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionLimits {
+    /// Close the connection once it has had no open operation and no
+    /// incoming message for this long.
+    pub idle_timeout: Option<Duration>,
+    /// Reject operations beyond this many running at once.
+    pub max_operations: Option<usize>,
+}
+
+impl ConnectionLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.idle_timeout.is_none() && self.max_operations.is_none()
+    }
+}
+
+/// Upgrades `req` to a GraphQL websocket that enforces `limits`.
+pub async fn upgrade(
+    req: axum::extract::Request,
+    executor: LiveExecutor,
+    limits: ConnectionLimits,
+) -> HttpResponse {
+    let (mut parts, _body) = req.into_parts();
+    let protocol = match GraphQLProtocol::from_request_parts(&mut parts, &()).await {
+        Ok(protocol) => protocol,
+        Err(err) => return err.into_response(),
+    };
+    let upgrade = match WebSocketUpgrade::from_request_parts(&mut parts, &()).await {
+        Ok(upgrade) => upgrade,
+        Err(err) => return err.into_response(),
+    };
+    upgrade
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| {
+            let (sink, stream) = socket.split();
+            serve(sink, stream, executor, protocol, limits)
+        })
+        .into_response()
+}
+
+/// Whether the client offers the `permessage-deflate` extension.
+pub fn offers_deflate(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::SEC_WEBSOCKET_EXTENSIONS)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|offer| offer.split(';').next().unwrap_or_default().trim() == "permessage-deflate")
+}
+
+/// Upgrades `req` to a GraphQL websocket like [`upgrade`], with
+/// `permessage-deflate` negotiated. axum's websocket can neither compress
+/// frames nor read compressed ones, so soketto runs this connection.
+pub async fn upgrade_deflate(
+    req: axum::extract::Request,
+    executor: LiveExecutor,
+    limits: ConnectionLimits,
+) -> HttpResponse {
+    let (mut parts, _body) = req.into_parts();
+    let protocol = match GraphQLProtocol::from_request_parts(&mut parts, &()).await {
+        Ok(protocol) => protocol,
+        Err(err) => return err.into_response(),
+    };
+    let mut req = axum::http::Request::from_parts(parts, ());
+    let mut handshake = soketto::handshake::http::Server::new();
+    handshake.add_extension(Box::new(Deflate::new(soketto::Mode::Server)));
+    let mut response = match handshake.receive_request(&req) {
+        Ok(response) => response,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    // The same pick axum makes: the client's first protocol we speak.
+    let accepted = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            value
+                .split(',')
+                .find_map(|name| WebSocketProtocols::from_str(name.trim()).ok())
+        });
+    if let Some(accepted) = accepted {
+        response.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(accepted.sec_websocket_protocol()),
+        );
+    }
+    let upgraded = hyper::upgrade::on(&mut req);
+    tokio::spawn(async move {
+        let io = match upgraded.await {
+            Ok(io) => TokioIo::new(io).compat(),
+            Err(e) => {
+                debug!(error = %e, "websocket upgrade failed");
+                return;
+            }
+        };
+        let (sender, receiver) = handshake.into_builder(io).finish();
+        let (sink, stream) = deflate_pair(sender, receiver);
+        serve(sink, stream, executor, protocol, limits).await;
+    });
+    response.map(|()| Body::empty())
+}
+
+/// Adapts a soketto connection to the message sink and stream that axum's
+/// websocket splits into. Pings are answered by soketto itself.
+fn deflate_pair<T>(
+    sender: soketto::Sender<T>,
+    receiver: soketto::Receiver<T>,
+) -> (
+    impl Sink<Message> + Send + Unpin,
+    impl Stream<Item = Result<Message, axum::Error>> + Send + Unpin,
+)
+where
+    T: futures_util::AsyncRead + futures_util::AsyncWrite + Send + Unpin + 'static,
+{
+    let sink = futures_util::sink::unfold(sender, |mut sender, message: Message| async move {
+        match message {
+            Message::Text(text) => sender.send_text(text.as_str()).await?,
+            Message::Binary(data) => sender.send_binary(&data).await?,
+            Message::Close(_) => sender.close().await?,
+            Message::Ping(_) | Message::Pong(_) => {}
+        }
+        sender.flush().await?;
+        Ok::<_, soketto::connection::Error>(sender)
+    });
+    let stream = stream::unfold(receiver, |mut receiver| async move {
+        let mut data = Vec::new();
+        let message = match receiver.receive_data(&mut data).await.ok()? {
+            soketto::Data::Text(_) => Message::Text(String::from_utf8(data).ok()?.into()),
+            soketto::Data::Binary(_) => Message::Binary(data.into()),
+        };
+        Some((Ok(message), receiver))
+    });
+    (Box::pin(sink), Box::pin(stream))
+}
+
+/// Whether the client offers the [`EVENTS_PROTOCOL`] subprotocol.
+pub fn wants_events(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim() == EVENTS_PROTOCOL)
+}
+
+/// Upgrades `req` to an [`EVENTS_PROTOCOL`] websocket fed from `schema`.
+pub async fn upgrade_events(req: axum::extract::Request, schema: AppSchema) -> HttpResponse {
+    let (mut parts, _body) = req.into_parts();
+    let upgrade = match WebSocketUpgrade::from_request_parts(&mut parts, &()).await {
+        Ok(upgrade) => upgrade,
+        Err(err) => return err.into_response(),
+    };
+    upgrade
+        .protocols([EVENTS_PROTOCOL])
+        .on_upgrade(move |socket| serve_events(socket, schema))
+        .into_response()
+}
+
+/// Sends every event as it happens, starting with the current state the way
+/// `events` does. A text frame from the client holding an `EventFilter`
+/// object (or null) replaces the filter and restarts the feed; the server
+/// answers `{"filter": filter}` ahead of the first filtered event. A filter
+/// the schema rejects is answered with `{"error": message}` and stops the
+/// feed until a valid one arrives.
+async fn serve_events(socket: WebSocket, schema: AppSchema) {
+    let (mut sink, mut incoming) = socket.split();
+    let mut events = sink::filtered_events(&schema, Value::Null).boxed();
+    loop {
+        let frame = tokio::select! {
+            event = events.next() => match event {
+                Some(Ok(event)) => event,
+                Some(Err(message)) => {
+                    events = stream::pending().boxed();
+                    json!({ "error": message })
+                }
+                None => return,
+            },
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    match serde_json::from_str::<Value>(&text) {
+                        Ok(filter) => {
+                            debug!(%filter, "event feed filter changed");
+                            events = sink::filtered_events(&schema, filter.clone()).boxed();
+                            json!({ "filter": filter })
+                        }
+                        Err(e) => json!({ "error": format!("filter is not JSON: {e}") }),
+                    }
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => return,
+            },
+        };
+        if sink
+            .send(Message::Text(frame.to_string().into()))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+async fn serve<S, R>(
+    mut sink: S,
+    stream: R,
+    executor: LiveExecutor,
+    protocol: GraphQLProtocol,
+    limits: ConnectionLimits,
+) where
+    S: Sink<Message> + Send + Unpin,
+    R: Stream<Item = Result<Message, axum::Error>> + Send,
+{
+    let activity = Arc::new(Activity::new());
+    let stream = stream.inspect({
+        let activity = activity.clone();
+        move |_| activity.touch()
+    });
+    let executor = TrackedExecutor {
+        inner: executor,
+        activity: activity.clone(),
+        max_operations: limits.max_operations,
+    };
+    let connection =
+        Box::pin(GraphQLWebSocket::new_with_pair(&mut sink, stream, executor, protocol).serve());
+    let idle = std::pin::pin!(async {
+        match limits.idle_timeout {
+            Some(timeout) => activity.idle(timeout).await,
+            None => future::pending().await,
+        }
+    });
+    match future::select(connection, idle).await {
+        Either::Left(_) => return,
+        // Release the sink before using it for the close frame.
+        Either::Right((_, connection)) => drop(connection),
+    }
+    debug!(timeout = ?limits.idle_timeout, "closing idle websocket");
+    let _ = sink
+        .send(Message::Close(Some(CloseFrame {
+            code: 1000,
+            reason: "idle timeout".into(),
+        })))
+        .await;
+}
+
+/// When a connection last heard from its client, and how many operations it
+/// has open.
+struct Activity {
+    last: Mutex<Instant>,
+    operations: AtomicUsize,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            last: Mutex::new(Instant::now()),
+            operations: AtomicUsize::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last.lock().unwrap() = Instant::now();
+    }
+
+    /// Resolves once the connection has been idle for `timeout`.
+    async fn idle(&self, timeout: Duration) {
+        loop {
+            let last = *self.last.lock().unwrap();
+            tokio::time::sleep_until((last + timeout).into()).await;
+            if self.operations.load(Ordering::SeqCst) > 0 {
+                // Busy connections restart the clock instead of spinning.
+                self.touch();
+            } else if self.last.lock().unwrap().elapsed() >= timeout {
+                return;
+            }
+        }
+    }
+}
+
+/// Counts the operations a connection has open; the idle clock restarts when
+/// the last one ends.
+struct OperationGuard(Arc<Activity>);
+
+impl OperationGuard {
+    /// Counts a new operation, unless `max` are already open.
+    fn acquire(activity: &Arc<Activity>, max: Option<usize>) -> Option<Self> {
+        activity
+            .operations
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                max.is_none_or(|max| open < max).then_some(open + 1)
+            })
+            .ok()?;
+        Some(Self(activity.clone()))
+    }
+}
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        self.0.operations.fetch_sub(1, Ordering::SeqCst);
+        self.0.touch();
+    }
+}
+
+#[derive(Clone)]
+struct TrackedExecutor {
+    inner: LiveExecutor,
+    activity: Arc<Activity>,
+    max_operations: Option<usize>,
+}
+
+impl Executor for TrackedExecutor {
+    async fn execute(&self, request: Request) -> Response {
+        self.inner.execute(request).await
+    }
+
+    fn execute_stream(
+        &self,
+        request: Request,
+        session_data: Option<Arc<Data>>,
+    ) -> BoxStream<'static, Response> {
+        let Some(guard) = OperationGuard::acquire(&self.activity, self.max_operations) else {
+            let limit = self.max_operations.unwrap_or_default();
+            let error = ServerError::new(
+                format!("too many operations on this connection (limit {limit})"),
+                None,
+            );
+            return stream::once(future::ready(Response::from_errors(vec![error]))).boxed();
+        };
+        self.inner
+            .execute_stream(request, session_data)
+            .map(move |response| {
+                let _ = &guard;
+                response
+            })
+            .boxed()
+    }
+}
@@ -0,0 +1,2000 @@
+//! End-to-end tests: fixture compositor -> riverql server -> GraphQL.
+//!
+//! Run with `cargo test --features test-fixture`.
+
+mod support;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+use support::fixture::{Command, LayoutCommit};
+use support::{DeflateSocket, Fixture, Server, Subscription};
+
+fn output<'a>(data: &'a Value, name: &str) -> &'a Value {
+    data["outputs"]
+        .as_array()
+        .expect("outputs list")
+        .iter()
+        .find(|o| o["name"] == name)
+        .unwrap_or_else(|| panic!("output {name} missing in {data}"))
+}
+
+/// Decodes a journal native-protocol entry: `KEY=value\n`, or `KEY\n`
+/// followed by a little-endian u64 length, the value and `\n`.
+fn journal_fields(mut entry: &[u8]) -> HashMap<String, String> {
+    let mut fields = HashMap::new();
+    while !entry.is_empty() {
+        let end = entry.iter().position(|&b| b == b'\n').expect("field ends");
+        let line = &entry[..end];
+        let (key, value, rest) = match line.iter().position(|&b| b == b'=') {
+            Some(eq) => (&line[..eq], &line[eq + 1..], &entry[end + 1..]),
+            None => {
+                let rest = &entry[end + 1..];
+                let len = u64::from_le_bytes(rest[..8].try_into().unwrap()) as usize;
+                assert_eq!(rest[8 + len], b'\n', "binary value ends with a newline");
+                (line, &rest[8..8 + len], &rest[8 + len + 1..])
+            }
+        };
+        fields.insert(
+            String::from_utf8(key.to_vec()).unwrap(),
+            String::from_utf8(value.to_vec()).unwrap(),
+        );
+        entry = rest;
+    }
+    fields
+}
+
+#[test]
+fn query_reflects_initial_compositor_state() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let server = Server::spawn(&fixture);
+
+    let data = server.query_until(
+        "{ outputs { name focusedTags } seatFocusedOutput { name } seatMode { name } }",
+        |data| data["outputs"].as_array().is_some_and(|o| o.len() == 2),
+    );
+
+    assert_eq!(output(&data, "FIX-1")["focusedTags"], 1);
+    assert_eq!(output(&data, "FIX-2")["focusedTags"], 0);
+    assert_eq!(data["seatFocusedOutput"]["name"], "FIX-1");
+    assert_eq!(data["seatMode"]["name"], "normal");
+}
+
+#[test]
+fn outputs_have_name_based_stable_ids() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let server = Server::spawn(&fixture);
+
+    let data = server.query_until("{ outputs { name outputId stableId } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| o.len() == 2)
+    });
+    assert_eq!(output(&data, "FIX-1")["stableId"], "output:FIX-1");
+    assert_eq!(output(&data, "FIX-2")["stableId"], "output:FIX-2");
+    assert!(output(&data, "FIX-1")["outputId"].is_string(), "{data}");
+}
+
+#[test]
+fn outputs_remember_their_previous_focused_tags() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    let query = "{ output(name: \"FIX-1\", tagList: true) { focusedTags previousFocusedTags previousFocusedTagsList } }";
+    let data = server.query_until(query, |data| data["output"]["focusedTags"] == 1);
+    assert!(data["output"]["previousFocusedTags"].is_null(), "{data}");
+
+    fixture.replay([
+        Command::FocusedTags { output: 0, tags: 4 },
+        // River repeats the focused tags on every focus change.
+        Command::FocusedTags { output: 0, tags: 4 },
+    ]);
+    let data = server.query_until(query, |data| data["output"]["focusedTags"] == 4);
+    assert_eq!(
+        data["output"],
+        json!({ "focusedTags": 4, "previousFocusedTags": 1, "previousFocusedTagsList": [0] })
+    );
+
+    fixture.send(Command::FocusedTags { output: 0, tags: 6 });
+    let data = server.query_until(query, |data| data["output"]["focusedTags"] == 6);
+    assert_eq!(data["output"]["previousFocusedTags"], 4);
+    assert_eq!(data["output"]["previousFocusedTagsList"], json!([2]));
+}
+
+#[test]
+fn server_info_reports_negotiated_capabilities() {
+    let fixture = Fixture::start_with_status_version(&["FIX-1"], 2);
+    let server = Server::spawn(&fixture);
+
+    let data = server.query(
+        "{ serverInfo { backend protocols { interface version } \
+           capabilities { focusedTags urgentTags seatMode layoutName } } }",
+    );
+    let info = &data["serverInfo"];
+    assert_eq!(info["backend"], "river");
+    assert!(
+        info["protocols"]
+            .as_array()
+            .expect("protocols list")
+            .contains(&json!({ "interface": "zriver_status_manager_v1", "version": 2 }))
+    );
+    assert_eq!(
+        info["capabilities"],
+        json!({ "focusedTags": true, "urgentTags": true, "seatMode": false, "layoutName": false })
+    );
+}
+
+#[test]
+fn debug_servers_list_bound_wayland_globals() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let query = "{ waylandGlobals { globals { interface name version objectId } \
+                 outputStatuses seatStatuses } }";
+
+    let plain = Server::spawn(&fixture);
+    let body = json!({ "query": query }).to_string();
+    let response: Value = serde_json::from_str(&plain.http(
+        "POST",
+        "/graphql",
+        &[("Content-Type", "application/json")],
+        &body,
+    ))
+    .expect("json response");
+    assert!(
+        response["errors"][0]["message"]
+            .as_str()
+            .is_some_and(|message| message.contains("--debug")),
+        "{response}"
+    );
+
+    let server = Server::spawn_with_args(&fixture, &["--debug"]);
+    let data = server.query_until(query, |data| data["waylandGlobals"]["outputStatuses"] == 2);
+    let registry = &data["waylandGlobals"];
+    assert_eq!(registry["seatStatuses"], 1);
+    let globals = registry["globals"].as_array().expect("globals list");
+    let outputs = globals
+        .iter()
+        .filter(|global| global["interface"] == "wl_output")
+        .collect::<Vec<_>>();
+    assert_eq!(outputs.len(), 2, "{registry}");
+    assert!(outputs.iter().all(|output| {
+        output["objectId"]
+            .as_str()
+            .is_some_and(|id| id.starts_with("wl_output@"))
+    }));
+    assert!(
+        globals
+            .iter()
+            .any(|global| global["interface"] == "zriver_status_manager_v1")
+    );
+}
+
+#[test]
+fn layout_demands_are_exposed_and_answered() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn_with_args(&fixture, &["--layout-namespace", "riverql"]);
+    server.query_until("{ outputs { name } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| !o.is_empty())
+    });
+
+    fixture.send(Command::LayoutDemand {
+        output: 0,
+        views: 3,
+        width: 1920,
+        height: 1050,
+    });
+    let query = "{ output(name: \"FIX-1\") { layoutDemand { viewCount usableWidth usableHeight tags } } \
+                 serverInfo { capabilities { layoutDemand } } }";
+    let data = server.query_until(query, |data| !data["output"]["layoutDemand"].is_null());
+    assert_eq!(
+        data["output"]["layoutDemand"],
+        json!({ "viewCount": 3, "usableWidth": 1920, "usableHeight": 1050, "tags": 1 })
+    );
+    assert_eq!(data["serverInfo"]["capabilities"]["layoutDemand"], true);
+    assert_eq!(
+        fixture.layout_commits(1),
+        vec![LayoutCommit {
+            output: 0,
+            name: "monocle".into(),
+            views: 3,
+        }]
+    );
+}
+
+#[test]
+fn scripted_events_update_snapshot() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let server = Server::spawn(&fixture);
+
+    fixture.replay([
+        Command::FocusedTags {
+            output: 1,
+            tags: 1 << 31,
+        },
+        Command::ViewTags {
+            output: 1,
+            tags: vec![1 << 31, 4],
+        },
+        Command::UrgentTags { output: 0, tags: 2 },
+        Command::LayoutName {
+            output: 0,
+            name: "rivertile".into(),
+        },
+        Command::FocusOutput { output: 1 },
+        Command::FocusedView {
+            title: "Emacs".into(),
+        },
+    ]);
+
+    let query = "{ outputs(tagList: true) { name focusedTags focusedTagsList viewTags urgentTags layoutName \
+                 tagIndicator custom: tagIndicator(format: { focused: \"<{}>\", empty: \".\", separator: \"\", tagCount: 4 }) \
+                 tagsSummary(tagCount: 3, names: [\"web\", \"code\"]) { number mask focused occupied urgent name } } \
+                 seatFocusedOutput { name } seatFocusedView { title } }";
+    let data = server.query_until(query, |data| {
+        data["seatFocusedView"]["title"] == "Emacs"
+            && data["seatFocusedOutput"]["name"] == "FIX-2"
+            && output(data, "FIX-2")["focusedTags"] == 1u32 << 31
+            && output(data, "FIX-1")["layoutName"] == "rivertile"
+    });
+
+    let second = output(&data, "FIX-2");
+    assert_eq!(second["focusedTags"], json!(2147483648u32));
+    assert_eq!(second["focusedTagsList"], json!([31]));
+    assert_eq!(second["viewTags"], json!([2147483648u32, 4]));
+    let first = output(&data, "FIX-1");
+    assert_eq!(first["urgentTags"], 2);
+    assert_eq!(first["layoutName"], "rivertile");
+    assert_eq!(first["tagIndicator"], "[1] *2");
+    assert_eq!(first["custom"], "<1>*2..");
+    assert_eq!(
+        first["tagsSummary"],
+        json!([
+            { "number": 1, "mask": 1, "focused": true, "occupied": false, "urgent": false, "name": "web" },
+            { "number": 2, "mask": 2, "focused": false, "occupied": false, "urgent": true, "name": "code" },
+            { "number": 3, "mask": 4, "focused": false, "occupied": false, "urgent": false, "name": null },
+        ])
+    );
+    assert_eq!(second["tagsSummary"][2]["occupied"], true);
+    assert_eq!(second["tagIndicator"], "3");
+    assert_eq!(data["seatFocusedOutput"]["name"], "FIX-2");
+}
+
+#[test]
+fn outputs_can_be_ordered_by_position() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2", "FIX-3"]);
+    let server = Server::spawn(&fixture);
+
+    // FIX-1 moves to the far right, FIX-3 below FIX-2.
+    fixture.replay([
+        Command::OutputPosition {
+            output: 0,
+            x: 3840,
+            y: 0,
+        },
+        Command::OutputPosition {
+            output: 2,
+            x: 1920,
+            y: 1080,
+        },
+    ]);
+    let expected = json!([
+        { "name": "FIX-2", "index": 0, "position": { "x": 1920, "y": 0 } },
+        { "name": "FIX-3", "index": 1, "position": { "x": 1920, "y": 1080 } },
+        { "name": "FIX-1", "index": 2, "position": { "x": 3840, "y": 0 } },
+    ]);
+    let query = "{ outputs(orderBy: POSITION) { name index position { x y } } \
+                 output(name: \"FIX-1\") { index } }";
+    let data = server.query_until(query, |data| data["outputs"] == expected);
+    assert_eq!(data["outputs"], expected);
+    assert_eq!(data["output"]["index"], 2);
+}
+
+#[test]
+fn outputs_by_tag_lists_where_a_tag_is_in_use() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2", "FIX-3"]);
+    let server = Server::spawn(&fixture);
+
+    fixture.replay([
+        Command::FocusedTags { output: 1, tags: 4 },
+        Command::ViewTags {
+            output: 2,
+            tags: vec![1, 4],
+        },
+        Command::UrgentTags { output: 0, tags: 4 },
+    ]);
+    let expected = json!({
+        "tag": 3,
+        "focused": [{ "name": "FIX-2" }],
+        "occupied": [{ "name": "FIX-3" }],
+        "urgent": [{ "name": "FIX-1" }],
+    });
+    let query =
+        "{ outputsByTag(tag: 3) { tag focused { name } occupied { name } urgent { name } } }";
+    let data = server.query_until(query, |data| data["outputsByTag"] == expected);
+    assert_eq!(data["outputsByTag"], expected);
+}
+
+#[test]
+fn focused_view_is_matched_to_its_toplevel() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+
+    fixture.replay([
+        Command::Toplevel {
+            index: 0,
+            title: "Terminal".into(),
+            app_id: "foot".into(),
+            activated: false,
+        },
+        Command::Toplevel {
+            index: 1,
+            title: "Emacs".into(),
+            app_id: "emacs".into(),
+            activated: true,
+        },
+        Command::FocusedView {
+            title: "Emacs".into(),
+        },
+    ]);
+    let query = "{ seatFocusedView { title appId viewId } serverInfo { capabilities { appId } } }";
+    let data = server.query_until(query, |data| data["seatFocusedView"]["appId"] == "emacs");
+    assert_eq!(data["serverInfo"]["capabilities"]["appId"], true);
+    let emacs = data["seatFocusedView"]["viewId"].clone();
+    assert!(emacs.is_string());
+
+    fixture.replay([
+        Command::Toplevel {
+            index: 0,
+            title: "Terminal".into(),
+            app_id: "foot".into(),
+            activated: true,
+        },
+        Command::FocusedView {
+            title: "Terminal".into(),
+        },
+    ]);
+    // The toplevel update can land before river's focus change.
+    let data = server.query_until(query, |data| {
+        data["seatFocusedView"]["appId"] == "foot" && data["seatFocusedView"]["title"] == "Terminal"
+    });
+    assert_ne!(data["seatFocusedView"]["viewId"], emacs);
+}
+
+#[test]
+fn get_requests_run_queries() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ outputs { name } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| !o.is_empty())
+    });
+
+    let body = server.http(
+        "GET",
+        "/graphql?query=query(%24n%3AString!)%7Boutput(name%3A%24n)%7Bname%20focusedTags%7D%7D\
+         &variables=%7B%22n%22%3A%22FIX-1%22%7D",
+        &[],
+        "",
+    );
+    let value: Value = serde_json::from_str(&body).expect("json response");
+    assert_eq!(
+        value,
+        json!({ "data": { "output": { "name": "FIX-1", "focusedTags": 1 } } })
+    );
+}
+
+#[test]
+fn batched_post_returns_results_in_order() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ outputs { name } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| !o.is_empty())
+    });
+
+    let batch = json!([
+        { "query": "{ seatMode { name } }" },
+        {
+            "query": "query($n: String!) { output(name: $n) { name } }",
+            "variables": { "n": "FIX-1" }
+        },
+    ]);
+    let body = server.http(
+        "POST",
+        "/graphql",
+        &[("Content-Type", "application/json")],
+        &batch.to_string(),
+    );
+    let value: Value = serde_json::from_str(&body).expect("json response");
+    assert_eq!(
+        value,
+        json!([
+            { "data": { "seatMode": { "name": "normal" } } },
+            { "data": { "output": { "name": "FIX-1" } } },
+        ])
+    );
+}
+
+#[test]
+fn cached_queries_see_snapshot_changes() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn_with_args(&fixture, &["--cache-ttl", "60000"]);
+    let query = "{ output(name: \"FIX-1\") { focusedTags } }";
+    server.query_until(query, |data| data["output"]["focusedTags"] == 1);
+    assert_eq!(server.query(query)["output"]["focusedTags"], 1);
+
+    fixture.send(Command::FocusedTags { output: 0, tags: 4 });
+    let data = server.query_until(query, |data| data["output"]["focusedTags"] == 4);
+    assert_eq!(data["output"]["focusedTags"], 4);
+}
+
+#[tokio::test]
+async fn suppressed_event_types_only_reach_the_snapshot() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn_with_args(&fixture, &["--suppress-events", "OutputViewTags"]);
+    fixture.replay([
+        Command::ViewTags {
+            output: 0,
+            tags: vec![2],
+        },
+        Command::FocusedTags { output: 0, tags: 8 },
+    ]);
+    server.query_until("{ output(name: \"FIX-1\") { focusedTags } }", |data| {
+        data["output"]["focusedTags"] == 8
+    });
+
+    // Neither the initial snapshot events nor live ones carry the type.
+    let mut sub = Subscription::start(
+        &server.ws_url(),
+        "subscription { events { __typename ... on OutputFocusedTags { tags } } }",
+    )
+    .await;
+    fixture.replay([
+        Command::ViewTags {
+            output: 0,
+            tags: vec![2, 4],
+        },
+        Command::FocusedTags {
+            output: 0,
+            tags: 16,
+        },
+    ]);
+    let first = sub
+        .next_matching(|data| {
+            data["events"]["__typename"] == "OutputViewTags" || data["events"]["tags"] == 16
+        })
+        .await;
+    assert_eq!(first["events"]["__typename"], "OutputFocusedTags");
+
+    let data = server.query(
+        "{ output(name: \"FIX-1\") { viewTags } \
+         changesSince(seq: 0) { seq complete patches { event { __typename } } } }",
+    );
+    assert_eq!(data["output"]["viewTags"], json!([2, 4]));
+    // The log kept no suppressed event, so it can't bring a client past one.
+    assert_eq!(data["changesSince"]["complete"], false);
+    let patches = data["changesSince"]["patches"].as_array().unwrap();
+    assert!(patches.is_empty());
+
+    let seq = data["changesSince"]["seq"].as_i64().unwrap();
+    fixture.send(Command::FocusedTags { output: 0, tags: 2 });
+    let query = format!(
+        "{{ changesSince(seq: {seq}) {{ complete patches {{ event {{ __typename }} }} }} }}"
+    );
+    let data = server.query_until(&query, |data| {
+        data["changesSince"]["patches"]
+            .as_array()
+            .is_some_and(|p| !p.is_empty())
+    });
+    assert_eq!(data["changesSince"]["complete"], true);
+    assert_eq!(
+        data["changesSince"]["patches"][0]["event"]["__typename"],
+        "OutputFocusedTags"
+    );
+}
+
+#[test]
+fn changes_since_replays_patches_after_a_seq() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    let snapshot =
+        "{ changesSince(seq: 0) { seq complete } output(name: \"FIX-1\") { focusedTags } }";
+    let data = server.query_until(snapshot, |data| data["output"]["focusedTags"] == 1);
+    let seq = data["changesSince"]["seq"].as_i64().unwrap();
+    assert!(seq > 0);
+    assert_eq!(data["changesSince"]["complete"], true);
+
+    fixture.replay([
+        Command::FocusedTags { output: 0, tags: 4 },
+        Command::LayoutName {
+            output: 0,
+            name: "rivertile".into(),
+        },
+    ]);
+    let query = format!(
+        "{{ changesSince(seq: {seq}, tagList: true) {{ seq complete patches {{ seq event {{ __typename \
+         ... on OutputFocusedTags {{ tagsList }} ... on OutputLayoutName {{ layout }} }} }} }} }}"
+    );
+    let data = server.query_until(&query, |data| {
+        data["changesSince"]["patches"]
+            .as_array()
+            .and_then(|patches| patches.last())
+            .is_some_and(|patch| patch["event"]["layout"] == "rivertile")
+    });
+    let changes = &data["changesSince"];
+    let last = changes["seq"].as_i64().unwrap();
+    let patches = changes["patches"].as_array().unwrap();
+    assert_eq!(changes["complete"], true);
+    assert_eq!(patches.len() as i64, last - seq);
+    assert_eq!(
+        patches[patches.len() - 2..],
+        [
+            json!({ "seq": last - 1, "event": { "__typename": "OutputFocusedTags", "tagsList": [2] } }),
+            json!({ "seq": last, "event": { "__typename": "OutputLayoutName", "layout": "rivertile" } }),
+        ]
+    );
+
+    let future = server.query(&format!(
+        "{{ changesSince(seq: {}) {{ complete patches {{ seq }} }} }}",
+        seq + 100
+    ));
+    assert_eq!(
+        future["changesSince"],
+        json!({ "complete": false, "patches": [] })
+    );
+}
+
+#[tokio::test]
+async fn subscription_streams_snapshot_then_updates() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ outputs { name } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| !o.is_empty())
+    });
+
+    let mut sub = Subscription::start(
+        &server.ws_url(),
+        "subscription { eventsForOutput(outputName: \"FIX-1\") { __typename ... on OutputFocusedTags { name tags } } }",
+    )
+    .await;
+
+    let initial = sub.next_data().await;
+    assert_eq!(
+        initial["eventsForOutput"]["__typename"],
+        "OutputFocusedTags"
+    );
+    assert_eq!(initial["eventsForOutput"]["tags"], 1);
+
+    fixture.send(Command::FocusedTags { output: 0, tags: 8 });
+    let update = sub
+        .next_matching(|data| data["eventsForOutput"]["tags"] == 8)
+        .await;
+    assert_eq!(update["eventsForOutput"]["name"], "FIX-1");
+}
+
+#[tokio::test]
+async fn output_subscriptions_get_their_output_and_seat_events_in_order() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ outputs { name } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| o.len() == 2)
+    });
+
+    let mut sub = Subscription::start(
+        &server.ws_url(),
+        "subscription { eventsForOutput(outputName: \"FIX-2\") { __typename \
+         ... on OutputFocusedTags { name tags } ... on SeatMode { mode: name } } }",
+    )
+    .await;
+    // The snapshot ends with the seat's events; wait for it before changing
+    // anything.
+    sub.next_matching(|data| data["eventsForOutput"]["mode"] == "normal")
+        .await;
+    fixture.replay([
+        Command::FocusedTags { output: 0, tags: 8 },
+        Command::Mode {
+            name: "resize".to_string(),
+        },
+        Command::FocusedTags { output: 1, tags: 4 },
+    ]);
+    let live = |data: &Value| {
+        let event = &data["eventsForOutput"];
+        event["name"] == "FIX-1" || event["mode"] == "resize" || event["tags"] == 4
+    };
+    let first = sub.next_matching(live).await;
+    assert_eq!(first["eventsForOutput"]["mode"], "resize");
+    let second = sub.next_matching(live).await;
+    assert_eq!(
+        second["eventsForOutput"],
+        json!({ "__typename": "OutputFocusedTags", "name": "FIX-2", "tags": 4 })
+    );
+}
+
+#[tokio::test]
+async fn lagging_subscribers_are_told_how_many_events_they_missed() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ outputs { name } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| o.len() == 1)
+    });
+
+    let mut sub = Subscription::start_slow(
+        &server.ws_url(),
+        "subscription { events { __typename ... on SubscriptionLagged { missed } } }",
+    )
+    .await;
+    sub.next_matching(|data| data["events"]["__typename"] == "SeatMode")
+        .await;
+    // Far more than the broadcast buffer and the socket buffers hold while
+    // the test isn't reading.
+    fixture.replay((0..8000).map(|i| Command::FocusedTags {
+        output: 0,
+        tags: 1 << (i % 2),
+    }));
+    let lagged = sub
+        .next_matching(|data| data["events"]["__typename"] == "SubscriptionLagged")
+        .await;
+    assert!(lagged["events"]["missed"].as_i64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn ws_deflate_compresses_subscriptions_for_clients_that_offer_it() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let plain = Server::spawn(&fixture);
+    let socket = DeflateSocket::connect(&plain.ws_url()).await;
+    assert_eq!(socket.protocol.as_deref(), Some("graphql-transport-ws"));
+    assert!(!socket.deflate, "compression is opt-in");
+
+    let server = Server::spawn_with_args(&fixture, &["--ws-deflate"]);
+    let mut socket = DeflateSocket::connect(&server.ws_url()).await;
+    assert_eq!(socket.protocol.as_deref(), Some("graphql-transport-ws"));
+    assert!(socket.deflate, "permessage-deflate was not negotiated");
+
+    // Both directions are compressed from here on.
+    socket
+        .send(json!({ "type": "connection_init", "payload": {} }))
+        .await;
+    assert_eq!(socket.next_json().await["type"], "connection_ack");
+    socket
+        .send(json!({ "id": "1", "type": "subscribe", "payload": {
+            "query": "subscription { events { __typename ... on OutputFocusedTags { name tags } } }"
+        } }))
+        .await;
+    fixture.send(Command::FocusedTags {
+        output: 0,
+        tags: 32,
+    });
+    loop {
+        let msg = socket.next_json().await;
+        assert_eq!(msg["type"], "next", "unexpected message {msg}");
+        if msg["payload"]["data"]["events"]["tags"] == 32 {
+            assert_eq!(msg["payload"]["data"]["events"]["name"], "FIX-1");
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn subscriptions_to_unknown_outputs_read_the_main_channel() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ outputs { name } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| o.len() == 1)
+    });
+
+    let mut sub = Subscription::start(
+        &server.ws_url(),
+        "subscription { eventsForOutput(outputName: \"NOPE-1\") { __typename \
+         ... on OutputFocusedTags { name } ... on SeatMode { mode: name } } }",
+    )
+    .await;
+    sub.next_matching(|data| data["eventsForOutput"]["mode"] == "normal")
+        .await;
+    fixture.replay([
+        Command::FocusedTags { output: 0, tags: 8 },
+        Command::Mode {
+            name: "resize".to_string(),
+        },
+    ]);
+    let next = sub
+        .next_matching(|data| data["eventsForOutput"]["mode"] != "normal")
+        .await;
+    assert_eq!(
+        next["eventsForOutput"],
+        json!({ "__typename": "SeatMode", "mode": "resize" })
+    );
+}
+
+#[tokio::test]
+async fn heartbeats_are_broadcast_while_river_is_quiet() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn_with_args(&fixture, &["--heartbeat", "1"]);
+
+    let mut sub = Subscription::start(
+        &server.ws_url(),
+        "subscription { events { ... on Heartbeat { seq at } } }",
+    )
+    .await;
+    let first = sub
+        .next_matching(|data| data["events"]["seq"].is_number())
+        .await;
+    let second = sub
+        .next_matching(|data| data["events"]["seq"].as_i64() > first["events"]["seq"].as_i64())
+        .await;
+    assert_eq!(
+        second["events"]["seq"].as_i64().unwrap(),
+        first["events"]["seq"].as_i64().unwrap() + 1
+    );
+    let gap = second["events"]["at"].as_i64().unwrap() - first["events"]["at"].as_i64().unwrap();
+    assert!((500..=1500).contains(&gap), "heartbeats {gap}ms apart");
+}
+
+#[test]
+fn control_fifo_adds_and_removes_operations() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    let fifo = std::env::temp_dir().join(format!("riverql-control-{}", std::process::id()));
+    let _ = std::fs::remove_file(&fifo);
+    let made = std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .expect("run mkfifo");
+    assert!(made.success());
+
+    let mut client = server.client(
+        &[
+            "--control",
+            fifo.to_str().unwrap(),
+            "subscription { events(types: [SEAT_MODE]) { ... on SeatMode { name } } }",
+        ],
+        None,
+    );
+    client.line_matching(|line| line.contains("\"id\":\"main\""));
+    let mut control = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&fifo)
+        .expect("open control fifo");
+    let mut command = |line: &str| {
+        use std::io::Write;
+        writeln!(control, "{line}").expect("write control command");
+    };
+
+    command(
+        "subscribe tags subscription { events(types: [OUTPUT_FOCUSED_TAGS]) { ... on OutputFocusedTags { tags } } }",
+    );
+    let line = client.line_matching(|line| line.contains("\"id\":\"tags\""));
+    let result: Value = serde_json::from_str(&line).expect("json result");
+    assert_eq!(result["data"]["events"]["tags"], 1);
+
+    // The probe's first result shows the unsubscribe went through before it.
+    command("unsubscribe tags");
+    command("subscribe probe { hello }");
+    client.line_matching(|line| line.contains("\"id\":\"probe\""));
+    fixture.replay([
+        Command::FocusedTags { output: 0, tags: 2 },
+        Command::Mode {
+            name: "resize".into(),
+        },
+    ]);
+    let line = client.line_matching(|line| line.contains("resize") || line.contains("\"tags\":2"));
+    assert!(line.contains("\"id\":\"main\""), "{line}");
+
+    // A rejected operation frees its id. The server answers in order, so
+    // once the second probe answers, the error came back too.
+    command("subscribe broken { nope }");
+    command("subscribe probe2 { hello }");
+    client.line_matching(|line| line.contains("\"id\":\"probe2\""));
+    command("subscribe broken { hello }");
+    let line = client.line_matching(|line| line.contains("\"id\":\"broken\""));
+    assert!(line.contains("\"hello\""), "{line}");
+    let _ = std::fs::remove_file(&fifo);
+}
+
+#[test]
+fn client_prints_a_summary_after_count_results() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+
+    let mut client = server.client(
+        &[
+            "--count",
+            "2",
+            "--summary",
+            "subscription { events(types: [OUTPUT_FOCUSED_TAGS]) { ... on OutputFocusedTags { tags } } }",
+        ],
+        None,
+    );
+    client.line_matching(|line| line.contains("\"tags\":1"));
+    fixture.send(Command::FocusedTags { output: 0, tags: 2 });
+    client.line_matching(|line| line.contains("\"tags\":2"));
+    let line = client.status_matching(|line| line.contains("\"exited\""));
+    let summary: Value = serde_json::from_str(&line).expect("json summary");
+    assert_eq!(summary["reason"], "count");
+    assert_eq!(summary["events"], 2);
+    assert_eq!(summary["reconnects"], 0);
+    assert!(summary["durationMs"].is_u64());
+}
+
+#[test]
+fn raw_client_prints_the_root_field_value() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+
+    let mut client = server.client(
+        &[
+            "--raw",
+            "subscription { events(types: [OUTPUT_FOCUSED_TAGS]) { ... on OutputFocusedTags { name tags } } }",
+        ],
+        None,
+    );
+    let line = client.line_matching(|line| line.contains("\"tags\":1"));
+    assert_eq!(
+        serde_json::from_str::<Value>(&line).expect("json line"),
+        json!({ "name": "FIX-1", "tags": 1 })
+    );
+    fixture.send(Command::FocusedTags { output: 0, tags: 2 });
+    let line = client.line_matching(|line| line.contains("\"tags\":2"));
+    assert_eq!(
+        serde_json::from_str::<Value>(&line).expect("json line"),
+        json!({ "name": "FIX-1", "tags": 2 })
+    );
+}
+
+#[test]
+fn client_script_formats_and_drops_results() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    let script = std::env::temp_dir().join(format!("riverql-script-{}.rhai", std::process::id()));
+    std::fs::write(
+        &script,
+        r#"
+            fn on_event(event) {
+                this.seen = (this.seen ?? 0) + 1;
+                if event.tags == 0 { return; }
+                `#${this.seen} ${event.name}: ${event.tags}`
+            }
+        "#,
+    )
+    .expect("write script");
+
+    let mut client = server.client(
+        &[
+            "--script",
+            script.to_str().expect("utf-8 path"),
+            "subscription { events(types: [OUTPUT_FOCUSED_TAGS]) { ... on OutputFocusedTags { name tags } } }",
+        ],
+        None,
+    );
+    assert_eq!(client.line_matching(|_| true), "#1 FIX-1: 1");
+    fixture.replay([
+        Command::FocusedTags { output: 0, tags: 0 },
+        Command::FocusedTags { output: 0, tags: 4 },
+    ]);
+    assert_eq!(client.line_matching(|_| true), "#3 FIX-1: 4");
+    let _ = std::fs::remove_file(&script);
+}
+
+#[test]
+fn pipe_to_feeds_a_restarted_command() {
+    let dir = std::env::temp_dir().join(format!("riverql-pipe-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create pipe dir");
+    let server_out = dir.join("server.out");
+    let client_out = dir.join("client.out");
+    let lines = |path: &std::path::Path, check: &dyn Fn(&[String]) -> bool| {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let lines = std::fs::read_to_string(path)
+                .unwrap_or_default()
+                .lines()
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+            if check(&lines) || Instant::now() > deadline {
+                return lines;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    };
+
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server_pipe = format!("cat >> '{}'", server_out.display());
+    let server = Server::spawn_with_args(&fixture, &["--pipe-to", &server_pipe]);
+    // Each run of the client's command takes one line and exits.
+    let client_pipe = format!("read -r line; echo \"$line\" >> '{}'", client_out.display());
+    let _client = server.client(
+        &[
+            "--pipe-to",
+            &client_pipe,
+            "--count",
+            "2",
+            "subscription { events(types: [OUTPUT_FOCUSED_TAGS]) { ... on OutputFocusedTags { tags } } }",
+        ],
+        None,
+    );
+    let first = lines(&client_out, &|lines| !lines.is_empty());
+    assert_eq!(first, [r#"{"data":{"events":{"tags":1}}}"#]);
+
+    // Let the first run exit so the next line goes to its replacement.
+    std::thread::sleep(Duration::from_millis(200));
+    fixture.send(Command::FocusedTags { output: 0, tags: 4 });
+    let client_lines = lines(&client_out, &|lines| lines.len() == 2);
+    assert_eq!(client_lines[1], r#"{"data":{"events":{"tags":4}}}"#);
+
+    let server_lines = lines(&server_out, &|lines| {
+        lines.iter().any(|line| line.contains(r#""tags":4"#))
+    });
+    let event: Value = server_lines
+        .iter()
+        .find_map(|line| {
+            serde_json::from_str::<Value>(line)
+                .ok()
+                .filter(|event| event["tags"] == 4)
+        })
+        .unwrap_or_else(|| panic!("no piped event in {server_lines:?}"));
+    assert_eq!(event["__typename"], "OutputFocusedTags");
+    assert_eq!(event["name"], "FIX-1");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn focused_view_changed_only_sends_matching_titles() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+
+    let mut sub = Subscription::start(
+        &server.ws_url(),
+        "subscription { focusedViewChanged(matching: \"^Emacs\") { title seat } }",
+    )
+    .await;
+    fixture.replay([
+        Command::FocusedView {
+            title: "Terminal".into(),
+        },
+        Command::FocusedView {
+            title: "Emacs: main.rs".into(),
+        },
+    ]);
+    let data = sub.next_data().await;
+    assert_eq!(
+        data["focusedViewChanged"],
+        json!({ "title": "Emacs: main.rs", "seat": "seat0" })
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn event_filter_matches_outputs_seats_and_tags() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let server = Server::spawn(&fixture);
+
+    let mut tags = Subscription::start(
+        &server.ws_url(),
+        "subscription { events(filter: { outputNames: [\"FIX-2\"], tagMask: 4 }) { \
+         __typename ... on OutputFocusedTags { name tags } } }",
+    )
+    .await;
+    let mut seat = Subscription::start(
+        &server.ws_url(),
+        "subscription { events(filter: { seatNames: [\"seat0\"], types: [SEAT_MODE] }) { \
+         ... on SeatMode { name seat } } }",
+    )
+    .await;
+    fixture.replay([
+        Command::FocusedTags { output: 0, tags: 4 },
+        Command::FocusedTags { output: 1, tags: 1 },
+        Command::FocusedTags { output: 1, tags: 4 },
+        Command::Mode {
+            name: "resize".into(),
+        },
+    ]);
+
+    let data = tags.next_data().await;
+    assert_eq!(
+        data["events"],
+        json!({ "__typename": "OutputFocusedTags", "name": "FIX-2", "tags": 4 })
+    );
+    let data = seat
+        .next_matching(|data| data["events"]["name"] == "resize")
+        .await;
+    assert_eq!(data["events"]["seat"], "seat0");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn output_events_carry_geometry_with_event_geometry() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let server = Server::spawn_with_args(&fixture, &["--event-geometry"]);
+    server.query_until(
+        "{ output(name: \"FIX-2\") { position { scale } } }",
+        |data| data["output"]["position"]["scale"] == 1,
+    );
+
+    let mut sub = Subscription::start(
+        &server.ws_url(),
+        "subscription { events(types: [OUTPUT_FOCUSED_TAGS]) { \
+         ... on OutputFocusedTags { name tags geometry { x y width height scale } } } }",
+    )
+    .await;
+    fixture.send(Command::OutputScale {
+        output: 1,
+        scale: 2,
+    });
+    server.query_until(
+        "{ output(name: \"FIX-2\") { position { scale } } }",
+        |data| data["output"]["position"]["scale"] == 2,
+    );
+    fixture.send(Command::FocusedTags { output: 1, tags: 4 });
+    let data = sub.next_matching(|data| data["events"]["tags"] == 4).await;
+    assert_eq!(data["events"]["name"], "FIX-2");
+    assert_eq!(
+        data["events"]["geometry"],
+        json!({ "x": 1920, "y": 0, "width": 960, "height": 540, "scale": 2 })
+    );
+}
+
+#[test]
+fn events_are_sent_as_datagrams() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let path = std::env::temp_dir().join(format!("riverql-dgram-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let socket = std::os::unix::net::UnixDatagram::bind(&path).expect("bind datagram socket");
+    socket
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .expect("set read timeout");
+    let server = Server::spawn_with_args(&fixture, &["--datagram", path.to_str().unwrap()]);
+    server.query_until("{ output(name: \"FIX-1\") { focusedTags } }", |data| {
+        data["output"]["focusedTags"] == 1
+    });
+
+    fixture.send(Command::LayoutName {
+        output: 0,
+        name: "rivertile".into(),
+    });
+    let mut buf = [0; 4096];
+    let event = loop {
+        let len = socket.recv(&mut buf).expect("receive event datagram");
+        let event: Value = serde_json::from_slice(&buf[..len]).expect("json datagram");
+        if event["__typename"] == "OutputLayoutName" && event["layout"] == "rivertile" {
+            break event;
+        }
+    };
+    assert_eq!(event["outputName"], "FIX-1");
+    assert!(event["outputId"].is_string());
+
+    // Sinks get every field, including tag lists and the optional geometry.
+    fixture.send(Command::FocusedTags { output: 0, tags: 4 });
+    let event = loop {
+        let len = socket.recv(&mut buf).expect("receive event datagram");
+        let event: Value = serde_json::from_slice(&buf[..len]).expect("json datagram");
+        if event["__typename"] == "OutputFocusedTags" && event["tags"] == 4 {
+            break event;
+        }
+    };
+    assert_eq!(event["tagsList"], json!([2]));
+    assert!(event.as_object().unwrap().contains_key("geometry"));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn events_are_written_to_the_journal_as_riverql_fields() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let path = std::env::temp_dir().join(format!("riverql-journal-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let socket = std::os::unix::net::UnixDatagram::bind(&path).expect("bind journal socket");
+    socket
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .expect("set read timeout");
+    let server = Server::spawn_journal(&fixture, &path);
+    server.query_until("{ output(name: \"FIX-1\") { focusedTags } }", |data| {
+        data["output"]["focusedTags"] == 1
+    });
+
+    // A newline in the value needs the length-prefixed binary form.
+    fixture.send(Command::Mode {
+        name: "resize\nor move".into(),
+    });
+    let mut buf = [0; 4096];
+    let fields = loop {
+        let len = socket.recv(&mut buf).expect("receive journal entry");
+        let fields = journal_fields(&buf[..len]);
+        if fields.get("RIVERQL_MODE").map(String::as_str) == Some("resize\nor move") {
+            break fields;
+        }
+    };
+    assert_eq!(fields["SYSLOG_IDENTIFIER"], "riverql");
+    assert_eq!(fields["MESSAGE"], "river event SeatMode");
+    assert_eq!(fields["RIVERQL_EVENT"], "SeatMode");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn metrics_count_events_and_active_subscriptions() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ outputs { name } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| !o.is_empty())
+    });
+
+    let mut sub = Subscription::start(
+        &server.ws_url(),
+        "subscription { events(types: [SEAT_MODE, OUTPUT_FOCUSED_TAGS]) { __typename } }",
+    )
+    .await;
+    sub.next_data().await;
+    fixture.send(Command::FocusedTags { output: 0, tags: 4 });
+    sub.next_matching(|data| data["events"]["__typename"] == "OutputFocusedTags")
+        .await;
+
+    let metrics = server.http("GET", "/metrics", &[], "");
+    let value = |series: &str| -> u64 {
+        metrics
+            .lines()
+            .find_map(|line| line.strip_prefix(series)?.trim().parse().ok())
+            .unwrap_or_else(|| panic!("{series} missing in {metrics}"))
+    };
+    assert_eq!(
+        value(
+            r#"riverql_subscriptions_active{field="events",types="OUTPUT_FOCUSED_TAGS,SEAT_MODE"}"#
+        ),
+        1
+    );
+    assert!(value(r#"riverql_events_total{type="OUTPUT_FOCUSED_TAGS"}"#) >= 2);
+    assert_eq!(
+        value(r#"riverql_events_total{type="OUTPUT_LAYOUT_DEMAND"}"#),
+        0
+    );
+    assert!(value(r#"riverql_http_requests_total{route="/graphql"}"#) >= 2);
+    assert_eq!(value("riverql_broadcast_lagged_events_total"), 0);
+    assert_eq!(value("riverql_river_dropped_events_total"), 0);
+    let latencies = value("riverql_event_latency_seconds_count");
+    assert!(latencies >= 2, "{metrics}");
+    assert_eq!(
+        value(r#"riverql_event_latency_seconds_bucket{le="+Inf"}"#),
+        latencies
+    );
+    assert!(value(r#"riverql_event_latency_seconds_bucket{le="1"}"#) <= latencies);
+
+    drop(sub);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let metrics = server.http("GET", "/metrics", &[], "");
+        if metrics.contains(r#"types="OUTPUT_FOCUSED_TAGS,SEAT_MODE"} 0"#) {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "subscription still active: {metrics}"
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn extra_displays_are_served_under_their_name() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let nested = Fixture::start(&["NEST-1", "NEST-2"]);
+    let display = format!("nested={}", nested.socket().display());
+    let server = Server::spawn_with_args(&fixture, &["--display", &display]);
+
+    let query =
+        "{ outputs { name } displays { name } display(name: \"nested\") { outputs { name } } }";
+    let data = server.query_until(query, |data| {
+        data["display"]["outputs"]
+            .as_array()
+            .is_some_and(|o| o.len() == 2)
+    });
+    assert_eq!(data["outputs"], json!([{ "name": "FIX-1" }]));
+    assert_eq!(data["displays"], json!([{ "name": "nested" }]));
+    assert_eq!(
+        data["display"]["outputs"],
+        json!([{ "name": "NEST-1" }, { "name": "NEST-2" }])
+    );
+    assert_eq!(
+        server.query("{ display(name: \"other\") { name } }")["display"],
+        Value::Null
+    );
+
+    let mut sub = Subscription::start(
+        &server.ws_url(),
+        "subscription { events(display: \"nested\", types: [OUTPUT_FOCUSED_TAGS]) { ... on OutputFocusedTags { name tags } } }",
+    )
+    .await;
+    sub.next_data().await;
+    fixture.send(Command::FocusedTags { output: 0, tags: 2 });
+    nested.send(Command::FocusedTags { output: 1, tags: 8 });
+    let update = sub.next_matching(|data| data["events"]["tags"] == 8).await;
+    assert_eq!(update["events"]["name"], "NEST-2");
+
+    let data = server.query_until(
+        "{ output(name: \"FIX-1\") { focusedTags } display(name: \"nested\") { output(name: \"NEST-1\") { focusedTags } } }",
+        |data| data["output"]["focusedTags"] == 2,
+    );
+    assert_eq!(data["display"]["output"]["focusedTags"], 1);
+}
+
+#[tokio::test]
+async fn idle_websockets_are_closed() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn_with_args(&fixture, &["--idle-timeout", "1"]);
+
+    let started = Instant::now();
+    let mut idle = Subscription::connect(&server.ws_url()).await;
+    let mut busy = Subscription::start(
+        &server.ws_url(),
+        "subscription { events(types: [OUTPUT_FOCUSED_TAGS]) { ... on OutputFocusedTags { tags } } }",
+    )
+    .await;
+    busy.next_data().await;
+
+    assert_eq!(idle.closed().await, Some(1000));
+    assert!(started.elapsed() >= Duration::from_secs(1));
+
+    // An open subscription keeps its connection alive past the timeout.
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    fixture.send(Command::FocusedTags { output: 0, tags: 4 });
+    busy.next_matching(|data| data["events"]["tags"] == 4).await;
+}
+
+#[tokio::test]
+async fn subscriptions_per_connection_are_capped() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn_with_args(&fixture, &["--max-subscriptions", "2"]);
+    server.query_until("{ seatMode { name } }", |data| !data["seatMode"].is_null());
+    let query = "subscription { events(types: [SEAT_MODE]) { __typename } }";
+
+    let mut conn = Subscription::connect(&server.ws_url()).await;
+    for id in ["1", "2", "3"] {
+        conn.subscribe(id, query).await;
+    }
+    for id in ["1", "2"] {
+        assert_eq!(conn.next_message_for(id).await["type"], "next");
+    }
+    let rejected = conn.next_message_for("3").await;
+    let message = rejected["payload"]["errors"][0]["message"]
+        .as_str()
+        .unwrap_or_else(|| panic!("expected an error, got {rejected}"));
+    assert!(message.contains("limit 2"), "{message}");
+    assert_eq!(conn.next_message_for("3").await["type"], "complete");
+
+    // Ending one operation frees its slot; other connections have their own.
+    conn.stop("1").await;
+    conn.subscribe("4", query).await;
+    assert_eq!(conn.next_message_for("4").await["type"], "next");
+    let mut other = Subscription::start(&server.ws_url(), query).await;
+    assert_eq!(other.next_data().await["events"]["__typename"], "SeatMode");
+}
+
+#[test]
+fn bar_renders_status_line_on_change() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ outputs { name } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| !o.is_empty())
+    });
+
+    let mut bar = server.bar(&["--format", "{output}:{tags}|{layout}|{title}"]);
+    bar.line_matching(|line| line == "FIX-1:[1]||");
+
+    fixture.replay([
+        Command::ViewTags {
+            output: 0,
+            tags: vec![1, 4],
+        },
+        Command::UrgentTags { output: 0, tags: 2 },
+        Command::LayoutName {
+            output: 0,
+            name: "rivertile".into(),
+        },
+        Command::FocusedView {
+            title: "Emacs".into(),
+        },
+    ]);
+    bar.line_matching(|line| line == "FIX-1:[1]!2! 3 |rivertile|Emacs");
+}
+
+#[test]
+fn subscribe_runs_named_queries_with_default_variables() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ outputs { name } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| o.len() == 2)
+    });
+
+    let config = std::env::temp_dir().join(format!("riverql-config-{}", std::process::id()));
+    let queries = config.join("riverql").join("queries");
+    std::fs::create_dir_all(&queries).expect("create query library");
+    std::fs::write(
+        queries.join("tags.graphql"),
+        "subscription ($output: String!, $tagList: Boolean) { \
+         eventsForOutput(outputName: $output, tagList: $tagList) { \
+         ... on OutputFocusedTags { name tags tagsList } } }",
+    )
+    .expect("write query");
+    std::fs::write(queries.join("tags.json"), r#"{ "output": "FIX-1" }"#).expect("write variables");
+
+    let mut defaults = server.client(&["subscribe", "tags"], Some(&config));
+    let line = defaults.line_matching(|line| line.contains("FIX"));
+    let payload: Value = serde_json::from_str(&line).expect("json line");
+    assert_eq!(
+        payload["data"]["eventsForOutput"],
+        json!({ "name": "FIX-1", "tags": 1, "tagsList": null })
+    );
+
+    let mut overridden = server.client(
+        &[
+            "subscribe",
+            "tags",
+            "--var",
+            "output=FIX-2",
+            "--var",
+            "tagList=true",
+        ],
+        Some(&config),
+    );
+    let line = overridden.line_matching(|line| line.contains("FIX-2"));
+    let payload: Value = serde_json::from_str(&line).expect("json line");
+    assert_eq!(
+        payload["data"]["eventsForOutput"],
+        json!({ "name": "FIX-2", "tags": 0, "tagsList": [] })
+    );
+
+    let _ = std::fs::remove_dir_all(&config);
+}
+
+#[test]
+fn repl_runs_piped_operations_in_turn() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ output(name: \"FIX-1\") { focusedTags } }", |data| {
+        data["output"]["focusedTags"] == 1
+    });
+
+    let mut repl = server.client_with_input(
+        &["repl"],
+        "{ output(name: \"FIX-1\") {\n  focusedTags\n} }\n{ bogus }\n{ seatFocusedOutput { name } }\n",
+    );
+    let first: Value =
+        serde_json::from_str(&repl.line_matching(|line| line.contains("output"))).expect("json");
+    assert_eq!(first["data"]["output"]["focusedTags"], 1);
+    let second: Value =
+        serde_json::from_str(&repl.line_matching(|line| line.contains("errors"))).expect("json");
+    assert!(
+        second["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("bogus")
+    );
+    let third: Value =
+        serde_json::from_str(&repl.line_matching(|line| line.contains("seatFocusedOutput")))
+            .expect("json");
+    assert_eq!(third["data"]["seatFocusedOutput"]["name"], "FIX-1");
+}
+
+#[test]
+fn reconnecting_client_reports_status_on_stderr() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+
+    let mut client = server.client(
+        &["--reconnect", "subscription { events { __typename } }"],
+        None,
+    );
+    let status = |line: &str| serde_json::from_str::<Value>(line).expect("json status line");
+    let connected = client.status_matching(|line| line.contains("\"connected\""));
+    assert_eq!(
+        status(&connected),
+        json!({ "status": "connected", "attempt": 1 })
+    );
+    client.line_matching(|line| line.contains("__typename"));
+
+    drop(server);
+    let retrying = status(&client.status_matching(|line| line.contains("\"retrying\"")));
+    assert_eq!(retrying["attempt"], 2);
+    assert!(retrying["delayMs"].as_u64().is_some_and(|ms| ms > 0));
+    let connecting = client.status_matching(|line| line.contains("\"connecting\""));
+    assert_eq!(
+        status(&connecting),
+        json!({ "status": "connecting", "attempt": 2 })
+    );
+}
+
+#[test]
+fn types_and_fields_generate_the_subscription() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    server.query_until(
+        "{ seatMode { name } output(name: \"FIX-1\") { focusedTags } }",
+        |data| !data["seatMode"].is_null() && data["output"]["focusedTags"] == 1,
+    );
+
+    let mut client = server.client(
+        &[
+            "--types",
+            "OutputFocusedTags,SeatMode",
+            "--fields",
+            "tags,name,tags_list",
+        ],
+        None,
+    );
+    let line = client.line_matching(|line| line.contains("OutputFocusedTags"));
+    let payload: Value = serde_json::from_str(&line).expect("json line");
+    assert_eq!(
+        payload["data"]["events"],
+        json!({ "__typename": "OutputFocusedTags", "tags": 1, "name": "FIX-1", "tagsList": [0] })
+    );
+    let line = client.line_matching(|line| line.contains("SeatMode"));
+    let payload: Value = serde_json::from_str(&line).expect("json line");
+    assert!(payload["data"]["events"]["name"].is_string());
+    assert!(payload["data"]["events"].get("tags").is_none());
+
+    let mut typo = server.client(&["--types", "SeatModes"], None);
+    typo.status_matching(|line| line.contains("unknown event type \"SeatModes\""));
+}
+
+#[test]
+fn shell_format_prints_evaluable_assignments() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn(&fixture);
+    fixture.replay([
+        Command::ViewTags {
+            output: 0,
+            tags: vec![1, 4],
+        },
+        Command::FocusedView {
+            title: "it's $HOME".into(),
+        },
+    ]);
+    server.query_until("{ seatFocusedView { title } }", |data| {
+        data["seatFocusedView"]["title"] == "it's $HOME"
+    });
+
+    let mut client = server.client(
+        &[
+            "--format",
+            "shell",
+            "{ outputs(tagList: true) { output: name viewTagsList } seatFocusedView { title } }",
+        ],
+        None,
+    );
+    let line = client.line_matching(|line| line.starts_with("RIVERQL_"));
+    let evaluated = std::process::Command::new("sh")
+        .arg("-c")
+        .arg("eval \"$1\"; printf '%s|%s|%s' \"$RIVERQL_OUTPUTS_0_OUTPUT\" \"$RIVERQL_SEAT_FOCUSED_VIEW_TITLE\" \"$RIVERQL_OUTPUTS_0_VIEW_TAGS_LIST\"")
+        .arg("sh")
+        .arg(&line)
+        .output()
+        .expect("run sh");
+    assert_eq!(
+        String::from_utf8_lossy(&evaluated.stdout),
+        "FIX-1|it's $HOME|0 2"
+    );
+}
+
+#[test]
+fn table_format_aligns_list_results() {
+    let fixture = Fixture::start(&["FIX-1", "LONGER-2"]);
+    let server = Server::spawn(&fixture);
+    fixture.replay([
+        Command::FocusedTags { output: 0, tags: 1 },
+        Command::FocusedTags {
+            output: 1,
+            tags: 12,
+        },
+    ]);
+    let query = "{ outputs(orderBy: NAME) { name focusedTags } }";
+    server.query_until(query, |data| data["outputs"][1]["focusedTags"] == 12);
+
+    let mut client = server.client(&["--format", "table", query], None);
+    assert_eq!(
+        client.line_matching(|line| line.starts_with("name")),
+        "name      focusedTags"
+    );
+    assert_eq!(client.line_matching(|_| true), "FIX-1     1");
+    assert_eq!(client.line_matching(|_| true), "LONGER-2  12");
+}
+
+#[tokio::test]
+async fn live_query_pushes_changed_results() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ outputs { name } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| o.len() == 2)
+    });
+
+    let mut live = Subscription::start(
+        &server.ws_url(),
+        "query @live { seatFocusedOutput { name } }",
+    )
+    .await;
+    let initial = live.next_data().await;
+    assert_eq!(initial["seatFocusedOutput"]["name"], "FIX-1");
+
+    // Changes outside the selection don't produce a result.
+    fixture.send(Command::Mode {
+        name: "locked".into(),
+    });
+    fixture.send(Command::FocusOutput { output: 1 });
+    let update = live.next_data().await;
+    assert_eq!(update["seatFocusedOutput"]["name"], "FIX-2");
+}
+
+#[test]
+fn river_runs_mutations_over_river_control() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ seatFocusedOutput { name } }", |data| {
+        data["seatFocusedOutput"]["name"] == "FIX-1"
+    });
+
+    fixture.replay([
+        Command::FocusedTags { output: 0, tags: 4 },
+        Command::FocusedTags { output: 0, tags: 1 },
+    ]);
+    server.query_until("{ outputs { name previousFocusedTags } }", |data| {
+        output(data, "FIX-1")["previousFocusedTags"] == 4
+    });
+    // The focused output needs no focus moved and restored.
+    let data = server.query("mutation { focusPreviousTags }");
+    assert_eq!(data["focusPreviousTags"], 4);
+    assert_eq!(
+        fixture.control_commands(),
+        [vec!["set-focused-tags".to_string(), "4".to_string()]]
+    );
+    let data = server.query_until("{ outputs { name focusedTags } }", |data| {
+        output(data, "FIX-1")["focusedTags"] == 4
+    });
+    assert_eq!(output(&data, "FIX-1")["focusedTags"], 4);
+
+    fixture.replay([
+        Command::FocusedTags { output: 1, tags: 2 },
+        Command::FocusedTags { output: 1, tags: 8 },
+    ]);
+    server.query_until("{ outputs { name focusedTags } }", |data| {
+        output(data, "FIX-2")["focusedTags"] == 8
+    });
+    let data = server.query(r#"mutation { focusPreviousTags(output: "FIX-2") }"#);
+    assert_eq!(data["focusPreviousTags"], 2);
+    assert_eq!(
+        fixture.control_commands()[1..],
+        [
+            vec!["focus-output".to_string(), "FIX-2".to_string()],
+            vec!["set-focused-tags".to_string(), "2".to_string()],
+            vec!["focus-output".to_string(), "FIX-1".to_string()],
+        ]
+    );
+    let query = "{ outputs { name focusedTags } seatFocusedOutput { name } }";
+    let data = server.query_until(query, |data| {
+        output(data, "FIX-2")["focusedTags"] == 2 && data["seatFocusedOutput"]["name"] == "FIX-1"
+    });
+    assert_eq!(output(&data, "FIX-2")["focusedTags"], 2);
+    assert_eq!(output(&data, "FIX-1")["focusedTags"], 4);
+    assert_eq!(data["seatFocusedOutput"]["name"], "FIX-1");
+}
+
+#[test]
+fn dedup_keeps_focus_returning_to_the_previous_output() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let server = Server::spawn_with_args(&fixture, &["--dedup"]);
+    let query = "{ seatFocusedOutput { name } }";
+    let data = server.query_until(query, |data| data["seatFocusedOutput"]["name"] == "FIX-1");
+    assert_eq!(data["seatFocusedOutput"]["name"], "FIX-1");
+
+    fixture.send(Command::FocusOutput { output: 1 });
+    let data = server.query_until(query, |data| data["seatFocusedOutput"]["name"] == "FIX-2");
+    assert_eq!(data["seatFocusedOutput"]["name"], "FIX-2");
+
+    // FIX-1's focus event equals the last one FIX-1 got, but it is news.
+    fixture.send(Command::FocusOutput { output: 0 });
+    let data = server.query_until(query, |data| data["seatFocusedOutput"]["name"] == "FIX-1");
+    assert_eq!(data["seatFocusedOutput"]["name"], "FIX-1");
+}
+
+fn sway_window(id: i64, name: &str, app_id: &str, focused: bool) -> Value {
+    json!({ "id": id, "type": "con", "name": name, "app_id": app_id, "focused": focused, "nodes": [] })
+}
+
+fn sway_tree(focused: i64) -> Value {
+    let workspace = |num: i32, nodes: Vec<Value>| json!({ "id": 100 + num, "type": "workspace", "name": num.to_string(), "num": num, "nodes": nodes });
+    json!({
+        "id": 1,
+        "type": "root",
+        "nodes": [
+            { "id": 2, "type": "output", "name": "__i3", "nodes": [] },
+            { "id": 3, "type": "output", "name": "DP-1", "nodes": [
+                workspace(1, vec![sway_window(7, "Terminal", "foot", focused == 7)]),
+                workspace(2, vec![json!({ "id": 8, "type": "con", "nodes": [
+                    sway_window(9, "Firefox", "firefox", focused == 9),
+                    sway_window(10, "Emacs", "emacs", focused == 10),
+                ] })]),
+            ] },
+            { "id": 4, "type": "output", "name": "HDMI-A-1", "nodes": [workspace(3, vec![])] },
+        ]
+    })
+}
+
+fn sway_workspaces(visible_on_dp1: i32) -> Value {
+    json!([
+        { "num": 1, "output": "DP-1", "visible": visible_on_dp1 == 1, "focused": visible_on_dp1 == 1,
+          "urgent": false, "layout": "splith" },
+        { "num": 2, "output": "DP-1", "visible": visible_on_dp1 == 2, "focused": visible_on_dp1 == 2,
+          "urgent": visible_on_dp1 != 2, "layout": "tabbed" },
+        { "num": 3, "output": "HDMI-A-1", "visible": true, "focused": false, "urgent": false,
+          "layout": "splitv" },
+    ])
+}
+
+#[test]
+fn sway_backend_maps_workspaces_to_tags() {
+    use support::sway::{FakeSway, MODE_EVENT, SwayState, WORKSPACE_EVENT};
+
+    let sway = FakeSway::start(SwayState {
+        outputs: json!([
+            { "name": "DP-1", "active": true, "scale": 1.0,
+              "rect": { "x": 0, "y": 0, "width": 1920, "height": 1080 } },
+            { "name": "HDMI-A-1", "active": true, "scale": 1.5,
+              "rect": { "x": 1920, "y": 0, "width": 1280, "height": 720 } },
+        ]),
+        workspaces: sway_workspaces(1),
+        tree: sway_tree(7),
+    });
+    let server = Server::spawn_sway(&sway);
+    let query = "{ serverInfo { backend source capabilities { urgentTags layoutDemand appId } } \
+                   outputs(orderBy: POSITION) { name focusedTags viewTags urgentTags layoutName \
+                   position { x width scale } } \
+                   seatFocusedOutput { name } seatFocusedView { title appId viewId } seatMode { name } }";
+
+    let data = server.query_until(query, |data| data["seatMode"]["name"] == "default");
+    assert_eq!(data["serverInfo"]["backend"], "sway");
+    assert_eq!(data["serverInfo"]["source"], "SWAY");
+    assert_eq!(
+        data["serverInfo"]["capabilities"],
+        json!({ "urgentTags": true, "layoutDemand": false, "appId": true })
+    );
+    assert_eq!(
+        data["outputs"],
+        json!([
+            { "name": "DP-1", "focusedTags": 1, "viewTags": [1, 2, 2], "urgentTags": 2,
+              "layoutName": "splith", "position": { "x": 0, "width": 1920, "scale": 1 } },
+            { "name": "HDMI-A-1", "focusedTags": 4, "viewTags": [], "urgentTags": 0,
+              "layoutName": "splitv", "position": { "x": 1920, "width": 1280, "scale": 2 } },
+        ])
+    );
+    assert_eq!(data["seatFocusedOutput"]["name"], "DP-1");
+    assert_eq!(
+        data["seatFocusedView"],
+        json!({ "title": "Terminal", "appId": "foot", "viewId": "7" })
+    );
+
+    sway.update(|state| {
+        state.workspaces = sway_workspaces(2);
+        state.tree = sway_tree(10);
+    });
+    sway.event(WORKSPACE_EVENT, json!({ "change": "focus" }));
+    sway.event(
+        MODE_EVENT,
+        json!({ "change": "resize", "pango_markup": false }),
+    );
+    let data = server.query_until(query, |data| {
+        data["seatMode"]["name"] == "resize" && data["outputs"][0]["focusedTags"] == 2
+    });
+    assert_eq!(data["outputs"][0]["urgentTags"], 0);
+    assert_eq!(data["outputs"][0]["layoutName"], "tabbed");
+    assert_eq!(data["seatFocusedView"]["title"], "Emacs");
+
+    let data = server.query(r#"mutation { focusPreviousTags(output: "DP-1") }"#);
+    assert_eq!(data["focusPreviousTags"], 1);
+    // DP-1 has focus, so there is no focus to move and restore.
+    assert_eq!(sway.commands(), ["workspace number 1"]);
+}
+
+fn hyprland_client(address: &str, workspace: i32, monitor: i64) -> Value {
+    json!({ "address": address, "mapped": true, "hidden": false,
+            "workspace": { "id": workspace, "name": workspace.to_string() },
+            "monitor": monitor, "class": "foot", "title": "Terminal" })
+}
+
+#[test]
+fn hyprland_backend_is_detected_from_the_environment() {
+    use support::hyprland::{FakeHyprland, HyprlandState};
+
+    let monitor = |id: i64, name: &str, x: i32, scale: f64, workspace: i32, focused: bool| {
+        json!({ "id": id, "name": name, "x": x, "y": 0, "width": 2560, "height": 1440,
+                "scale": scale, "activeWorkspace": { "id": workspace, "name": workspace.to_string() },
+                "focused": focused, "disabled": false })
+    };
+    let hyprland = FakeHyprland::start(HyprlandState {
+        monitors: json!([
+            monitor(0, "DP-1", 0, 1.0, 1, true),
+            monitor(1, "DP-2", 2560, 1.25, 5, false),
+        ]),
+        clients: json!([
+            hyprland_client("0xa1", 1, 0),
+            hyprland_client("0xa2", 2, 0),
+            hyprland_client("0xb1", 5, 1),
+        ]),
+        active_window: json!({ "address": "0xa1", "class": "foot", "title": "Terminal" }),
+    });
+    let server = Server::spawn_hyprland(&hyprland);
+    let query = "{ serverInfo { backend source } \
+                   outputs(orderBy: POSITION) { name focusedTags viewTags urgentTags layoutName \
+                   position { width scale } } \
+                   seatFocusedOutput { name } seatFocusedView { title appId viewId } seatMode { name } }";
+
+    let data = server.query_until(query, |data| data["seatMode"]["name"] == "default");
+    assert_eq!(
+        data["serverInfo"],
+        json!({ "backend": "hyprland", "source": "HYPRLAND" })
+    );
+    assert_eq!(
+        data["outputs"],
+        json!([
+            { "name": "DP-1", "focusedTags": 1, "viewTags": [1, 2], "urgentTags": 0,
+              "layoutName": "dwindle", "position": { "width": 2560, "scale": 1 } },
+            { "name": "DP-2", "focusedTags": 16, "viewTags": [16], "urgentTags": 0,
+              "layoutName": "dwindle", "position": { "width": 2048, "scale": 2 } },
+        ])
+    );
+    assert_eq!(data["seatFocusedOutput"]["name"], "DP-1");
+    assert_eq!(
+        data["seatFocusedView"],
+        json!({ "title": "Terminal", "appId": "foot", "viewId": "0xa1" })
+    );
+
+    hyprland.event("urgent", "a2");
+    hyprland.event("submap", "resize");
+    let data = server.query_until(query, |data| {
+        data["seatMode"]["name"] == "resize" && data["outputs"][0]["urgentTags"] == 2
+    });
+    assert_eq!(data["outputs"][1]["urgentTags"], 0);
+
+    hyprland.update(|state| {
+        state.monitors[0]["activeWorkspace"]["id"] = json!(2);
+        state.active_window = json!({ "address": "0xa2", "class": "foot", "title": "Terminal" });
+    });
+    hyprland.event("activewindowv2", "a2");
+    hyprland.event("submap", "");
+    let data = server.query_until(query, |data| {
+        data["seatMode"]["name"] == "default" && data["outputs"][0]["focusedTags"] == 2
+    });
+    assert_eq!(data["outputs"][0]["urgentTags"], 0);
+    assert_eq!(data["seatFocusedView"]["viewId"], "0xa2");
+}
+
+fn niri_window(id: u64, title: &str, workspace_id: u64, focused: bool) -> Value {
+    json!({ "id": id, "title": title, "app_id": "foot", "pid": 100 + id,
+            "workspace_id": workspace_id, "is_focused": focused, "is_floating": false,
+            "is_urgent": false })
+}
+
+fn niri_workspaces(active_on_dp1: u64) -> Value {
+    let workspace = |id: u64, idx: u32, output: &str, active: bool, focused: bool| {
+        json!({ "id": id, "idx": idx, "name": null, "output": output, "is_urgent": false,
+                "is_active": active, "is_focused": focused, "active_window_id": null })
+    };
+    json!([
+        workspace(1, 1, "DP-1", active_on_dp1 == 1, active_on_dp1 == 1),
+        workspace(2, 2, "DP-1", active_on_dp1 == 2, active_on_dp1 == 2),
+        workspace(3, 1, "HDMI-A-1", true, false),
+    ])
+}
+
+#[test]
+fn niri_backend_maps_workspace_indexes_to_tags() {
+    use support::niri::{FakeNiri, NiriState};
+
+    let output = |x: i32, width: i32, scale: f64| {
+        json!({ "name": "", "make": "", "model": "", "serial": null,
+                "logical": { "x": x, "y": 0, "width": width, "height": 1080, "scale": scale,
+                             "transform": "Normal" } })
+    };
+    let niri = FakeNiri::start(NiriState {
+        outputs: json!({
+            "DP-1": output(0, 1920, 1.0),
+            "HDMI-A-1": output(1920, 1280, 1.5),
+            "eDP-1": { "name": "eDP-1", "logical": null },
+        }),
+        workspaces: niri_workspaces(1),
+        windows: json!([
+            niri_window(7, "Terminal", 1, true),
+            niri_window(8, "Editor", 2, false),
+            niri_window(9, "Browser", 3, false),
+        ]),
+    });
+    let server = Server::spawn_niri(&niri);
+    let query = "{ serverInfo { backend capabilities { urgentTags seatMode layoutName } } \
+                   outputs(orderBy: POSITION) { name focusedTags viewTags position { width scale } } \
+                   seatFocusedOutput { name } seatFocusedView { title viewId } }";
+
+    let data = server.query_until(query, |data| !data["seatFocusedView"].is_null());
+    assert_eq!(data["serverInfo"]["backend"], "niri");
+    assert_eq!(
+        data["serverInfo"]["capabilities"],
+        json!({ "urgentTags": true, "seatMode": false, "layoutName": false })
+    );
+    assert_eq!(
+        data["outputs"],
+        json!([
+            { "name": "DP-1", "focusedTags": 1, "viewTags": [1, 2],
+              "position": { "width": 1920, "scale": 1 } },
+            { "name": "HDMI-A-1", "focusedTags": 1, "viewTags": [1],
+              "position": { "width": 1280, "scale": 2 } },
+        ])
+    );
+    assert_eq!(data["seatFocusedOutput"]["name"], "DP-1");
+    assert_eq!(
+        data["seatFocusedView"],
+        json!({ "title": "Terminal", "viewId": "7" })
+    );
+
+    niri.update(|state| {
+        state.workspaces = niri_workspaces(2);
+        state.windows[0]["is_focused"] = json!(false);
+        state.windows[1]["is_focused"] = json!(true);
+    });
+    niri.event(json!({ "WorkspaceActivated": { "id": 2, "focused": true } }));
+    let data = server.query_until(query, |data| data["outputs"][0]["focusedTags"] == 2);
+    assert_eq!(data["seatFocusedView"]["title"], "Editor");
+}
+
+#[test]
+fn snapshot_prints_the_current_status_once() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let server = Server::spawn(&fixture);
+    server.query_until("{ outputs { name } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| o.len() == 2)
+    });
+
+    let mut client = server.client(&["snapshot"], None);
+    let line = client.line_matching(|line| line.starts_with('{'));
+    let data: Value = serde_json::from_str(&line).expect("json snapshot");
+    assert_eq!(data["serverInfo"]["backend"], "river");
+    assert_eq!(output(&data, "FIX-1")["focusedTags"], 1);
+    assert_eq!(data["seatFocusedOutput"]["name"], "FIX-1");
+    assert_eq!(data["seatMode"]["name"], "normal");
+
+    let standalone = support::run_standalone(&fixture, &["snapshot", "--standalone"]);
+    assert!(standalone.status.success(), "{standalone:?}");
+    let data: Value = serde_json::from_slice(&standalone.stdout).expect("json snapshot");
+    assert_eq!(
+        data["outputs"].as_array().map(|outputs| outputs
+            .iter()
+            .map(|o| o["name"].clone())
+            .collect::<Vec<_>>()),
+        Some(vec![json!("FIX-1"), json!("FIX-2")])
+    );
+    assert_eq!(output(&data, "FIX-1")["focusedTags"], 1);
+    assert_eq!(data["seatFocusedOutput"]["name"], "FIX-1");
+}
+
+#[test]
+fn clients_find_the_server_through_its_discovery_file() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let runtime_dir =
+        std::env::temp_dir().join(format!("riverql-discovery-{}", std::process::id()));
+    std::fs::create_dir_all(&runtime_dir).expect("create runtime dir");
+    let file = runtime_dir.join("riverql.json");
+    let mut server = Server::spawn_in_runtime_dir(&fixture, &runtime_dir);
+
+    let discovery: Value =
+        serde_json::from_str(&std::fs::read_to_string(&file).expect("discovery file"))
+            .expect("json discovery file");
+    assert_eq!(
+        server.ws_url(),
+        format!("ws://{}/graphql", discovery["tcp"].as_str().unwrap())
+    );
+    assert_eq!(discovery["socket"], Value::Null);
+    assert_eq!(discovery["schemaHash"].as_str().map(str::len), Some(16));
+    assert!(discovery["pid"].as_u64().is_some());
+
+    // No --endpoint: the default would be a unix socket nobody listens on.
+    let snapshot = std::process::Command::new(env!("CARGO_BIN_EXE_riverql"))
+        .arg("snapshot")
+        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .env("RUST_LOG", "off")
+        .output()
+        .expect("run riverql snapshot");
+    assert!(snapshot.status.success(), "{snapshot:?}");
+    let data: Value = serde_json::from_slice(&snapshot.stdout).expect("json snapshot");
+    assert_eq!(output(&data, "FIX-1")["focusedTags"], 1);
+
+    assert!(server.terminate().success());
+    assert!(!file.exists(), "discovery file left behind");
+    let _ = std::fs::remove_dir_all(&runtime_dir);
+}
+
+#[test]
+fn install_service_writes_units_with_the_server_flags() {
+    let config_home =
+        std::env::temp_dir().join(format!("riverql-install-service-{}", std::process::id()));
+    let units = config_home.join("systemd").join("user");
+    let args = [
+        "--listen",
+        "tcp://127.0.0.1:4555",
+        "--dedup",
+        "--layout-namespace",
+        "my layout",
+        "install-service",
+    ];
+
+    let installed = support::run_with_config_home(&config_home, &args);
+    assert!(installed.status.success(), "{installed:?}");
+    let service = std::fs::read_to_string(units.join("riverql.service")).expect("service unit");
+    let socket = std::fs::read_to_string(units.join("riverql.socket")).expect("socket unit");
+    let exec_start = service
+        .lines()
+        .find_map(|line| line.strip_prefix("ExecStart="))
+        .expect("ExecStart line");
+    assert_eq!(
+        exec_start,
+        format!(
+            "{} --server --listen tcp://127.0.0.1:4555 --dedup --layout-namespace \"my layout\"",
+            env!("CARGO_BIN_EXE_riverql")
+        )
+    );
+    assert!(service.contains("Requires=riverql.socket\n"), "{service}");
+    assert!(socket.contains("ListenStream=127.0.0.1:4555\n"), "{socket}");
+    assert!(socket.contains("WantedBy=sockets.target\n"), "{socket}");
+
+    let again = support::run_with_config_home(&config_home, &args);
+    assert!(!again.status.success());
+    assert!(String::from_utf8_lossy(&again.stderr).contains("--force"));
+    let forced = support::run_with_config_home(&config_home, &[&args[..], &["--force"]].concat());
+    assert!(forced.status.success(), "{forced:?}");
+
+    let client = support::run_with_config_home(&config_home, &["--raw", "install-service"]);
+    assert!(!client.status.success());
+    let _ = std::fs::remove_dir_all(&config_home);
+}
+
+#[test]
+fn server_takes_its_listener_from_socket_activation() {
+    let fixture = Fixture::start(&["FIX-1"]);
+    let server = Server::spawn_activated(&fixture);
+    let data = server.query_until("{ outputs { name focusedTags } }", |data| {
+        data["outputs"].as_array().is_some_and(|o| o.len() == 1)
+    });
+    assert_eq!(output(&data, "FIX-1")["focusedTags"], 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn events_subprotocol_streams_plain_json_events() {
+    let fixture = Fixture::start(&["FIX-1", "FIX-2"]);
+    let server = Server::spawn(&fixture);
+
+    let mut feed = support::EventFeed::connect(&server.ws_url()).await;
+    let initial = feed
+        .next_matching(|event| event["__typename"] == "OutputFocusedTags")
+        .await;
+    assert!(initial["outputId"].is_string(), "{initial}");
+
+    feed.filter(json!({ "types": ["OUTPUT_FOCUSED_TAGS"], "outputNames": ["FIX-2"] }))
+        .await;
+    // Frames sent before the acknowledgement are still unfiltered.
+    feed.next_matching(|frame| frame.get("filter").is_some())
+        .await;
+    let event = feed.next_matching(|_| true).await;
+    assert_eq!(
+        (&event["name"], &event["tags"]),
+        (&json!("FIX-2"), &json!(0))
+    );
+    fixture.replay([
+        Command::FocusedTags { output: 0, tags: 8 },
+        Command::FocusedTags { output: 1, tags: 4 },
+    ]);
+    let event = feed.next_matching(|_| true).await;
+    assert_eq!(
+        event,
+        json!({
+            "__typename": "OutputFocusedTags",
+            "outputId": event["outputId"],
+            "stableId": "output:FIX-2",
+            "name": "FIX-2",
+            "tags": 4,
+            "tagsList": [2],
+            "geometry": null
+        })
+    );
+
+    feed.filter(json!({ "types": ["NO_SUCH_EVENT"] })).await;
+    let error = feed
+        .next_matching(|frame| frame.get("error").is_some())
+        .await;
+    assert!(
+        error["error"]
+            .as_str()
+            .is_some_and(|e| e.contains("NO_SUCH_EVENT")),
+        "{error}"
+    );
+}
@@ -0,0 +1,984 @@
+//! Minimal Wayland compositor that speaks just enough of the core protocol,
+//! river-status-unstable-v1, river-control-unstable-v1, river-layout-v3,
+//! xdg-output and wlr-foreign-toplevel-management to drive riverql end to
+//! end.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use wayland_server::backend::{ClientData, ClientId, DisconnectReason, ObjectId};
+use wayland_server::protocol::{
+    wl_output::{self, WlOutput},
+    wl_seat::{self, WlSeat},
+};
+use wayland_server::{
+    Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, ListeningSocket, New,
+    Resource,
+};
+
+pub mod river_status {
+    use wayland_server;
+    use wayland_server::protocol::*;
+    pub mod __interfaces {
+        use wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("protocol/river-status-unstable-v1.xml");
+    }
+    use self::__interfaces::*;
+    wayland_scanner::generate_server_code!("protocol/river-status-unstable-v1.xml");
+}
+
+pub mod river_control {
+    use wayland_server;
+    use wayland_server::protocol::*;
+    pub mod __interfaces {
+        use wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("protocol/river-control-unstable-v1.xml");
+    }
+    use self::__interfaces::*;
+    wayland_scanner::generate_server_code!("protocol/river-control-unstable-v1.xml");
+}
+
+pub mod river_layout {
+    use wayland_server;
+    use wayland_server::protocol::*;
+    pub mod __interfaces {
+        use wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("protocol/river-layout-v3.xml");
+    }
+    use self::__interfaces::*;
+    wayland_scanner::generate_server_code!("protocol/river-layout-v3.xml");
+}
+
+pub mod foreign_toplevel {
+    use wayland_server;
+    use wayland_server::protocol::*;
+    pub mod __interfaces {
+        use wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!(
+            "protocol/wlr-foreign-toplevel-management-unstable-v1.xml"
+        );
+    }
+    use self::__interfaces::*;
+    wayland_scanner::generate_server_code!(
+        "protocol/wlr-foreign-toplevel-management-unstable-v1.xml"
+    );
+}
+
+pub mod xdg_output {
+    use wayland_server;
+    use wayland_server::protocol::*;
+    pub mod __interfaces {
+        use wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("protocol/xdg-output-unstable-v1.xml");
+    }
+    use self::__interfaces::*;
+    wayland_scanner::generate_server_code!("protocol/xdg-output-unstable-v1.xml");
+}
+
+use foreign_toplevel::zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1};
+use foreign_toplevel::zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1};
+use river_control::zriver_command_callback_v1::ZriverCommandCallbackV1;
+use river_control::zriver_control_v1::{self, ZriverControlV1};
+use river_layout::river_layout_manager_v3::{self, RiverLayoutManagerV3};
+use river_layout::river_layout_v3::{self, RiverLayoutV3};
+use river_status::zriver_output_status_v1::ZriverOutputStatusV1;
+use river_status::zriver_seat_status_v1::ZriverSeatStatusV1;
+use river_status::zriver_status_manager_v1::{self, ZriverStatusManagerV1};
+use xdg_output::zxdg_output_manager_v1::{self, ZxdgOutputManagerV1};
+use xdg_output::zxdg_output_v1::{self, ZxdgOutputV1};
+
+/// Scripted compositor-side change, applied in order by the fixture thread.
+#[derive(Debug, Clone)]
+pub enum Command {
+    FocusedTags {
+        output: usize,
+        tags: u32,
+    },
+    ViewTags {
+        output: usize,
+        tags: Vec<u32>,
+    },
+    UrgentTags {
+        output: usize,
+        tags: u32,
+    },
+    LayoutName {
+        output: usize,
+        name: String,
+    },
+    FocusOutput {
+        output: usize,
+    },
+    /// Moves `output` in the global compositor space.
+    OutputPosition {
+        output: usize,
+        x: i32,
+        y: i32,
+    },
+    /// Changes `output`'s scale, which also shrinks its logical size.
+    OutputScale {
+        output: usize,
+        scale: i32,
+    },
+    FocusedView {
+        title: String,
+    },
+    Mode {
+        name: String,
+    },
+    /// Asks every layout generator on `output` to arrange `views` views.
+    LayoutDemand {
+        output: usize,
+        views: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Opens toplevel `index` (the next unused one) or updates it. Activating
+    /// a toplevel deactivates the others.
+    Toplevel {
+        index: usize,
+        title: String,
+        app_id: String,
+        activated: bool,
+    },
+}
+
+/// A layout a generator committed in answer to a demand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutCommit {
+    pub output: usize,
+    pub name: String,
+    /// Number of `push_view_dimensions` requests before the commit.
+    pub views: u32,
+}
+
+#[derive(Debug, Clone)]
+struct ToplevelSpec {
+    title: String,
+    app_id: String,
+    activated: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct OutputSpec {
+    name: String,
+    focused_tags: u32,
+    view_tags: Vec<u32>,
+    urgent_tags: u32,
+    layout: Option<String>,
+    position: (i32, i32),
+    scale: i32,
+}
+
+struct State {
+    display: DisplayHandle,
+    outputs: Vec<OutputSpec>,
+    focused_output: usize,
+    focused_view: String,
+    mode: String,
+    bound_outputs: Vec<(usize, WlOutput)>,
+    xdg_outputs: Vec<(usize, ZxdgOutputV1, WlOutput)>,
+    output_statuses: Vec<(usize, ZriverOutputStatusV1)>,
+    seat_statuses: Vec<ZriverSeatStatusV1>,
+    layouts: Vec<(usize, RiverLayoutV3)>,
+    pushed_views: HashMap<ObjectId, u32>,
+    layout_serial: u32,
+    commits: Arc<Mutex<Vec<LayoutCommit>>>,
+    /// Arguments added to each control object since its last run_command.
+    control_arguments: HashMap<ObjectId, Vec<String>>,
+    control_commands: Arc<Mutex<Vec<Vec<String>>>>,
+    /// Callbacks to answer once the request that created them is handled;
+    /// answering destroys them.
+    control_answers: Vec<(ZriverCommandCallbackV1, Result<(), String>)>,
+    toplevels: Vec<ToplevelSpec>,
+    toplevel_managers: Vec<ZwlrForeignToplevelManagerV1>,
+    toplevel_handles: Vec<(usize, ZwlrForeignToplevelHandleV1)>,
+}
+
+impl State {
+    fn apply(&mut self, command: Command) {
+        match command {
+            Command::FocusedTags { output, tags } => {
+                self.outputs[output].focused_tags = tags;
+                for status in self.statuses_for(output) {
+                    status.focused_tags(tags);
+                }
+            }
+            Command::ViewTags { output, tags } => {
+                let bytes = encode_tags(&tags);
+                self.outputs[output].view_tags = tags;
+                for status in self.statuses_for(output) {
+                    status.view_tags(bytes.clone());
+                }
+            }
+            Command::UrgentTags { output, tags } => {
+                self.outputs[output].urgent_tags = tags;
+                for status in self.statuses_for(output) {
+                    if status.version() >= 2 {
+                        status.urgent_tags(tags);
+                    }
+                }
+            }
+            Command::LayoutName { output, name } => {
+                self.outputs[output].layout = Some(name.clone());
+                for status in self.statuses_for(output) {
+                    if status.version() >= 4 {
+                        status.layout_name(name.clone());
+                    }
+                }
+            }
+            Command::FocusOutput { output } => {
+                let previous = self.focused_output;
+                self.focused_output = output;
+                for status in &self.seat_statuses {
+                    if let Some(prev) = self.output_for_client(status, previous) {
+                        status.unfocused_output(&prev);
+                    }
+                    if let Some(next) = self.output_for_client(status, output) {
+                        status.focused_output(&next);
+                    }
+                }
+            }
+            Command::OutputPosition { output, x, y } => {
+                self.outputs[output].position = (x, y);
+                for (index, xdg_output, wl_output) in &self.xdg_outputs {
+                    if *index == output && xdg_output.is_alive() {
+                        send_xdg_output(xdg_output, wl_output, &self.outputs[output]);
+                    }
+                }
+            }
+            Command::OutputScale { output, scale } => {
+                self.outputs[output].scale = scale;
+                for (index, wl_output) in &self.bound_outputs {
+                    if *index == output && wl_output.is_alive() && wl_output.version() >= 2 {
+                        wl_output.scale(scale);
+                    }
+                }
+                for (index, xdg_output, wl_output) in &self.xdg_outputs {
+                    if *index == output && xdg_output.is_alive() {
+                        send_xdg_output(xdg_output, wl_output, &self.outputs[output]);
+                    }
+                }
+                for (index, wl_output) in &self.bound_outputs {
+                    if *index == output && wl_output.is_alive() && wl_output.version() >= 2 {
+                        wl_output.done();
+                    }
+                }
+            }
+            Command::FocusedView { title } => {
+                for status in &self.seat_statuses {
+                    status.focused_view(title.clone());
+                }
+                self.focused_view = title;
+            }
+            Command::Mode { name } => {
+                for status in &self.seat_statuses {
+                    if status.version() >= 3 {
+                        status.mode(name.clone());
+                    }
+                }
+                self.mode = name;
+            }
+            Command::LayoutDemand {
+                output,
+                views,
+                width,
+                height,
+            } => {
+                let tags = self.outputs[output].focused_tags;
+                for (index, layout) in &self.layouts {
+                    if *index == output && layout.is_alive() {
+                        self.layout_serial += 1;
+                        layout.layout_demand(views, width, height, tags, self.layout_serial);
+                    }
+                }
+            }
+            Command::Toplevel {
+                index,
+                title,
+                app_id,
+                activated,
+            } => {
+                let spec = ToplevelSpec {
+                    title,
+                    app_id,
+                    activated,
+                };
+                if index == self.toplevels.len() {
+                    self.toplevels.push(spec);
+                    for manager in self.toplevel_managers.clone() {
+                        self.announce_toplevel(&manager, index);
+                    }
+                } else {
+                    self.toplevels[index] = spec;
+                }
+                let mut changed = vec![index];
+                if activated {
+                    for (other, spec) in self.toplevels.iter_mut().enumerate() {
+                        if other != index && spec.activated {
+                            spec.activated = false;
+                            changed.push(other);
+                        }
+                    }
+                }
+                for (toplevel, handle) in &self.toplevel_handles {
+                    if changed.contains(toplevel) {
+                        send_toplevel(handle, &self.toplevels[*toplevel]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs a river-control command the way river would, for the commands
+    /// riverql sends.
+    fn run_control_command(&mut self, args: &[String]) -> Result<(), String> {
+        match args {
+            [command, name] if command == "focus-output" => {
+                let output = self
+                    .outputs
+                    .iter()
+                    .position(|spec| spec.name == *name)
+                    .ok_or_else(|| format!("no output named {name}"))?;
+                if output != self.focused_output {
+                    self.apply(Command::FocusOutput { output });
+                }
+                Ok(())
+            }
+            [command, tags] if command == "set-focused-tags" => {
+                let tags = tags.parse().map_err(|_| format!("invalid tags {tags}"))?;
+                self.apply(Command::FocusedTags {
+                    output: self.focused_output,
+                    tags,
+                });
+                Ok(())
+            }
+            _ => Err(format!("unknown command {args:?}")),
+        }
+    }
+
+    fn announce_toplevel(&mut self, manager: &ZwlrForeignToplevelManagerV1, index: usize) {
+        let Some(client) = manager.client() else {
+            return;
+        };
+        let Ok(handle) = client.create_resource::<ZwlrForeignToplevelHandleV1, _, Self>(
+            &self.display,
+            manager.version(),
+            (),
+        ) else {
+            return;
+        };
+        manager.toplevel(&handle);
+        send_toplevel(&handle, &self.toplevels[index]);
+        self.toplevel_handles.push((index, handle));
+    }
+
+    fn statuses_for(&self, output: usize) -> Vec<ZriverOutputStatusV1> {
+        self.output_statuses
+            .iter()
+            .filter(|(index, status)| *index == output && status.is_alive())
+            .map(|(_, status)| status.clone())
+            .collect()
+    }
+
+    fn output_for_client<R: Resource>(&self, resource: &R, output: usize) -> Option<WlOutput> {
+        let client = resource.client()?;
+        self.bound_outputs
+            .iter()
+            .find(|(index, out)| *index == output && out.client().as_ref() == Some(&client))
+            .map(|(_, out)| out.clone())
+    }
+
+    fn send_output_state(&self, status: &ZriverOutputStatusV1, output: usize) {
+        let spec = &self.outputs[output];
+        status.focused_tags(spec.focused_tags);
+        status.view_tags(encode_tags(&spec.view_tags));
+        if status.version() >= 2 {
+            status.urgent_tags(spec.urgent_tags);
+        }
+        if status.version() >= 4 {
+            match &spec.layout {
+                Some(name) => status.layout_name(name.clone()),
+                None => status.layout_name_clear(),
+            }
+        }
+    }
+}
+
+fn send_xdg_output(xdg_output: &ZxdgOutputV1, wl_output: &WlOutput, spec: &OutputSpec) {
+    let (x, y) = spec.position;
+    xdg_output.logical_position(x, y);
+    xdg_output.logical_size(1920 / spec.scale, 1080 / spec.scale);
+    if xdg_output.version() >= 3 {
+        wl_output.done();
+    } else {
+        xdg_output.done();
+    }
+}
+
+fn send_toplevel(handle: &ZwlrForeignToplevelHandleV1, spec: &ToplevelSpec) {
+    handle.title(spec.title.clone());
+    handle.app_id(spec.app_id.clone());
+    let states: &[u32] = if spec.activated {
+        &[zwlr_foreign_toplevel_handle_v1::State::Activated as u32]
+    } else {
+        &[]
+    };
+    handle.state(encode_tags(states));
+    handle.done();
+}
+
+fn encode_tags(tags: &[u32]) -> Vec<u8> {
+    tags.iter().flat_map(|tag| tag.to_ne_bytes()).collect()
+}
+
+struct ClientState;
+
+impl ClientData for ClientState {
+    fn initialized(&self, _client_id: ClientId) {}
+    fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {}
+}
+
+impl GlobalDispatch<WlOutput, usize> for State {
+    fn bind(
+        state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<WlOutput>,
+        index: &usize,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let output = data_init.init(resource, *index);
+        output.geometry(
+            0,
+            0,
+            0,
+            0,
+            wl_output::Subpixel::Unknown,
+            "Fixture".into(),
+            format!("Output {index}"),
+            wl_output::Transform::Normal,
+        );
+        if output.version() >= 4 {
+            output.name(state.outputs[*index].name.clone());
+        }
+        if output.version() >= 2 {
+            output.scale(state.outputs[*index].scale);
+            output.done();
+        }
+        state.bound_outputs.push((*index, output));
+    }
+}
+
+impl Dispatch<WlOutput, usize> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WlOutput,
+        _request: wl_output::Request,
+        _data: &usize,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<WlSeat, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<WlSeat>,
+        _data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let seat = data_init.init(resource, ());
+        if seat.version() >= 2 {
+            seat.name("seat0".into());
+        }
+    }
+}
+
+impl Dispatch<WlSeat, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WlSeat,
+        _request: wl_seat::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ZriverStatusManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZriverStatusManagerV1>,
+        _data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZriverStatusManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ZriverStatusManagerV1,
+        request: zriver_status_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zriver_status_manager_v1::Request::GetRiverOutputStatus { id, output } => {
+                let index = *output.data::<usize>().expect("fixture output data");
+                let status = data_init.init(id, ());
+                state.send_output_state(&status, index);
+                state.output_statuses.push((index, status));
+            }
+            zriver_status_manager_v1::Request::GetRiverSeatStatus { id, .. } => {
+                let status = data_init.init(id, ());
+                if let Some(output) = state.output_for_client(&status, state.focused_output) {
+                    status.focused_output(&output);
+                }
+                status.focused_view(state.focused_view.clone());
+                if status.version() >= 3 {
+                    status.mode(state.mode.clone());
+                }
+                state.seat_statuses.push(status);
+            }
+            zriver_status_manager_v1::Request::Destroy => {}
+        }
+    }
+}
+
+impl Dispatch<ZriverOutputStatusV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZriverOutputStatusV1,
+        _request: river_status::zriver_output_status_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZriverSeatStatusV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZriverSeatStatusV1,
+        _request: river_status::zriver_seat_status_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ZriverControlV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZriverControlV1>,
+        _data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZriverControlV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZriverControlV1,
+        request: zriver_control_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zriver_control_v1::Request::AddArgument { argument } => {
+                state
+                    .control_arguments
+                    .entry(resource.id())
+                    .or_default()
+                    .push(argument);
+            }
+            zriver_control_v1::Request::RunCommand { callback, .. } => {
+                let args = state
+                    .control_arguments
+                    .remove(&resource.id())
+                    .unwrap_or_default();
+                let callback = data_init.init(callback, ());
+                state.control_commands.lock().unwrap().push(args.clone());
+                let answer = state.run_control_command(&args);
+                state.control_answers.push((callback, answer));
+            }
+            zriver_control_v1::Request::Destroy => {}
+        }
+    }
+}
+
+impl Dispatch<ZriverCommandCallbackV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZriverCommandCallbackV1,
+        _request: river_control::zriver_command_callback_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<RiverLayoutManagerV3, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<RiverLayoutManagerV3>,
+        _data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<RiverLayoutManagerV3, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &RiverLayoutManagerV3,
+        request: river_layout_manager_v3::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let river_layout_manager_v3::Request::GetLayout { id, output, .. } = request {
+            let index = *output.data::<usize>().expect("fixture output data");
+            let layout = data_init.init(id, index);
+            state.layouts.push((index, layout));
+        }
+    }
+}
+
+impl Dispatch<RiverLayoutV3, usize> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &RiverLayoutV3,
+        request: river_layout_v3::Request,
+        index: &usize,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            river_layout_v3::Request::PushViewDimensions { .. } => {
+                *state.pushed_views.entry(resource.id()).or_default() += 1;
+            }
+            river_layout_v3::Request::Commit { layout_name, .. } => {
+                let views = state.pushed_views.remove(&resource.id()).unwrap_or(0);
+                state.commits.lock().unwrap().push(LayoutCommit {
+                    output: *index,
+                    name: layout_name,
+                    views,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+impl GlobalDispatch<ZxdgOutputManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZxdgOutputManagerV1>,
+        _data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZxdgOutputManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ZxdgOutputManagerV1,
+        request: zxdg_output_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zxdg_output_manager_v1::Request::GetXdgOutput { id, output } = request {
+            let index = *output.data::<usize>().expect("fixture output data");
+            let xdg_output = data_init.init(id, ());
+            send_xdg_output(&xdg_output, &output, &state.outputs[index]);
+            state.xdg_outputs.push((index, xdg_output, output));
+        }
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZxdgOutputV1,
+        _request: zxdg_output_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ZwlrForeignToplevelManagerV1, ()> for State {
+    fn bind(
+        state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrForeignToplevelManagerV1>,
+        _data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let manager = data_init.init(resource, ());
+        for index in 0..state.toplevels.len() {
+            state.announce_toplevel(&manager, index);
+        }
+        state.toplevel_managers.push(manager);
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrForeignToplevelManagerV1,
+        _request: zwlr_foreign_toplevel_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrForeignToplevelHandleV1,
+        _request: zwlr_foreign_toplevel_handle_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+/// Running fixture compositor. Dropping it stops the compositor thread.
+pub struct Fixture {
+    dir: PathBuf,
+    socket: PathBuf,
+    commits: Arc<Mutex<Vec<LayoutCommit>>>,
+    control_commands: Arc<Mutex<Vec<Vec<String>>>>,
+    commands: Option<Sender<Command>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Fixture {
+    /// Starts a compositor advertising one `wl_output` per name, a seat,
+    /// `zriver_status_manager_v1`, `zriver_control_v1`, `river_layout_manager_v3`,
+    /// `zxdg_output_manager_v1` and `zwlr_foreign_toplevel_manager_v1`.
+    /// Outputs sit side by side in the given order; output 0 starts focused
+    /// with tag 1.
+    pub fn start(outputs: &[&str]) -> Self {
+        Self::start_with_status_version(outputs, 4)
+    }
+
+    /// Like `start`, but advertises river-status at `version` to mimic an
+    /// older river.
+    pub fn start_with_status_version(outputs: &[&str], version: u32) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "riverql-fixture-{}-{}",
+            std::process::id(),
+            unique_suffix()
+        ));
+        std::fs::create_dir_all(&dir).expect("create fixture dir");
+        let socket = dir.join("wayland-fixture");
+        let listener =
+            ListeningSocket::bind_absolute(socket.clone()).expect("bind fixture wayland socket");
+
+        let specs = outputs
+            .iter()
+            .enumerate()
+            .map(|(index, name)| OutputSpec {
+                name: name.to_string(),
+                focused_tags: if index == 0 { 1 } else { 0 },
+                position: (index as i32 * 1920, 0),
+                scale: 1,
+                ..OutputSpec::default()
+            })
+            .collect();
+
+        let (tx, rx) = mpsc::channel();
+        let commits = Arc::new(Mutex::new(Vec::new()));
+        let control_commands = Arc::new(Mutex::new(Vec::new()));
+        let fixture = Recorded {
+            commits: commits.clone(),
+            control_commands: control_commands.clone(),
+        };
+        let thread = thread::spawn(move || run(listener, specs, version, fixture, rx));
+        Self {
+            dir,
+            socket,
+            commits,
+            control_commands,
+            commands: Some(tx),
+            thread: Some(thread),
+        }
+    }
+
+    /// Absolute socket path, suitable for `WAYLAND_DISPLAY`.
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    pub fn send(&self, command: Command) {
+        self.commands
+            .as_ref()
+            .expect("fixture running")
+            .send(command)
+            .expect("fixture thread alive");
+    }
+
+    pub fn replay(&self, script: impl IntoIterator<Item = Command>) {
+        for command in script {
+            self.send(command);
+        }
+    }
+
+    /// Waits until generators committed at least `count` layouts and returns
+    /// them oldest first.
+    pub fn layout_commits(&self, count: usize) -> Vec<LayoutCommit> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let commits = self.commits.lock().unwrap().clone();
+            if commits.len() >= count || Instant::now() > deadline {
+                return commits;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// River-control commands run so far, oldest first, each as its
+    /// arguments.
+    pub fn control_commands(&self) -> Vec<Vec<String>> {
+        self.control_commands.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        self.commands.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn unique_suffix() -> u64 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// What the fixture thread records for the test to inspect.
+struct Recorded {
+    commits: Arc<Mutex<Vec<LayoutCommit>>>,
+    control_commands: Arc<Mutex<Vec<Vec<String>>>>,
+}
+
+fn run(
+    listener: ListeningSocket,
+    outputs: Vec<OutputSpec>,
+    status_version: u32,
+    recorded: Recorded,
+    commands: Receiver<Command>,
+) {
+    let mut display: Display<State> = Display::new().expect("create fixture display");
+    let handle = display.handle();
+    for index in 0..outputs.len() {
+        handle.create_global::<State, WlOutput, _>(4, index);
+    }
+    handle.create_global::<State, WlSeat, _>(7, ());
+    handle.create_global::<State, ZriverStatusManagerV1, _>(status_version, ());
+    handle.create_global::<State, ZriverControlV1, _>(1, ());
+    handle.create_global::<State, RiverLayoutManagerV3, _>(2, ());
+    handle.create_global::<State, ZxdgOutputManagerV1, _>(3, ());
+    handle.create_global::<State, ZwlrForeignToplevelManagerV1, _>(3, ());
+
+    let mut state = State {
+        display: handle.clone(),
+        outputs,
+        focused_output: 0,
+        focused_view: String::new(),
+        mode: "normal".into(),
+        bound_outputs: Vec::new(),
+        xdg_outputs: Vec::new(),
+        output_statuses: Vec::new(),
+        seat_statuses: Vec::new(),
+        layouts: Vec::new(),
+        pushed_views: HashMap::new(),
+        layout_serial: 0,
+        commits: recorded.commits,
+        control_arguments: HashMap::new(),
+        control_commands: recorded.control_commands,
+        control_answers: Vec::new(),
+        toplevels: Vec::new(),
+        toplevel_managers: Vec::new(),
+        toplevel_handles: Vec::new(),
+    };
+
+    loop {
+        while let Ok(Some(stream)) = listener.accept() {
+            let _ = display
+                .handle()
+                .insert_client(stream, Arc::new(ClientState));
+        }
+        loop {
+            match commands.try_recv() {
+                Ok(command) => state.apply(command),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+        let _ = display.dispatch_clients(&mut state);
+        for (callback, answer) in state.control_answers.drain(..) {
+            match answer {
+                Ok(()) => callback.success(String::new()),
+                Err(message) => callback.failure(message),
+            }
+        }
+        let _ = display.flush_clients();
+        thread::sleep(Duration::from_millis(5));
+    }
+}
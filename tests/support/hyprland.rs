@@ -0,0 +1,116 @@
+//! Minimal Hyprland IPC sockets for the Hyprland backend: answers `j/`
+//! requests from `HyprlandState` and lets tests push event lines.
+
+use std::ffi::OsStr;
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{Value, json};
+
+const SIGNATURE: &str = "riverql_test";
+
+/// Replies to `j/monitors`, `j/clients` and `j/activewindow`.
+pub struct HyprlandState {
+    pub monitors: Value,
+    pub clients: Value,
+    pub active_window: Value,
+}
+
+pub struct FakeHyprland {
+    runtime_dir: PathBuf,
+    state: Arc<Mutex<HyprlandState>>,
+    listeners: Arc<Mutex<Vec<UnixStream>>>,
+}
+
+impl FakeHyprland {
+    pub fn start(state: HyprlandState) -> Self {
+        let runtime_dir = std::env::temp_dir().join(format!(
+            "riverql-hyprland-{}-{}",
+            std::process::id(),
+            unique_suffix()
+        ));
+        let dir = runtime_dir.join("hypr").join(SIGNATURE);
+        std::fs::create_dir_all(&dir).expect("create fake Hyprland dir");
+        let requests = UnixListener::bind(dir.join(".socket.sock")).expect("bind request socket");
+        let events = UnixListener::bind(dir.join(".socket2.sock")).expect("bind event socket");
+        let state = Arc::new(Mutex::new(state));
+        let listeners = Arc::new(Mutex::new(Vec::new()));
+        {
+            let state = state.clone();
+            std::thread::spawn(move || {
+                for stream in requests.incoming() {
+                    let Ok(stream) = stream else {
+                        return;
+                    };
+                    answer(stream, &state);
+                }
+            });
+        }
+        {
+            let listeners = listeners.clone();
+            std::thread::spawn(move || {
+                for stream in events.incoming() {
+                    let Ok(stream) = stream else {
+                        return;
+                    };
+                    listeners.lock().unwrap().push(stream);
+                }
+            });
+        }
+        Self {
+            runtime_dir,
+            state,
+            listeners,
+        }
+    }
+
+    /// What a client of this instance finds in its environment.
+    pub fn env(&self) -> [(&str, &OsStr); 2] {
+        [
+            ("XDG_RUNTIME_DIR", self.runtime_dir.as_os_str()),
+            ("HYPRLAND_INSTANCE_SIGNATURE", OsStr::new(SIGNATURE)),
+        ]
+    }
+
+    pub fn update(&self, change: impl FnOnce(&mut HyprlandState)) {
+        change(&mut self.state.lock().unwrap());
+    }
+
+    /// Sends `name>>data` to every event socket connection.
+    pub fn event(&self, name: &str, data: &str) {
+        for stream in self.listeners.lock().unwrap().iter_mut() {
+            let _ = writeln!(stream, "{name}>>{data}");
+        }
+    }
+}
+
+impl Drop for FakeHyprland {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.runtime_dir);
+    }
+}
+
+fn answer(mut stream: UnixStream, state: &Mutex<HyprlandState>) {
+    let mut request = [0; 256];
+    let Ok(len) = stream.read(&mut request) else {
+        return;
+    };
+    let request = String::from_utf8_lossy(&request[..len]);
+    let state = state.lock().unwrap();
+    let reply = match request.as_ref() {
+        "j/monitors" => state.monitors.clone(),
+        "j/clients" => state.clients.clone(),
+        "j/activewindow" => state.active_window.clone(),
+        "j/getoption general:layout" => json!({ "option": "general:layout", "str": "dwindle" }),
+        _ => json!({}),
+    };
+    let _ = stream.write_all(reply.to_string().as_bytes());
+}
+
+fn unique_suffix() -> u64 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
@@ -0,0 +1,647 @@
+#![allow(dead_code)]
+
+pub mod fixture;
+pub mod hyprland;
+pub mod niri;
+pub mod sway;
+
+use std::ffi::OsStr;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use soketto::extension::deflate::Deflate;
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream, client_async, connect_async,
+    tungstenite::{client::IntoClientRequest, http::HeaderValue, protocol::Message},
+};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+pub use fixture::Fixture;
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A `riverql --server` process connected to a fixture compositor.
+pub struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Server {
+    pub fn spawn(fixture: &Fixture) -> Self {
+        Self::spawn_with_args(fixture, &[])
+    }
+
+    pub fn spawn_with_args(fixture: &Fixture, args: &[&str]) -> Self {
+        Self::spawn_with_env(args, &[("WAYLAND_DISPLAY", fixture.socket().as_os_str())])
+    }
+
+    /// A server that publishes its discovery file in `runtime_dir`.
+    pub fn spawn_in_runtime_dir(fixture: &Fixture, runtime_dir: &Path) -> Self {
+        Self::spawn_with_env(
+            &[],
+            &[
+                ("WAYLAND_DISPLAY", fixture.socket().as_os_str()),
+                ("XDG_RUNTIME_DIR", runtime_dir.as_os_str()),
+            ],
+        )
+    }
+
+    /// A server run with `--journal`, writing its entries to `socket`.
+    pub fn spawn_journal(fixture: &Fixture, socket: &Path) -> Self {
+        Self::spawn_with_env(
+            &["--journal"],
+            &[
+                ("WAYLAND_DISPLAY", fixture.socket().as_os_str()),
+                ("RIVERQL_JOURNAL_SOCKET", socket.as_os_str()),
+            ],
+        )
+    }
+
+    /// A server started the way systemd socket activation starts it: the
+    /// listener is bound here and passed as fd 3, while `--listen` names an
+    /// address nothing binds.
+    pub fn spawn_activated(fixture: &Fixture) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind activation socket");
+        let addr = listener
+            .local_addr()
+            .expect("activation address")
+            .to_string();
+        let unused = {
+            let probe = TcpListener::bind("127.0.0.1:0").expect("reserve port");
+            probe.local_addr().expect("probe address").to_string()
+        };
+        let fd = listener.as_raw_fd();
+        let mut command = Command::new("sh");
+        // LISTEN_PID has to name the server itself, which only the shell
+        // knows before it execs.
+        command
+            .arg("-c")
+            .arg(r#"LISTEN_PID=$$ LISTEN_FDS=1 exec "$0" "$@""#)
+            .arg(env!("CARGO_BIN_EXE_riverql"))
+            .arg("--server")
+            .arg("--listen")
+            .arg(format!("tcp://{unused}"))
+            .env_remove("SWAYSOCK")
+            .env_remove("HYPRLAND_INSTANCE_SIGNATURE")
+            .env_remove("NIRI_SOCKET")
+            .env_remove("XDG_RUNTIME_DIR")
+            .env("WAYLAND_DISPLAY", fixture.socket())
+            .env("RUST_LOG", "riverql=warn")
+            .stdout(Stdio::null());
+        unsafe {
+            command.pre_exec(move || {
+                // dup2 onto itself keeps close-on-exec, so clear it instead.
+                let ok = if fd == 3 {
+                    libc::fcntl(fd, libc::F_SETFD, 0) == 0
+                } else {
+                    libc::dup2(fd, 3) == 3
+                };
+                if ok {
+                    Ok(())
+                } else {
+                    Err(std::io::Error::last_os_error())
+                }
+            });
+        }
+        let child = command.spawn().expect("spawn activated riverql server");
+        drop(listener);
+        let server = Self { child, addr };
+        server.wait_ready();
+        assert!(
+            TcpStream::connect(&unused).is_err(),
+            "activated server also bound --listen"
+        );
+        server
+    }
+
+    /// A server on the sway backend, reading from `sway`.
+    pub fn spawn_sway(sway: &sway::FakeSway) -> Self {
+        Self::spawn_with_env(
+            &["--backend", "sway"],
+            &[("SWAYSOCK", sway.socket().as_os_str())],
+        )
+    }
+
+    /// A server left to detect the Hyprland backend from the environment.
+    pub fn spawn_hyprland(hyprland: &hyprland::FakeHyprland) -> Self {
+        Self::spawn_with_env(&[], &hyprland.env())
+    }
+
+    /// A server left to detect the niri backend from the environment.
+    pub fn spawn_niri(niri: &niri::FakeNiri) -> Self {
+        Self::spawn_with_env(&[], &[("NIRI_SOCKET", niri.socket().as_os_str())])
+    }
+
+    /// Variables the test itself may run under, such as `SWAYSOCK`, are
+    /// cleared so they don't change the detected backend. `XDG_RUNTIME_DIR`
+    /// is cleared too, keeping discovery files out of the real one.
+    fn spawn_with_env(args: &[&str], env: &[(&str, &OsStr)]) -> Self {
+        let addr = {
+            let probe = TcpListener::bind("127.0.0.1:0").expect("reserve port");
+            probe.local_addr().expect("probe address").to_string()
+        };
+        let child = Command::new(env!("CARGO_BIN_EXE_riverql"))
+            .arg("--server")
+            .arg("--listen")
+            .arg(format!("tcp://{addr}"))
+            .args(args)
+            .env_remove("SWAYSOCK")
+            .env_remove("HYPRLAND_INSTANCE_SIGNATURE")
+            .env_remove("NIRI_SOCKET")
+            .env_remove("XDG_RUNTIME_DIR")
+            .envs(env.iter().copied())
+            .env("RUST_LOG", "riverql=warn")
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("spawn riverql server");
+        let server = Self { child, addr };
+        server.wait_ready();
+        server
+    }
+
+    fn wait_ready(&self) {
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while Instant::now() < deadline {
+            if TcpStream::connect(&self.addr).is_ok() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        panic!("riverql server did not start listening on {}", self.addr);
+    }
+
+    /// Stops the server with SIGTERM, as a service manager would, and waits
+    /// for it to exit.
+    pub fn terminate(&mut self) -> std::process::ExitStatus {
+        let pid = self.child.id() as libc::pid_t;
+        unsafe { libc::kill(pid, libc::SIGTERM) };
+        self.child.wait().expect("wait for riverql server")
+    }
+
+    pub fn ws_url(&self) -> String {
+        format!("ws://{}/graphql", self.addr)
+    }
+
+    /// Starts `riverql bar` against this server with extra `args`.
+    pub fn bar(&self, args: &[&str]) -> Client {
+        let args = [&["bar"], args].concat();
+        self.client(&args, None)
+    }
+
+    /// Starts a riverql client against this server, optionally reading its
+    /// config from `config_home`.
+    pub fn client(&self, args: &[&str], config_home: Option<&Path>) -> Client {
+        self.spawn_client(args, config_home, None)
+    }
+
+    /// Starts a riverql client against this server with `input` on stdin,
+    /// which is closed afterwards.
+    pub fn client_with_input(&self, args: &[&str], input: &str) -> Client {
+        self.spawn_client(args, None, Some(input))
+    }
+
+    fn spawn_client(
+        &self,
+        args: &[&str],
+        config_home: Option<&Path>,
+        input: Option<&str>,
+    ) -> Client {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_riverql"));
+        command
+            .arg("--endpoint")
+            .arg(self.ws_url())
+            .args(args)
+            .env("RUST_LOG", "off")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(dir) = config_home {
+            command.env("XDG_CONFIG_HOME", dir);
+        }
+        if input.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        let mut child = command.spawn().expect("spawn riverql client");
+        if let Some(input) = input {
+            let mut stdin = child.stdin.take().expect("client stdin");
+            stdin
+                .write_all(input.as_bytes())
+                .expect("write client stdin");
+        }
+        let lines = collect_lines(child.stdout.take().expect("client stdout"));
+        let status_lines = collect_lines(child.stderr.take().expect("client stderr"));
+        Client {
+            child,
+            lines,
+            status_lines,
+        }
+    }
+
+    /// Runs a query over HTTP POST and returns the `data` member.
+    pub fn query(&self, query: &str) -> Value {
+        let body = json!({ "query": query }).to_string();
+        let response = self.http(
+            "POST",
+            "/graphql",
+            &[("Content-Type", "application/json")],
+            &body,
+        );
+        let value: Value = serde_json::from_str(&response).expect("json response");
+        assert!(value.get("errors").is_none(), "query failed: {value}");
+        value["data"].clone()
+    }
+
+    /// Re-runs `query` until `check` accepts the data or the timeout expires.
+    pub fn query_until(&self, query: &str, check: impl Fn(&Value) -> bool) -> Value {
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        loop {
+            let data = self.query(query);
+            if check(&data) || Instant::now() > deadline {
+                return data;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Sends a raw HTTP/1.1 request and returns the response body.
+    pub fn http(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &str) -> String {
+        let mut stream = TcpStream::connect(&self.addr).expect("connect to server");
+        let mut request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            self.addr,
+            body.len()
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        stream.write_all(request.as_bytes()).expect("write request");
+        let mut raw = String::new();
+        stream.read_to_string(&mut raw).expect("read response");
+        let (head, body) = raw.split_once("\r\n\r\n").expect("http response");
+        if head
+            .to_ascii_lowercase()
+            .contains("transfer-encoding: chunked")
+        {
+            decode_chunked(body)
+        } else {
+            body.to_string()
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Runs `riverql ARGS` to completion with `XDG_CONFIG_HOME` set to
+/// `config_home`, for subcommands that only write files.
+pub fn run_with_config_home(config_home: &Path, args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_riverql"))
+        .args(args)
+        .env("XDG_CONFIG_HOME", config_home)
+        .env("RUST_LOG", "off")
+        .output()
+        .expect("run riverql")
+}
+
+/// Runs `riverql ARGS` to completion against `fixture`, with no server in
+/// between.
+pub fn run_standalone(fixture: &Fixture, args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_riverql"))
+        .args(args)
+        .env_remove("SWAYSOCK")
+        .env_remove("HYPRLAND_INSTANCE_SIGNATURE")
+        .env_remove("NIRI_SOCKET")
+        .env("WAYLAND_DISPLAY", fixture.socket())
+        .env("RUST_LOG", "off")
+        .output()
+        .expect("run riverql")
+}
+
+/// A riverql client process whose stdout and stderr lines are collected in
+/// the background.
+pub struct Client {
+    child: Child,
+    lines: mpsc::Receiver<String>,
+    status_lines: mpsc::Receiver<String>,
+}
+
+impl Client {
+    /// Reads stdout lines until one satisfies `check`.
+    pub fn line_matching(&mut self, check: impl Fn(&str) -> bool) -> String {
+        next_matching(&self.lines, check)
+    }
+
+    /// Reads stderr lines until one satisfies `check`.
+    pub fn status_matching(&mut self, check: impl Fn(&str) -> bool) -> String {
+        next_matching(&self.status_lines, check)
+    }
+}
+
+fn collect_lines(stream: impl Read + Send + 'static) -> mpsc::Receiver<String> {
+    let (tx, lines) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    lines
+}
+
+fn next_matching(lines: &mpsc::Receiver<String>, check: impl Fn(&str) -> bool) -> String {
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    let mut seen = Vec::new();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match lines.recv_timeout(remaining) {
+            Ok(line) if check(&line) => return line,
+            Ok(line) => seen.push(line),
+            Err(_) => panic!("no matching output line; saw {seen:?}"),
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn decode_chunked(mut body: &str) -> String {
+    let mut out = String::new();
+    while let Some((size, rest)) = body.split_once("\r\n") {
+        let size = usize::from_str_radix(size.trim(), 16).unwrap_or(0);
+        if size == 0 {
+            break;
+        }
+        out.push_str(&rest[..size]);
+        body = &rest[size + 2..];
+    }
+    out
+}
+
+/// graphql-transport-ws client driving a single operation.
+pub struct Subscription {
+    ws: WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>,
+    /// Messages skipped by `next_message_for`, oldest first.
+    pending: Vec<Value>,
+}
+
+impl Subscription {
+    pub async fn start(url: &str, query: &str) -> Self {
+        let mut sub = Self::connect(url).await;
+        sub.subscribe("1", query).await;
+        sub
+    }
+
+    /// Starts another operation on this connection.
+    pub async fn subscribe(&mut self, id: &str, query: &str) {
+        send(
+            &mut self.ws,
+            json!({ "id": id, "type": "subscribe", "payload": { "query": query } }),
+        )
+        .await;
+    }
+
+    /// Stops the operation `id`.
+    pub async fn stop(&mut self, id: &str) {
+        send(&mut self.ws, json!({ "id": id, "type": "complete" })).await;
+    }
+
+    /// Waits for the next message about operation `id`; messages for other
+    /// operations are kept for later calls.
+    pub async fn next_message_for(&mut self, id: &str) -> Value {
+        if let Some(index) = self.pending.iter().position(|msg| msg["id"] == id) {
+            return self.pending.remove(index);
+        }
+        loop {
+            let msg = next_json(&mut self.ws).await;
+            if msg["id"] == id {
+                return msg;
+            }
+            self.pending.push(msg);
+        }
+    }
+
+    /// Like `start`, but with a receive buffer small enough that the
+    /// server's writes back up as soon as the test stops reading.
+    pub async fn start_slow(url: &str, query: &str) -> Self {
+        let addr = url
+            .trim_start_matches("ws://")
+            .split('/')
+            .next()
+            .and_then(|addr| addr.parse().ok())
+            .expect("ws address");
+        let socket = tokio::net::TcpSocket::new_v4().expect("socket");
+        socket.set_recv_buffer_size(4096).expect("receive buffer");
+        let stream = socket.connect(addr).await.expect("tcp connect");
+        let (ws, _) = client_async(ws_request(url), MaybeTlsStream::Plain(stream))
+            .await
+            .expect("ws connect");
+        let mut sub = Self::handshake(ws).await;
+        sub.subscribe("1", query).await;
+        sub
+    }
+
+    /// Completes the connection handshake without starting an operation.
+    pub async fn connect(url: &str) -> Self {
+        let (ws, _) = connect_async(ws_request(url)).await.expect("ws connect");
+        Self::handshake(ws).await
+    }
+
+    async fn handshake(mut ws: WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>) -> Self {
+        send(&mut ws, json!({ "type": "connection_init", "payload": {} })).await;
+        loop {
+            let msg = next_json(&mut ws).await;
+            if msg["type"] == "connection_ack" {
+                break;
+            }
+        }
+        Self {
+            ws,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Waits for the server to close the connection; returns the close code.
+    pub async fn closed(&mut self) -> Option<u16> {
+        let wait = async {
+            while let Some(Ok(msg)) = self.ws.next().await {
+                if let Message::Close(frame) = msg {
+                    return frame.map(|frame| frame.code.into());
+                }
+            }
+            None
+        };
+        tokio::time::timeout(STARTUP_TIMEOUT, wait)
+            .await
+            .expect("timed out waiting for the server to close")
+    }
+
+    /// Waits for the next `next` payload's `data` member.
+    pub async fn next_data(&mut self) -> Value {
+        loop {
+            let msg = next_json(&mut self.ws).await;
+            match msg["type"].as_str() {
+                Some("next") => return msg["payload"]["data"].clone(),
+                Some("error") => panic!("subscription error: {msg}"),
+                Some("complete") => panic!("subscription completed early"),
+                _ => {}
+            }
+        }
+    }
+
+    /// Collects `next` payloads until one satisfies `check`.
+    pub async fn next_matching(&mut self, check: impl Fn(&Value) -> bool) -> Value {
+        let wait = async {
+            loop {
+                let data = self.next_data().await;
+                if check(&data) {
+                    return data;
+                }
+            }
+        };
+        tokio::time::timeout(STARTUP_TIMEOUT, wait)
+            .await
+            .expect("timed out waiting for subscription event")
+    }
+}
+
+/// A graphql-transport-ws connection that offered `permessage-deflate`,
+/// driven by soketto since tungstenite can't compress.
+pub struct DeflateSocket {
+    /// Subprotocol the server picked.
+    pub protocol: Option<String>,
+    /// Whether the server agreed to compress.
+    pub deflate: bool,
+    sender: soketto::Sender<Compat<tokio::net::TcpStream>>,
+    receiver: soketto::Receiver<Compat<tokio::net::TcpStream>>,
+}
+
+impl DeflateSocket {
+    pub async fn connect(url: &str) -> Self {
+        let rest = url.trim_start_matches("ws://");
+        let (host, path) = rest.split_once('/').expect("ws path");
+        let path = format!("/{path}");
+        let stream = tokio::net::TcpStream::connect(host)
+            .await
+            .expect("tcp connect");
+        let mut client = soketto::handshake::Client::new(stream.compat(), host, &path);
+        client.add_protocol("graphql-transport-ws");
+        client.add_extension(Box::new(Deflate::new(soketto::Mode::Client)));
+        let protocol = match client.handshake().await.expect("ws handshake") {
+            soketto::handshake::ServerResponse::Accepted { protocol } => protocol,
+            other => panic!("websocket upgrade refused: {other:?}"),
+        };
+        let extensions: Vec<_> = client.drain_extensions().collect();
+        let deflate = extensions.iter().any(|e| e.is_enabled());
+        let buffer = client.take_buffer();
+        let mut builder =
+            soketto::connection::Builder::new(client.into_inner(), soketto::Mode::Client);
+        builder.set_buffer(buffer);
+        builder.add_extensions(extensions);
+        let (sender, receiver) = builder.finish();
+        Self {
+            protocol,
+            deflate,
+            sender,
+            receiver,
+        }
+    }
+
+    pub async fn send(&mut self, value: Value) {
+        self.sender
+            .send_text(value.to_string())
+            .await
+            .expect("ws send");
+        self.sender.flush().await.expect("ws flush");
+    }
+
+    pub async fn next_json(&mut self) -> Value {
+        let mut data = Vec::new();
+        tokio::time::timeout(STARTUP_TIMEOUT, self.receiver.receive_data(&mut data))
+            .await
+            .expect("timed out waiting for websocket message")
+            .expect("websocket receive");
+        serde_json::from_slice(&data).expect("json frame")
+    }
+}
+
+/// Client of the plain `riverql-events` websocket feed.
+pub struct EventFeed {
+    ws: WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>,
+}
+
+impl EventFeed {
+    pub async fn connect(url: &str) -> Self {
+        let mut request = url.into_client_request().expect("ws request");
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static("riverql-events"),
+        );
+        let (ws, response) = connect_async(request).await.expect("ws connect");
+        assert_eq!(
+            response.headers().get("Sec-WebSocket-Protocol"),
+            Some(&HeaderValue::from_static("riverql-events"))
+        );
+        Self { ws }
+    }
+
+    /// Replaces the server-side filter.
+    pub async fn filter(&mut self, filter: Value) {
+        send(&mut self.ws, filter).await;
+    }
+
+    /// Reads frames until one satisfies `check`.
+    pub async fn next_matching(&mut self, check: impl Fn(&Value) -> bool) -> Value {
+        loop {
+            let frame = next_json(&mut self.ws).await;
+            if check(&frame) {
+                return frame;
+            }
+        }
+    }
+}
+
+fn ws_request(url: &str) -> tokio_tungstenite::tungstenite::handshake::client::Request {
+    let mut request = url.into_client_request().expect("ws request");
+    request.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        HeaderValue::from_static("graphql-transport-ws"),
+    );
+    request
+}
+
+async fn send(ws: &mut WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>, value: Value) {
+    ws.send(Message::Text(value.to_string()))
+        .await
+        .expect("ws send");
+}
+
+async fn next_json(ws: &mut WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>) -> Value {
+    loop {
+        let msg = tokio::time::timeout(STARTUP_TIMEOUT, ws.next())
+            .await
+            .expect("timed out waiting for websocket message")
+            .expect("websocket closed")
+            .expect("websocket error");
+        if let Message::Text(text) = msg {
+            return serde_json::from_str(&text).expect("json frame");
+        }
+    }
+}
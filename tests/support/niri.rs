@@ -0,0 +1,106 @@
+//! Minimal niri IPC socket for the niri backend: answers requests from
+//! `NiriState` and lets tests push events to event streams.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde_json::{Value, json};
+
+/// Replies to the `Outputs`, `Workspaces` and `Windows` requests.
+pub struct NiriState {
+    pub outputs: Value,
+    pub workspaces: Value,
+    pub windows: Value,
+}
+
+pub struct FakeNiri {
+    dir: PathBuf,
+    socket: PathBuf,
+    state: Arc<Mutex<NiriState>>,
+    streams: Arc<Mutex<Vec<UnixStream>>>,
+}
+
+impl FakeNiri {
+    pub fn start(state: NiriState) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "riverql-niri-{}-{}",
+            std::process::id(),
+            unique_suffix()
+        ));
+        std::fs::create_dir_all(&dir).expect("create fake niri dir");
+        let socket = dir.join("niri.sock");
+        let listener = UnixListener::bind(&socket).expect("bind fake niri socket");
+        let state = Arc::new(Mutex::new(state));
+        let streams = Arc::new(Mutex::new(Vec::new()));
+        {
+            let state = state.clone();
+            let streams = streams.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else {
+                        return;
+                    };
+                    answer(stream, &state, &streams);
+                }
+            });
+        }
+        Self {
+            dir,
+            socket,
+            state,
+            streams,
+        }
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    pub fn update(&self, change: impl FnOnce(&mut NiriState)) {
+        change(&mut self.state.lock().unwrap());
+    }
+
+    /// Sends `event` to every open event stream.
+    pub fn event(&self, event: Value) {
+        for stream in self.streams.lock().unwrap().iter_mut() {
+            let _ = writeln!(stream, "{event}");
+        }
+    }
+}
+
+impl Drop for FakeNiri {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn answer(mut stream: UnixStream, state: &Mutex<NiriState>, streams: &Mutex<Vec<UnixStream>>) {
+    let mut request = String::new();
+    let reader = stream.try_clone().expect("clone niri connection");
+    if BufReader::new(reader).read_line(&mut request).is_err() {
+        return;
+    }
+    let request: Value = serde_json::from_str(&request).unwrap_or_default();
+    let reply = {
+        let state = state.lock().unwrap();
+        match request.as_str() {
+            Some("Outputs") => json!({ "Ok": { "Outputs": state.outputs } }),
+            Some("Workspaces") => json!({ "Ok": { "Workspaces": state.workspaces } }),
+            Some("Windows") => json!({ "Ok": { "Windows": state.windows } }),
+            Some("EventStream") => json!({ "Ok": "Handled" }),
+            _ => json!({ "Err": "unsupported request" }),
+        }
+    };
+    let _ = writeln!(stream, "{reply}");
+    if request == "EventStream" {
+        streams.lock().unwrap().push(stream);
+    }
+}
+
+fn unique_suffix() -> u64 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
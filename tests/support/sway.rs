@@ -0,0 +1,153 @@
+//! Minimal sway IPC server for the sway backend: answers the state queries
+//! from `SwayState` and lets tests push events to subscribers.
+
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde_json::{Value, json};
+
+const MAGIC: &[u8] = b"i3-ipc";
+const EVENT_FLAG: u32 = 1 << 31;
+pub const WORKSPACE_EVENT: u32 = EVENT_FLAG;
+pub const WINDOW_EVENT: u32 = EVENT_FLAG | 3;
+pub const MODE_EVENT: u32 = EVENT_FLAG | 2;
+
+/// Replies to GET_OUTPUTS, GET_WORKSPACES and GET_TREE.
+pub struct SwayState {
+    pub outputs: Value,
+    pub workspaces: Value,
+    pub tree: Value,
+}
+
+pub struct FakeSway {
+    dir: PathBuf,
+    socket: PathBuf,
+    state: Arc<Mutex<SwayState>>,
+    subscribers: Arc<Mutex<Vec<UnixStream>>>,
+    commands: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeSway {
+    pub fn start(state: SwayState) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "riverql-sway-{}-{}",
+            std::process::id(),
+            unique_suffix()
+        ));
+        std::fs::create_dir_all(&dir).expect("create fake sway dir");
+        let socket = dir.join("sway-ipc.sock");
+        let listener = UnixListener::bind(&socket).expect("bind fake sway socket");
+        let state = Arc::new(Mutex::new(state));
+        let subscribers = Arc::new(Mutex::new(Vec::new()));
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        {
+            let state = state.clone();
+            let subscribers = subscribers.clone();
+            let commands = commands.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else {
+                        return;
+                    };
+                    let state = state.clone();
+                    let subscribers = subscribers.clone();
+                    let commands = commands.clone();
+                    std::thread::spawn(move || serve(stream, &state, &subscribers, &commands));
+                }
+            });
+        }
+        Self {
+            dir,
+            socket,
+            state,
+            subscribers,
+            commands,
+        }
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    pub fn update(&self, change: impl FnOnce(&mut SwayState)) {
+        change(&mut self.state.lock().unwrap());
+    }
+
+    /// Payloads of the RUN_COMMAND messages received so far.
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.lock().unwrap().clone()
+    }
+
+    /// Sends an event of `kind` to every subscribed connection.
+    pub fn event(&self, kind: u32, payload: Value) {
+        for stream in self.subscribers.lock().unwrap().iter_mut() {
+            let _ = write_message(stream, kind, &payload);
+        }
+    }
+}
+
+impl Drop for FakeSway {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn serve(
+    mut stream: UnixStream,
+    state: &Mutex<SwayState>,
+    subscribers: &Mutex<Vec<UnixStream>>,
+    commands: &Mutex<Vec<String>>,
+) {
+    let mut header = [0; 14];
+    while stream.read_exact(&mut header).is_ok() {
+        let len = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+        let kind = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+        let mut payload = vec![0; len as usize];
+        if stream.read_exact(&mut payload).is_err() {
+            return;
+        }
+        let reply = {
+            let state = state.lock().unwrap();
+            match kind {
+                0 => {
+                    let command = String::from_utf8_lossy(&payload).into_owned();
+                    commands.lock().unwrap().push(command);
+                    json!([{ "success": true }])
+                }
+                1 => state.workspaces.clone(),
+                2 => json!({ "success": true }),
+                3 => state.outputs.clone(),
+                4 => state.tree.clone(),
+                12 => json!({ "name": "default" }),
+                _ => json!({ "success": false }),
+            }
+        };
+        // Reply before registering, so the reply comes ahead of any event.
+        if write_message(&mut stream, kind, &reply).is_err() {
+            return;
+        }
+        if kind == 2 {
+            subscribers
+                .lock()
+                .unwrap()
+                .push(stream.try_clone().expect("clone subscriber"));
+        }
+    }
+}
+
+fn write_message(stream: &mut UnixStream, kind: u32, payload: &Value) -> std::io::Result<()> {
+    let payload = payload.to_string();
+    let mut message = MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    stream.write_all(&message)
+}
+
+fn unique_suffix() -> u64 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}